@@ -0,0 +1,44 @@
+use std::path::Path;
+use std::process::Command;
+
+// Included by the build.rs of rust-bot and rust-bot-plugin.
+// Embeds the compiler and sled versions as RUSTC_VERSION and SLED_VERSION,
+// the host and every plugin compare them before any `sled::Db` crosses the library boundary.
+// Neither may be unknown, otherwise the comparison would pass for any two builds.
+pub fn embed() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(&rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| panic!("failed to run `{} --version`", rustc));
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let lock_path = Path::new(&manifest_dir).join("Cargo.lock");
+    let lock = std::fs::read_to_string(&lock_path)
+        .unwrap_or_else(|err| panic!("failed to read {}, it records the sled version: {}", lock_path.display(), err));
+    let sled_version = locked_version(&lock, "sled")
+        .unwrap_or_else(|| panic!("{} has no sled package", lock_path.display()));
+
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=SLED_VERSION={}", sled_version);
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
+
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name {
+            return lines
+                .next()
+                .and_then(|line| line.trim().strip_prefix("version = \""))
+                .map(|version| version.trim_end_matches('"').to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,128 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+use serde::{Deserialize, Serialize};
+
+// Checked by the host against the `plugin_abi_version` and the declaration of every plugin.
+// Bump it whenever `PluginDeclaration`, `PluginVTable` or the `PluginManifest` JSON change.
+pub const PLUGIN_ABI_VERSION: u32 = 9;
+
+pub const PLUGIN_OK: i32 = 0;
+pub const PLUGIN_ERROR: i32 = 1;
+pub const PLUGIN_PANICKED: i32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    // the last argument is the nul-terminated JSON of the host's per-agent settings, keyed by agent name
+    pub init: unsafe extern "C" fn(*const sled::Db, *const sled::Db, *const c_char) -> i32,
+    // alternative to `init` for worker processes, takes the host's nul-terminated socket path
+    pub init_remote: unsafe extern "C" fn(*const c_char, *const c_char) -> i32,
+    pub start: extern "C" fn() -> i32,
+    pub stop: extern "C" fn() -> i32,
+    // waits at most the given milliseconds for in-flight tasks before they are aborted
+    pub shutdown: extern "C" fn(u64) -> i32,
+    // start, stop or restart a single agent, takes the nul-terminated agent name of the manifest
+    pub start_agent: unsafe extern "C" fn(*const c_char) -> i32,
+    pub stop_agent: unsafe extern "C" fn(*const c_char) -> i32,
+    pub restart_agent: unsafe extern "C" fn(*const c_char) -> i32,
+    // JSON array of `TaskStatus`, valid until the next call, or null
+    pub task_states: extern "C" fn() -> *const c_char,
+    // runs a scheduled task of an agent right away, takes the agent name and the task name of `task_states`
+    pub run_task: unsafe extern "C" fn(*const c_char, *const c_char) -> i32,
+    // upgrades the stored entries to the current version of their schema, only counts them if the argument is true
+    pub migrate: extern "C" fn(bool) -> i32,
+    // message of the last call that returned `PLUGIN_ERROR`, or null
+    pub last_error: extern "C" fn() -> *const c_char,
+}
+
+impl PluginVTable {
+    // `check_status` with the plugin's error message attached
+    pub fn check(&self, call: &str, status: i32) -> anyhow::Result<()> {
+        check_status(call, status).map_err(|err| {
+            let last_error = (self.last_error)();
+            if status == PLUGIN_ERROR && !last_error.is_null() {
+                anyhow::anyhow!("{}: {}", err, unsafe { read_c_str(last_error) })
+            } else {
+                err
+            }
+        })
+    }
+
+    pub fn start_agent(&self, agent: &str) -> anyhow::Result<()> {
+        let agent = CString::new(agent)?;
+        self.check("start_agent", unsafe { (self.start_agent)(agent.as_ptr()) })
+    }
+
+    pub fn stop_agent(&self, agent: &str) -> anyhow::Result<()> {
+        let agent = CString::new(agent)?;
+        self.check("stop_agent", unsafe { (self.stop_agent)(agent.as_ptr()) })
+    }
+
+    pub fn restart_agent(&self, agent: &str) -> anyhow::Result<()> {
+        let agent = CString::new(agent)?;
+        self.check("restart_agent", unsafe { (self.restart_agent)(agent.as_ptr()) })
+    }
+
+    pub fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>> {
+        let json = (self.task_states)();
+        if json.is_null() {
+            return Err(anyhow::anyhow!("plugin did not report its task states"));
+        }
+        serde_json::from_str(&unsafe { read_c_str(json) })
+            .map_err(|err| anyhow::anyhow!("invalid task states: {}", err))
+    }
+
+    pub fn run_task(&self, agent: &str, task: &str) -> anyhow::Result<()> {
+        let agent = CString::new(agent)?;
+        let task = CString::new(task)?;
+        self.check("run_task", unsafe { (self.run_task)(agent.as_ptr(), task.as_ptr()) })
+    }
+
+    pub fn migrate(&self, dry_run: bool) -> anyhow::Result<()> {
+        self.check("migrate", (self.migrate)(dry_run))
+    }
+}
+
+// Returned by the exported `plugin_declaration` symbol.
+// `rustc_version` and `sled_version` are nul-terminated and live as long as the library is loaded.
+// `manifest` returns the JSON encoded `PluginManifest`, or null if it could not be built.
+#[repr(C)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    pub rustc_version: *const c_char,
+    pub sled_version: *const c_char,
+    pub manifest: extern "C" fn() -> *const c_char,
+    pub vtable: PluginVTable,
+}
+
+unsafe impl Sync for PluginDeclaration {}
+
+// State of a task as reported by `task_states` and the host's admin socket, the task is named by its `Debug` output.
+// `state` is one of scheduled, pending, hung (pending for longer than `hung_after_in_secs`), resolved, failed,
+// timed_out, panicked or cancelled. `timestamp` is the unix time the task finished at, or started at while it is pending or hung.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskStatus {
+    pub agent: String,
+    pub task: String,
+    pub state: String,
+    pub timestamp: Option<i64>,
+}
+
+pub fn check_status(call: &str, status: i32) -> anyhow::Result<()> {
+    match status {
+        PLUGIN_OK => Ok(()),
+        PLUGIN_ERROR => Err(anyhow::anyhow!("plugin `{}` returned an error", call)),
+        PLUGIN_PANICKED => Err(anyhow::anyhow!("plugin `{}` panicked", call)),
+        other => Err(anyhow::anyhow!("plugin `{}` returned unknown status {}", call, other)),
+    }
+}
+
+/// # Safety
+/// `ptr` is null or points to a nul-terminated string.
+pub unsafe fn read_c_str(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
@@ -0,0 +1,64 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use serde::{Deserialize, Serialize};
+
+// Wire format between the host and its worker processes, the host serves the store (rust-bot/src/ipc.rs),
+// workers reach it through `RemoteStore` (rust-bot-plugin/src/plugin/store/remote_store/mod.rs).
+// Every frame is a big endian u32 length followed by the bincode encoded message.
+// The first frame on a connection is a `Channel` that tells the host what the connection is used for.
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DbKind {
+    Persistent,
+    Temporary,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Channel {
+    Control,
+    Store,
+    Watch { db: DbKind, prefix: Vec<u8> },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StoreRequest {
+    Get { db: DbKind, key: Vec<u8> },
+    Insert { db: DbKind, key: Vec<u8>, value: Vec<u8> },
+    Remove { db: DbKind, key: Vec<u8> },
+    ContainsKey { db: DbKind, key: Vec<u8> },
+    ScanPrefix { db: DbKind, prefix: Vec<u8> },
+    // see `store::apply_if`
+    ApplyIf { db: DbKind, expected: Vec<(Vec<u8>, Option<Vec<u8>>)>, batch: Vec<(Vec<u8>, Option<Vec<u8>>)> },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StoreResponse {
+    Value(Option<Vec<u8>>),
+    Bool(bool),
+    Entries(Vec<(Vec<u8>, Vec<u8>)>),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum WatchEvent {
+    Insert { key: Vec<u8>, value: Vec<u8> },
+    Remove { key: Vec<u8> },
+}
+
+pub fn write_frame<T: Serialize>(stream: &mut UnixStream, message: &T) -> std::io::Result<()> {
+    let payload = bincode::serialize(message)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(&payload)?;
+    stream.flush()
+}
+
+pub fn read_frame<T: for<'a> Deserialize<'a>>(stream: &mut UnixStream) -> std::io::Result<T> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let mut payload = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut payload)?;
+    bincode::deserialize(&payload)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,9 @@
+// Definitions both sides of the plugin interface are built from: the C ABI of native plugins,
+// their manifest, the wire format of worker processes, the store layout and operations they share
+// and how their tasks are scheduled, so native and WASM agents behave the same.
+pub mod abi;
+pub mod ipc;
+pub mod manifest;
+pub mod retry;
+pub mod settings;
+pub mod store;
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Describes a plugin build to the host, serialized as JSON through `PluginDeclaration::manifest`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    pub features: Vec<String>,
+    pub agents: Vec<AgentManifest>,
+    // sled key prefix of the AGENT_STORE revision index, `<store_index_prefix><key>` exists once `key` was written
+    pub store_index_prefix: String,
+}
+
+// `reads` and `writes` are key prefixes in AGENT_STORE (without its global prefix).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentManifest {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    pub depends_on: Vec<Dependency>,
+}
+
+// Something that has to exist before the agent can do any work.
+// The host holds back `start` until all dependencies of a plugin are met.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dependency {
+    // a key (or key prefix) in AGENT_STORE
+    Key(String),
+    // an agent, by its manifest name, that has to be running
+    Agent(String),
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dependency::Key(key) => write!(f, "key '{}'", key),
+            Dependency::Agent(name) => write!(f, "agent {}", name),
+        }
+    }
+}
+
+impl AgentManifest {
+    pub fn new(name: &str, reads: &[&str], writes: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            reads: reads.iter().map(|x| x.to_string()).collect(),
+            writes: writes.iter().map(|x| x.to_string()).collect(),
+            depends_on: Vec::new(),
+        }
+    }
+
+    pub fn depends_on(mut self, dependency: Dependency) -> Self {
+        self.depends_on.push(dependency);
+        self
+    }
+}
+
+impl PluginManifest {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let manifest: PluginManifest = serde_json::from_str(json)
+            .map_err(|err| anyhow::anyhow!("invalid plugin manifest: {}", err))?;
+        semver::Version::parse(&manifest.version).map_err(|err| {
+            anyhow::anyhow!(
+                "invalid plugin manifest: version `{}` is not semver: {}",
+                manifest.version,
+                err
+            )
+        })?;
+        Ok(manifest)
+    }
+
+    // every prefix written by any agent of this plugin
+    pub fn writes(&self) -> impl Iterator<Item = &String> {
+        self.agents.iter().flat_map(|agent| agent.writes.iter())
+    }
+
+    pub fn provides_agent(&self, name: &str) -> bool {
+        self.agents.iter().any(|agent| agent.name == name)
+    }
+
+    pub fn writes_key(&self, key: &str) -> bool {
+        self.writes().any(|prefix| key.starts_with(prefix.as_str()) || prefix.starts_with(key))
+    }
+
+    // Dependencies of all agents that the plugin does not satisfy on its own.
+    pub fn external_dependencies(&self) -> Vec<&Dependency> {
+        let mut dependencies: Vec<&Dependency> = Vec::new();
+        for dependency in self.agents.iter().flat_map(|agent| agent.depends_on.iter()) {
+            let internal = match dependency {
+                Dependency::Key(key) => self.writes_key(key),
+                Dependency::Agent(name) => self.provides_agent(name),
+            };
+            if !internal && !dependencies.contains(&dependency) {
+                dependencies.push(dependency);
+            }
+        }
+        dependencies
+    }
+}
+
+impl fmt::Display for PluginManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v{} (features: [{}])",
+            self.name,
+            self.version,
+            self.features.join(", ")
+        )?;
+        for agent in &self.agents {
+            write!(
+                f,
+                "\n  {}: reads [{}], writes [{}]",
+                agent.name,
+                agent.reads.join(", "),
+                agent.writes.join(", ")
+            )?;
+            if !agent.depends_on.is_empty() {
+                let depends_on: Vec<String> = agent.depends_on.iter().map(|x| x.to_string()).collect();
+                write!(f, ", depends on [{}]", depends_on.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use rand::Rng;
+use serde::Deserialize;
+
+// How a failed task is retried, see `Agent::get_retry_policy`. Rate limited runs wait for the endpoint instead.
+// In the agent settings, e.g. `retry_policy = { backoff = { fixed = { delay_in_secs = 30 } }, max_attempts = 5, give_up = "stop" }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    // failed runs in a row before the task gives up (the last one is not retried), retried until it resolves if not set
+    pub max_attempts: Option<u32>,
+    pub give_up: GiveUp,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "BackoffSettings")]
+pub enum Backoff {
+    Fixed {
+        delay_in_secs: i64,
+    },
+    // grows by `increment_in_secs` per retry
+    Linear {
+        initial_delay_in_secs: i64,
+        increment_in_secs: i64,
+        max_delay_in_secs: i64,
+    },
+    // grows by `factor` per retry, plus up to `jitter` of the delay at random that does not carry over to the next
+    Exponential {
+        initial_delay_in_secs: i64,
+        factor: f64,
+        max_delay_in_secs: i64,
+        jitter: f64,
+    },
+    // random between `base_delay_in_secs` and three times the last delay, so retries of many tasks spread out
+    DecorrelatedJitter {
+        base_delay_in_secs: i64,
+        max_delay_in_secs: i64,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum BackoffSettings {
+    Fixed {
+        delay_in_secs: i64,
+    },
+    Linear {
+        initial_delay_in_secs: i64,
+        increment_in_secs: i64,
+        max_delay_in_secs: i64,
+    },
+    Exponential {
+        initial_delay_in_secs: i64,
+        factor: f64,
+        max_delay_in_secs: i64,
+        jitter: f64,
+    },
+    DecorrelatedJitter {
+        base_delay_in_secs: i64,
+        max_delay_in_secs: i64,
+    },
+}
+
+impl TryFrom<BackoffSettings> for Backoff {
+    type Error = String;
+
+    fn try_from(settings: BackoffSettings) -> Result<Self, Self::Error> {
+        let positive = |name: &str, value: i64| {
+            if value <= 0 {
+                return Err(format!("{} has to be positive, got {}", name, value));
+            }
+            Ok(())
+        };
+        let at_least = |name: &str, value: i64, min_name: &str, min: i64| {
+            if value < min {
+                return Err(format!("{} must not be less than {} ({}), got {}", name, min_name, min, value));
+            }
+            Ok(())
+        };
+        match settings {
+            BackoffSettings::Fixed { delay_in_secs } => {
+                positive("delay_in_secs", delay_in_secs)?;
+                Ok(Backoff::Fixed { delay_in_secs })
+            }
+            BackoffSettings::Linear { initial_delay_in_secs, increment_in_secs, max_delay_in_secs } => {
+                positive("initial_delay_in_secs", initial_delay_in_secs)?;
+                if increment_in_secs < 0 {
+                    return Err(format!("increment_in_secs must not be negative, got {}", increment_in_secs));
+                }
+                at_least("max_delay_in_secs", max_delay_in_secs, "initial_delay_in_secs", initial_delay_in_secs)?;
+                Ok(Backoff::Linear { initial_delay_in_secs, increment_in_secs, max_delay_in_secs })
+            }
+            BackoffSettings::Exponential { initial_delay_in_secs, factor, max_delay_in_secs, jitter } => {
+                positive("initial_delay_in_secs", initial_delay_in_secs)?;
+                if !factor.is_finite() || factor < 1.0 {
+                    return Err(format!("factor must be at least 1, got {}", factor));
+                }
+                if !jitter.is_finite() || jitter < 0.0 {
+                    return Err(format!("jitter must not be negative, got {}", jitter));
+                }
+                at_least("max_delay_in_secs", max_delay_in_secs, "initial_delay_in_secs", initial_delay_in_secs)?;
+                Ok(Backoff::Exponential { initial_delay_in_secs, factor, max_delay_in_secs, jitter })
+            }
+            BackoffSettings::DecorrelatedJitter { base_delay_in_secs, max_delay_in_secs } => {
+                positive("base_delay_in_secs", base_delay_in_secs)?;
+                at_least("max_delay_in_secs", max_delay_in_secs, "base_delay_in_secs", base_delay_in_secs)?;
+                Ok(Backoff::DecorrelatedJitter { base_delay_in_secs, max_delay_in_secs })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GiveUp {
+    // the task waits for its next scheduled run as if it had resolved
+    NextRun,
+    // the task is not run again until the agent is restarted
+    Stop,
+}
+
+// Retries of a task since it last resolved, kept by `AgentManager`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryState {
+    pub attempts: u32,
+    // delay before the last retry, 0 before the first
+    pub delay_in_secs: i64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::Exponential {
+                initial_delay_in_secs: 60,
+                factor: 2.0,
+                max_delay_in_secs: 60 * 5,
+                jitter: 0.5,
+            },
+            max_attempts: None,
+            give_up: GiveUp::NextRun,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn fixed(delay_in_secs: i64) -> Self {
+        Self {
+            backoff: Backoff::Fixed { delay_in_secs },
+            ..RetryPolicy::default()
+        }
+    }
+
+    // Counts another failed run, the delay before its retry or none once the task gives up.
+    pub fn next_retry(&self, retry: &mut RetryState) -> Option<i64> {
+        retry.attempts += 1;
+        if self.max_attempts.map(|max| retry.attempts >= max).unwrap_or(false) {
+            return None;
+        }
+        retry.delay_in_secs = self.backoff.next_delay(retry.attempts - 1, retry.delay_in_secs).max(0);
+        Some(retry.delay_in_secs)
+    }
+}
+
+impl Backoff {
+    // `retries` before this one, 0 for the first
+    fn next_delay(&self, retries: u32, last_delay_in_secs: i64) -> i64 {
+        let first = last_delay_in_secs <= 0;
+        match *self {
+            Backoff::Fixed { delay_in_secs } => delay_in_secs,
+            Backoff::Linear { initial_delay_in_secs, increment_in_secs, max_delay_in_secs } => {
+                let delay = if first { initial_delay_in_secs } else { last_delay_in_secs + increment_in_secs };
+                delay.min(max_delay_in_secs)
+            }
+            Backoff::Exponential { initial_delay_in_secs, factor, max_delay_in_secs, jitter } => {
+                // from the number of retries, the last delay has its jitter in it
+                let delay = (initial_delay_in_secs as f64 * factor.powf(retries as f64)).min(max_delay_in_secs as f64) as i64;
+                let jitter = rand::thread_rng().gen_range(0..=(delay as f64 * jitter).max(0.0) as i64);
+                (delay + jitter).min(max_delay_in_secs)
+            }
+            Backoff::DecorrelatedJitter { base_delay_in_secs, max_delay_in_secs } => {
+                let last_delay = last_delay_in_secs.max(base_delay_in_secs);
+                rand::thread_rng()
+                    .gen_range(base_delay_in_secs..=(last_delay * 3).max(base_delay_in_secs))
+                    .min(max_delay_in_secs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn backoff(json: &serde_json::Value) -> Result<Backoff, String> {
+        Backoff::deserialize(json).map_err(|err| err.to_string())
+    }
+
+    // the delays of `n` retries in a row
+    fn delays(backoff: &Backoff, n: u32) -> Vec<i64> {
+        let mut last = 0;
+        (0..n)
+            .map(|retries| {
+                last = backoff.next_delay(retries, last);
+                last
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixed_delay() {
+        let backoff = backoff(&json!({ "fixed": { "delay_in_secs": 30 } })).unwrap();
+        assert_eq!(delays(&backoff, 3), vec![30, 30, 30]);
+    }
+
+    #[test]
+    fn linear_delay_is_capped() {
+        let settings = json!({ "linear": { "initial_delay_in_secs": 10, "increment_in_secs": 20, "max_delay_in_secs": 45 } });
+        let backoff = backoff(&settings).unwrap();
+        assert_eq!(delays(&backoff, 4), vec![10, 30, 45, 45]);
+    }
+
+    #[test]
+    fn exponential_delay_is_capped() {
+        let settings = json!({ "exponential": { "initial_delay_in_secs": 10, "factor": 2.0, "max_delay_in_secs": 50, "jitter": 0.0 } });
+        let backoff = backoff(&settings).unwrap();
+        assert_eq!(delays(&backoff, 4), vec![10, 20, 40, 50]);
+    }
+
+    #[test]
+    fn exponential_jitter_only_adds() {
+        let backoff = Backoff::Exponential { initial_delay_in_secs: 10, factor: 2.0, max_delay_in_secs: 1000, jitter: 0.5 };
+        for _ in 0..100 {
+            // the jitter of one retry does not grow the next
+            for (retries, (delay, base)) in delays(&backoff, 6).into_iter().zip([10, 20, 40, 80, 160, 320]).enumerate() {
+                assert!((base..=base * 3 / 2).contains(&delay), "retry {}: {}", retries, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn decorrelated_jitter_stays_within_bounds() {
+        let backoff = backoff(&json!({ "decorrelated_jitter": { "base_delay_in_secs": 5, "max_delay_in_secs": 100 } })).unwrap();
+        for _ in 0..100 {
+            assert!((5..=15).contains(&backoff.next_delay(0, 0)));
+            assert!((5..=60).contains(&backoff.next_delay(1, 20)));
+            assert!((5..=100).contains(&backoff.next_delay(2, 90)));
+        }
+    }
+
+    #[test]
+    fn invalid_backoff_is_rejected() {
+        for invalid in [
+            json!({ "fixed": { "delay_in_secs": 0 } }),
+            json!({ "linear": { "initial_delay_in_secs": 10, "increment_in_secs": -1, "max_delay_in_secs": 60 } }),
+            json!({ "linear": { "initial_delay_in_secs": 10, "increment_in_secs": 1, "max_delay_in_secs": 5 } }),
+            json!({ "exponential": { "initial_delay_in_secs": 10, "factor": 0.5, "max_delay_in_secs": 60, "jitter": 0.0 } }),
+            json!({ "exponential": { "initial_delay_in_secs": 10, "factor": 2.0, "max_delay_in_secs": 60, "jitter": -0.1 } }),
+            json!({ "exponential": { "initial_delay_in_secs": -10, "factor": 2.0, "max_delay_in_secs": 60, "jitter": 0.0 } }),
+            json!({ "decorrelated_jitter": { "base_delay_in_secs": 0, "max_delay_in_secs": 60 } }),
+        ] {
+            assert!(backoff(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let retry_policy = RetryPolicy { max_attempts: Some(3), ..RetryPolicy::fixed(1) };
+        let mut retry = RetryState::default();
+        let retries: Vec<Option<i64>> = (0..3).map(|_| retry_policy.next_retry(&mut retry)).collect();
+        assert_eq!(retries, vec![Some(1), Some(1), None]);
+        assert_eq!(retry.attempts, 3);
+    }
+}
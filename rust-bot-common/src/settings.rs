@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::retry::RetryPolicy;
+
+// Settings of the `AgentManager` that runs an agent, given in the agent's section next to the agent's own settings.
+// The host's WASM scheduler applies the timeouts and retry policies as well.
+// Per task settings are keyed by the task name shown by `rust-bot-ctl tasks`, e.g. `PoolTasks { blockchain_name: "osmosis" }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ManagerSettings {
+    // a run that takes longer is aborted, `timed_out` and retried like a failed one, no limit if not set
+    pub task_timeout_in_secs: Option<u64>,
+    pub task_timeouts_in_secs: HashMap<String, u64>,
+    // pending tasks are reported as `hung` after this long
+    pub hung_after_in_secs: i64,
+    // replaces the agent's retry policy, see `RetryPolicy`
+    pub retry_policy: Option<RetryPolicy>,
+    pub retry_policies: HashMap<String, RetryPolicy>,
+}
+
+// The section of an agent, the manager takes the keys of `ManagerSettings` and the agent gets the rest.
+#[derive(Deserialize)]
+struct AgentSection {
+    #[serde(flatten)]
+    manager_settings: ManagerSettings,
+    #[serde(flatten)]
+    agent_settings: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Default for ManagerSettings {
+    fn default() -> Self {
+        Self {
+            task_timeout_in_secs: Some(60 * 30),
+            task_timeouts_in_secs: HashMap::new(),
+            hung_after_in_secs: 60 * 10,
+            retry_policy: None,
+            retry_policies: HashMap::new(),
+        }
+    }
+}
+
+impl ManagerSettings {
+    // Splits the section of an agent into the agent's own settings and those of its manager.
+    pub fn split(section: &serde_json::Value) -> anyhow::Result<(serde_json::Value, ManagerSettings)> {
+        let AgentSection {
+            manager_settings,
+            agent_settings,
+        } = AgentSection::deserialize(section)?;
+        if manager_settings.task_timeout_in_secs == Some(0) || manager_settings.task_timeouts_in_secs.values().any(|x| *x == 0) {
+            return Err(anyhow::anyhow!("a task timeout must be at least 1s"));
+        }
+        Ok((serde_json::Value::Object(agent_settings), manager_settings))
+    }
+
+    pub fn task_timeout(&self, task: &str) -> Option<Duration> {
+        self.task_timeouts_in_secs
+            .get(task)
+            .copied()
+            .or(self.task_timeout_in_secs)
+            .map(Duration::from_secs)
+    }
+
+    pub fn retry_policy(&self, task: &str) -> Option<RetryPolicy> {
+        self.retry_policies.get(task).or(self.retry_policy.as_ref()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn split_takes_the_manager_settings_out_of_the_section() {
+        let section = json!({
+            "blockchains": ["osmosis"],
+            "task_timeout_in_secs": 5,
+            "hung_after_in_secs": 30,
+            "retry_policies": { "FetchTask": { "backoff": { "fixed": { "delay_in_secs": 10 } }, "max_attempts": 3 } },
+        });
+        let (agent_settings, manager_settings) = ManagerSettings::split(&section).unwrap();
+        assert_eq!(agent_settings, json!({"blockchains": ["osmosis"]}));
+        assert_eq!(manager_settings.task_timeout_in_secs, Some(5));
+        assert_eq!(manager_settings.hung_after_in_secs, 30);
+        assert_eq!(manager_settings.retry_policy("FetchTask").unwrap().max_attempts, Some(3));
+        assert!(manager_settings.retry_policy("OtherTask").is_none());
+    }
+
+    #[test]
+    fn split_rejects_invalid_manager_settings() {
+        assert!(ManagerSettings::split(&json!({"task_timeout_in_secs": 0})).is_err());
+        assert!(ManagerSettings::split(&json!({"hung_after_in_secs": "soon"})).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+
+// Layout of the revisioned entry stores, `FallbackEntryStore` in rust-bot-plugin and `EntryStore` of the host's WASM plugins.
+// Below the global prefix of the store, `rev_index_<key>` holds the current revision (u64, big endian)
+// and `key_<key>_rev_<n>` the value of revision n.
+pub const GLOBAL_PREFIX_TASK_STORE: &str = "task_store_";
+pub const REV_INDEX_PREFIX: &str = "rev_index_";
+pub const KEY_PREFIX: &str = "key_";
+// starts a value in an envelope with the name and version of its schema, see `migration` in rust-bot-plugin
+pub const ENVELOPE_MAGIC: [u8; 4] = *b"ENTV";
+
+pub fn rev_index_key(key: &str) -> String {
+    format!("{}{}", REV_INDEX_PREFIX, key)
+}
+
+pub fn revision_key(key: &str, index: u64) -> String {
+    format!("{}{}_rev_{}", KEY_PREFIX, key, index)
+}
+
+// the key of a `revision_key`, none if it is not one
+pub fn key_of_revision(revision_key: &str) -> Option<&str> {
+    let (key, index) = revision_key.strip_prefix(KEY_PREFIX)?.rsplit_once("_rev_")?;
+    index.parse::<u64>().ok().map(|_| key)
+}
+
+// Writes `batch` (`None` removes the key) in one transaction, if every key in `expected` still has the given value.
+// false if another writer changed one of them since it was read.
+// Used by the plugins on a local sled and by the host, for itself and to serve `StoreRequest::ApplyIf`.
+pub fn apply_if(
+    db: &sled::Db,
+    expected: &[(Vec<u8>, Option<Vec<u8>>)],
+    batch: &[(Vec<u8>, Option<Vec<u8>>)],
+) -> sled::Result<bool> {
+    let result = db.transaction(|tx| {
+        for (key, value) in expected {
+            if tx.get(key)?.as_deref() != value.as_deref() {
+                return Err(ConflictableTransactionError::Abort(()));
+            }
+        }
+        for (key, value) in batch {
+            match value {
+                Some(value) => tx.insert(key.as_slice(), value.as_slice())?,
+                None => tx.remove(key.as_slice())?,
+            };
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(true),
+        Err(TransactionError::Abort(())) => Ok(false),
+        Err(TransactionError::Storage(err)) => Err(err),
+    }
+}
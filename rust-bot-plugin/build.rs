@@ -0,0 +1,6 @@
+#[path = "../rust-bot-common/build/versions.rs"]
+mod versions;
+
+fn main() {
+    versions::embed();
+}
@@ -3,7 +3,7 @@
 # Array of all features
 #features=("ChainRegistry")
 
-features=("ChainRegistry" "Params" "TallyResults" "Pool" "Validators" "GovernanceProposalFetch" "GovernanceProposalView")
+features=("ChainRegistry" "Params" "TallyResults" "Pool" "Validators" "GovernanceProposalFetch" "GovernanceProposalView" "Janitor")
 
 
 # Loop over the features
@@ -12,7 +12,7 @@ do
    echo "Building for feature: $feature"
    # Build with --no-default-features and only the current feature
    if [ "$USE_DOCKER" = "1" ] || [ ! -f Cargo.toml ]; then
-        docker run --rm -it -e RUSTFLAGS="--cfg tokio_unstable" -v "$(pwd)":/usr/src/workspace -w /usr/src/workspace rust cargo build --release --no-default-features --features "$feature EnvLogger"
+        docker run --rm -it -e RUSTFLAGS="--cfg tokio_unstable" -v "$(pwd)/..":/usr/src/workspace -w /usr/src/workspace/rust-bot-plugin rust cargo build --release --no-default-features --features "$feature EnvLogger"
    else
         RUSTFLAGS="--cfg tokio_unstable" cargo build --release --no-default-features --features "$feature EnvLogger"
    fi
@@ -0,0 +1,35 @@
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use rust_bot_common::abi::{PLUGIN_ERROR, PLUGIN_OK, PLUGIN_PANICKED};
+
+// Error reporting of the vtable functions, the vtable itself is declared in `rust_bot_common::abi`.
+
+lazy_static::lazy_static! {
+    static ref LAST_ERROR: Mutex<Option<CString>> = Mutex::new(None);
+}
+
+// Runs `f` and turns a panic into `PLUGIN_PANICKED`, unwinding into the host is undefined behaviour.
+pub fn call_guarded<F: FnOnce() -> anyhow::Result<()>>(f: F) -> i32 {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(Ok(())) => PLUGIN_OK,
+        Ok(Err(err)) => {
+            log::error!("plugin call failed: {}", err);
+            if let Ok(mut last_error) = LAST_ERROR.lock() {
+                *last_error = CString::new(format!("{:#}", err).replace('\0', " ")).ok();
+            }
+            PLUGIN_ERROR
+        }
+        Err(_) => PLUGIN_PANICKED,
+    }
+}
+
+// The pointer stays valid until the next failing call.
+pub extern "C" fn last_error() -> *const c_char {
+    std::panic::catch_unwind(|| match LAST_ERROR.lock() {
+        Ok(last_error) => last_error.as_ref().map(|x| x.as_ptr()).unwrap_or(std::ptr::null()),
+        Err(_) => std::ptr::null(),
+    })
+    .unwrap_or(std::ptr::null())
+}
@@ -1,15 +1,21 @@
+mod abi;
+mod manifest;
 mod plugin;
 
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 use std::sync::Once;
 use std::sync::{Arc, Mutex, RwLock};
 
 use env_logger::{Builder, Env};
-use log::{error, info};
+use log::{error, info, warn};
 
 #[cfg(feature = "ChainRegistry")]
 use crate::plugin::interface::agent::chain_registry::ChainRegistryAgent;
 #[cfg(feature = "Dummy")]
 use crate::plugin::interface::agent::dummy::DummyAgent;
+#[cfg(feature = "Janitor")]
+use crate::plugin::interface::agent::janitor::JanitorAgent;
 #[cfg(feature = "FraudDetection")]
 use crate::plugin::interface::agent::fraud_detection::FraudDetectionAgent;
 #[cfg(feature = "Params")]
@@ -24,35 +30,354 @@ use crate::plugin::interface::agent::governance::tally_results::TallyResultsAgen
 use crate::plugin::interface::agent::governance::validators::ValidatorsAgent;
 #[cfg(feature = "Pool")]
 use crate::plugin::interface::agent::staking::pool::PoolAgent;
-use crate::plugin::interface::{Agent, TaskResult, AgentManager};
+use crate::abi::{call_guarded, last_error};
+use crate::manifest::{enabled_features, plugin_name, AgentManifest, PluginManifest};
+use crate::plugin::interface::agent::{self, agent_store_index_prefix};
+use crate::plugin::interface::{Agent, TaskResult, AgentManager, TaskControl};
+use crate::plugin::interface::circuit_breaker::{self, CircuitBreakerSettings, CIRCUIT_BREAKER_KEY};
+use crate::plugin::interface::concurrency::{self, ConcurrencyLimits, CONCURRENCY_KEY};
+use crate::plugin::interface::settings::ManagerSettings;
+use crate::plugin::store::remote_store::RemoteStore;
+use crate::plugin::store::sled_store::StoreBackend;
 use chrono::Utc;
-use core::sync::atomic::{AtomicBool, Ordering};
+use rust_bot_common::abi::{PluginDeclaration, PluginVTable, TaskStatus, PLUGIN_ABI_VERSION, PLUGIN_ERROR};
+use rust_bot_common::ipc::DbKind;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
-use tokio::task::JoinSet;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
 
 lazy_static::lazy_static! {
-    static ref PERSISTENT_SLED: Mutex<Option<sled::Db>> = Mutex::new(None);
-    static ref TEMPORARY_SLED: Mutex<Option<sled::Db>> = Mutex::new(None);
+    static ref PERSISTENT_SLED: Mutex<Option<StoreBackend>> = Mutex::new(None);
+    static ref TEMPORARY_SLED: Mutex<Option<StoreBackend>> = Mutex::new(None);
     static ref RT: Arc<RwLock<Option<Runtime>>> = Arc::new(RwLock::new(Runtime::new().ok()));
-    static ref CANCELLATION_FLAG: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    static ref AGENTS: Mutex<HashMap<String, RunningAgent>> = Mutex::new(HashMap::new());
+    // returned by `plugin_task_states`, valid until its next call
+    static ref TASK_STATES_JSON: Mutex<Option<CString>> = Mutex::new(None);
+    static ref AGENT_CONFIG: Mutex<HashMap<String, serde_json::Value>> = Mutex::new(HashMap::new());
+    static ref MANIFEST_JSON: Option<CString> = serde_json::to_string(&manifest())
+        .ok()
+        .and_then(|json| CString::new(json).ok());
 }
 
 static INIT: Once = Once::new();
 
+// Agent managers started for an agent, keyed by the agent name of the manifest.
+// A stopped agent only starts again once the in-flight tasks of its manager finished.
+#[derive(Default)]
+struct RunningAgent {
+    cancellation_token: CancellationToken,
+    handle: Option<JoinHandle<()>>,
+    // the manager of the latest run
+    manager: Option<Box<dyn TaskControl>>,
+}
+
+impl RunningAgent {
+    fn is_running(&self) -> bool {
+        !self.cancellation_token.is_cancelled() && !self.is_finished()
+    }
+
+    // stopped, but the in-flight tasks of a manager are still running
+    fn is_stopping(&self) -> bool {
+        self.cancellation_token.is_cancelled() && !self.is_finished()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.handle.as_ref().map(|handle| handle.is_finished()).unwrap_or(true)
+    }
+}
+
+// how long `restart_agent` waits for the in-flight tasks of the stopped manager
+const RESTART_WAIT: Duration = Duration::from_secs(5);
+
 pub enum TaskState {
-    Pending(tokio::task::Id),
+    // waits for its first run at the given unix time
+    Scheduled(i64),
+    // started at the given unix time
+    Pending(tokio::task::Id, i64),
     Cancelled(i64),
     Panicked(i64),
     Failed(i64),
+    // did not finish within its timeout
+    TimedOut(i64),
     Resolved(i64),
 }
 
+impl TaskState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskState::Scheduled(_) => "scheduled",
+            TaskState::Pending(..) => "pending",
+            TaskState::Cancelled(_) => "cancelled",
+            TaskState::Panicked(_) => "panicked",
+            TaskState::Failed(_) => "failed",
+            TaskState::TimedOut(_) => "timed_out",
+            TaskState::Resolved(_) => "resolved",
+        }
+    }
+
+    // when the task finished, or started if it is still pending
+    pub fn timestamp(&self) -> Option<i64> {
+        match self {
+            TaskState::Scheduled(_) => None,
+            TaskState::Pending(_, timestamp)
+            | TaskState::Cancelled(timestamp)
+            | TaskState::Panicked(timestamp)
+            | TaskState::Failed(timestamp)
+            | TaskState::TimedOut(timestamp)
+            | TaskState::Resolved(timestamp) => Some(*timestamp),
+        }
+    }
+}
+
+static PLUGIN_DECLARATION: PluginDeclaration = PluginDeclaration {
+    abi_version: PLUGIN_ABI_VERSION,
+    rustc_version: concat!(env!("RUSTC_VERSION"), "\0").as_ptr() as *const c_char,
+    sled_version: concat!(env!("SLED_VERSION"), "\0").as_ptr() as *const c_char,
+    manifest: plugin_manifest,
+    vtable: PluginVTable {
+        init: plugin_init,
+        init_remote: plugin_init_remote,
+        start: plugin_start,
+        stop: plugin_stop,
+        shutdown: plugin_shutdown,
+        start_agent: plugin_start_agent,
+        stop_agent: plugin_stop_agent,
+        restart_agent: plugin_restart_agent,
+        task_states: plugin_task_states,
+        run_task: plugin_run_task,
+        migrate: plugin_migrate,
+        last_error,
+    },
+};
+
 #[no_mangle]
-pub fn init(persistent_sled: &sled::Db, temporary_sled: &sled::Db) {
+pub extern "C" fn plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn plugin_declaration() -> *const PluginDeclaration {
+    &PLUGIN_DECLARATION
+}
+
+extern "C" fn plugin_manifest() -> *const c_char {
+    std::panic::catch_unwind(|| {
+        MANIFEST_JSON
+            .as_ref()
+            .map(|json| json.as_ptr())
+            .unwrap_or(std::ptr::null())
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+unsafe extern "C" fn plugin_init(
+    persistent_sled: *const sled::Db,
+    temporary_sled: *const sled::Db,
+    agent_config: *const c_char,
+) -> i32 {
+    if persistent_sled.is_null() || temporary_sled.is_null() {
+        return PLUGIN_ERROR;
+    }
+    let agent_config = read_agent_config(agent_config);
+    call_guarded(|| {
+        init(
+            StoreBackend::Local((*persistent_sled).clone()),
+            StoreBackend::Local((*temporary_sled).clone()),
+            &agent_config,
+        )
+    })
+}
+
+// Used by a worker process, all store access is proxied through the host listening on `socket_path`.
+unsafe extern "C" fn plugin_init_remote(socket_path: *const c_char, agent_config: *const c_char) -> i32 {
+    if socket_path.is_null() {
+        return PLUGIN_ERROR;
+    }
+    let socket_path = CStr::from_ptr(socket_path).to_string_lossy().into_owned();
+    let agent_config = read_agent_config(agent_config);
+    call_guarded(|| {
+        init(
+            StoreBackend::Remote(RemoteStore::connect(&socket_path, DbKind::Persistent)?),
+            StoreBackend::Remote(RemoteStore::connect(&socket_path, DbKind::Temporary)?),
+            &agent_config,
+        )
+    })
+}
+
+// no settings if the host passed null
+unsafe fn read_agent_config(agent_config: *const c_char) -> String {
+    if agent_config.is_null() {
+        return "{}".to_string();
+    }
+    CStr::from_ptr(agent_config).to_string_lossy().into_owned()
+}
+
+extern "C" fn plugin_start() -> i32 {
+    call_guarded(start)
+}
+
+extern "C" fn plugin_stop() -> i32 {
+    call_guarded(stop)
+}
+
+extern "C" fn plugin_shutdown(timeout_in_ms: u64) -> i32 {
+    call_guarded(|| shutdown(Duration::from_millis(timeout_in_ms)))
+}
+
+unsafe extern "C" fn plugin_start_agent(name: *const c_char) -> i32 {
+    let name = read_agent_name(name);
+    call_guarded(|| start_agent(&name?))
+}
+
+unsafe extern "C" fn plugin_stop_agent(name: *const c_char) -> i32 {
+    let name = read_agent_name(name);
+    call_guarded(|| stop_agent(&name?))
+}
+
+unsafe extern "C" fn plugin_restart_agent(name: *const c_char) -> i32 {
+    let name = read_agent_name(name);
+    call_guarded(|| restart_agent(&name?))
+}
+
+extern "C" fn plugin_task_states() -> *const c_char {
+    std::panic::catch_unwind(|| {
+        let json = serde_json::to_string(&task_states())
+            .ok()
+            .and_then(|json| CString::new(json).ok());
+        let mut task_states_json = TASK_STATES_JSON.lock().unwrap();
+        *task_states_json = json;
+        task_states_json
+            .as_ref()
+            .map(|json| json.as_ptr())
+            .unwrap_or(std::ptr::null())
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+unsafe extern "C" fn plugin_run_task(agent: *const c_char, task: *const c_char) -> i32 {
+    let agent = read_agent_name(agent);
+    let task = read_agent_name(task);
+    call_guarded(|| run_task(&agent?, &task?))
+}
+
+extern "C" fn plugin_migrate(dry_run: bool) -> i32 {
+    call_guarded(|| migrate(dry_run))
+}
+
+unsafe fn read_agent_name(name: *const c_char) -> anyhow::Result<String> {
+    if name.is_null() {
+        return Err(anyhow::anyhow!("no agent name given"));
+    }
+    Ok(CStr::from_ptr(name).to_string_lossy().into_owned())
+}
+
+fn manifest() -> PluginManifest {
+    let mut agents: Vec<AgentManifest> = Vec::new();
+
+    #[cfg(feature = "ChainRegistry")]
+    agents.push(ChainRegistryAgent::default().manifest());
+
+    #[cfg(feature = "Params")]
+    agents.push(ParamsAgent::default().manifest());
+
+    #[cfg(feature = "TallyResults")]
+    agents.push(TallyResultsAgent::default().manifest());
+
+    #[cfg(feature = "Pool")]
+    agents.push(PoolAgent::default().manifest());
+
+    #[cfg(feature = "FraudDetection")]
+    agents.push(FraudDetectionAgent::default().manifest());
+
+    #[cfg(feature = "Validators")]
+    agents.push(ValidatorsAgent::default().manifest());
+
+    #[cfg(feature = "GovernanceProposalFetch")]
+    agents.push(GovernanceProposalFetchAgent::default().manifest());
+
+    #[cfg(feature = "GovernanceProposalView")]
+    agents.push(GovernanceProposalViewAgent::default().manifest());
+
+    #[cfg(feature = "Dummy")]
+    agents.push(DummyAgent::default().manifest());
+
+    #[cfg(feature = "Janitor")]
+    agents.push(JanitorAgent::default().manifest());
+
+    PluginManifest {
+        name: plugin_name(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        features: enabled_features(),
+        agents,
+        store_index_prefix: agent_store_index_prefix(),
+    }
+}
+
+// Builds the agent from its section of the host's agent settings, missing fields keep their `Default` value.
+fn configured<A>() -> anyhow::Result<A>
+where
+    A: Agent + Default + serde::de::DeserializeOwned,
+{
+    let name = A::default().manifest().name;
+    match AGENT_CONFIG.lock().unwrap().get(&name) {
+        Some(settings) => ManagerSettings::split(settings)
+            .and_then(|(settings, _)| Ok(serde_json::from_value(settings)?))
+            .map_err(|err| anyhow::anyhow!("invalid settings for {}: {}", name, err)),
+        None => Ok(A::default()),
+    }
+}
+
+// The settings of the agent's manager, from the same section as the agent's.
+fn manager_settings(name: &str) -> anyhow::Result<ManagerSettings> {
+    match AGENT_CONFIG.lock().unwrap().get(name) {
+        Some(settings) => ManagerSettings::split(settings)
+            .map(|(_, manager_settings)| manager_settings)
+            .map_err(|err| anyhow::anyhow!("invalid settings for {}: {}", name, err)),
+        None => Ok(ManagerSettings::default()),
+    }
+}
+
+// Rejects settings that do not fit the enabled agents before any of them is started.
+fn check_agent_config() -> anyhow::Result<()> {
+    #[cfg(feature = "ChainRegistry")]
+    configured::<ChainRegistryAgent>()?;
+
+    #[cfg(feature = "Params")]
+    configured::<ParamsAgent>()?;
+
+    #[cfg(feature = "TallyResults")]
+    configured::<TallyResultsAgent>()?;
+
+    #[cfg(feature = "Pool")]
+    configured::<PoolAgent>()?;
+
+    #[cfg(feature = "FraudDetection")]
+    configured::<FraudDetectionAgent>()?;
+
+    #[cfg(feature = "Validators")]
+    configured::<ValidatorsAgent>()?;
+
+    #[cfg(feature = "GovernanceProposalFetch")]
+    configured::<GovernanceProposalFetchAgent>()?;
+
+    #[cfg(feature = "GovernanceProposalView")]
+    configured::<GovernanceProposalViewAgent>()?;
+
+    #[cfg(feature = "Dummy")]
+    configured::<DummyAgent>()?;
+
+    #[cfg(feature = "Janitor")]
+    configured::<JanitorAgent>()?;
+
+    Ok(())
+}
+
+fn init(persistent_sled: StoreBackend, temporary_sled: StoreBackend, agent_config: &str) -> anyhow::Result<()> {
     #[cfg(feature = "EnvLogger")]
     {
         Builder::from_env(Env::default().default_filter_or("info"))
@@ -70,93 +395,253 @@ pub fn init(persistent_sled: &sled::Db, temporary_sled: &sled::Db) {
             .init();
     }
     info!("Init called");
+    let mut agent_config: HashMap<String, serde_json::Value> = serde_json::from_str(agent_config)
+        .map_err(|err| anyhow::anyhow!("invalid agent settings: {}", err))?;
+    // not agents, the settings of all of them
+    let limits = match agent_config.remove(CONCURRENCY_KEY) {
+        Some(limits) => serde_json::from_value(limits)
+            .map_err(|err| anyhow::anyhow!("invalid concurrency limits: {}", err))?,
+        None => ConcurrencyLimits::default(),
+    };
+    concurrency::configure(limits)?;
+    let circuit_breaker_settings = match agent_config.remove(CIRCUIT_BREAKER_KEY) {
+        Some(settings) => serde_json::from_value(settings)
+            .map_err(|err| anyhow::anyhow!("invalid circuit breaker settings: {}", err))?,
+        None => CircuitBreakerSettings::default(),
+    };
+    circuit_breaker::configure(circuit_breaker_settings)?;
+    *AGENT_CONFIG.lock().unwrap() = agent_config;
+    check_agent_config()?;
     INIT.call_once(|| {
         let mut persistent = PERSISTENT_SLED.lock().unwrap();
         let mut temporary = TEMPORARY_SLED.lock().unwrap();
 
         if persistent.is_none() && temporary.is_none() {
-            *persistent = Some(persistent_sled.clone());
-            *temporary = Some(temporary_sled.clone());
+            *persistent = Some(persistent_sled);
+            *temporary = Some(temporary_sled);
         }
         info!("Init completed");
     });
+    Ok(())
 }
 
-#[no_mangle]
-pub fn start() {
-    if INIT.is_completed() {
-        #[cfg(feature = "ChainRegistry")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(ChainRegistryAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+fn start() -> anyhow::Result<()> {
+    for agent in manifest().agents {
+        start_agent(&agent.name)?;
+    }
+    Ok(())
+}
 
-        #[cfg(feature = "Params")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(ParamsAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+fn is_agent<A: Agent + Default>(name: &str) -> bool {
+    A::default().manifest().name == name
+}
 
-        #[cfg(feature = "TallyResults")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(TallyResultsAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+// Starts a new manager for the agent, with the current settings of its section.
+fn start_agent(name: &str) -> anyhow::Result<()> {
+    if !INIT.is_completed() {
+        error!("Plugin not yet initialized");
+        return Err(anyhow::anyhow!("Plugin not yet initialized"));
+    }
 
-        #[cfg(feature = "Pool")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(PoolAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+    #[cfg(feature = "ChainRegistry")]
+    if is_agent::<ChainRegistryAgent>(name) {
+        return run_agent(name, Box::new(configured::<ChainRegistryAgent>()?));
+    }
 
-        #[cfg(feature = "FraudDetection")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(FraudDetectionAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+    #[cfg(feature = "Params")]
+    if is_agent::<ParamsAgent>(name) {
+        return run_agent(name, Box::new(configured::<ParamsAgent>()?));
+    }
 
-        #[cfg(feature = "Validators")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(ValidatorsAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+    #[cfg(feature = "TallyResults")]
+    if is_agent::<TallyResultsAgent>(name) {
+        return run_agent(name, Box::new(configured::<TallyResultsAgent>()?));
+    }
 
-        #[cfg(feature = "GovernanceProposalFetch")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> =
-                Box::new(GovernanceProposalFetchAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+    #[cfg(feature = "Pool")]
+    if is_agent::<PoolAgent>(name) {
+        return run_agent(name, Box::new(configured::<PoolAgent>()?));
+    }
 
-        #[cfg(feature = "GovernanceProposalView")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> =
-                Box::new(GovernanceProposalViewAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
+    #[cfg(feature = "FraudDetection")]
+    if is_agent::<FraudDetectionAgent>(name) {
+        return run_agent(name, Box::new(configured::<FraudDetectionAgent>()?));
+    }
 
-        #[cfg(feature = "Dummy")]
-        {
-            let agent: Box<dyn Agent<TaskType = _>> = Box::new(DummyAgent::default());
-            let _join_handle = AgentManager::new(agent).run();
-        }
-    } else {
-        error!("Plugin not yet initialized");
+    #[cfg(feature = "Validators")]
+    if is_agent::<ValidatorsAgent>(name) {
+        return run_agent(name, Box::new(configured::<ValidatorsAgent>()?));
+    }
+
+    #[cfg(feature = "GovernanceProposalFetch")]
+    if is_agent::<GovernanceProposalFetchAgent>(name) {
+        return run_agent(name, Box::new(configured::<GovernanceProposalFetchAgent>()?));
     }
+
+    #[cfg(feature = "GovernanceProposalView")]
+    if is_agent::<GovernanceProposalViewAgent>(name) {
+        return run_agent(name, Box::new(configured::<GovernanceProposalViewAgent>()?));
+    }
+
+    #[cfg(feature = "Dummy")]
+    if is_agent::<DummyAgent>(name) {
+        return run_agent(name, Box::new(configured::<DummyAgent>()?));
+    }
+
+    #[cfg(feature = "Janitor")]
+    if is_agent::<JanitorAgent>(name) {
+        return run_agent(name, Box::new(configured::<JanitorAgent>()?));
+    }
+
+    Err(anyhow::anyhow!("unknown agent `{}`", name))
+}
+
+fn run_agent<T>(name: &str, agent: Box<dyn Agent<TaskType = T>>) -> anyhow::Result<()>
+where
+    T: Clone + Send + Sync + Hash + Eq + Debug + 'static,
+{
+    let mut agents = AGENTS.lock().unwrap();
+    let running = agents.entry(name.to_string()).or_default();
+    if running.is_running() {
+        return Err(anyhow::anyhow!("agent `{}` is already running", name));
+    }
+    // a second manager would run the same tasks side by side with the in-flight ones
+    if running.is_stopping() {
+        return Err(anyhow::anyhow!("agent `{}` is still stopping, its in-flight tasks did not finish yet", name));
+    }
+    let cancellation_token = CancellationToken::new();
+    let mut manager = AgentManager::new(agent, manager_settings(name)?, cancellation_token.clone());
+    let handle = manager
+        .run()
+        .ok_or_else(|| anyhow::anyhow!("runtime is shut down"))?;
+    running.handle = Some(handle);
+    running.cancellation_token = cancellation_token;
+    running.manager = Some(Box::new(manager));
+    info!("agent started: {}", name);
+    Ok(())
 }
 
+// Only cancels the agent's manager, its in-flight tasks still run to completion.
+fn stop_agent(name: &str) -> anyhow::Result<()> {
+    if !manifest().agents.iter().any(|agent| agent.name == name) {
+        return Err(anyhow::anyhow!("unknown agent `{}`", name));
+    }
+    if let Some(running) = AGENTS.lock().unwrap().get(name) {
+        running.cancellation_token.cancel();
+        info!("agent stopped: {}", name);
+    }
+    Ok(())
+}
 
-#[no_mangle]
-pub fn stop() {
+// Waits up to RESTART_WAIT for the in-flight tasks before the agent starts again.
+fn restart_agent(name: &str) -> anyhow::Result<()> {
+    stop_agent(name)?;
+    let deadline = Instant::now() + RESTART_WAIT;
+    while AGENTS.lock().unwrap().get(name).map(|running| running.is_stopping()).unwrap_or(false)
+        && Instant::now() < deadline
+    {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    start_agent(name)
+}
+
+// Tasks of all agents that were started, stopped agents report the state their tasks were left in.
+fn task_states() -> Vec<TaskStatus> {
+    let agents = AGENTS.lock().unwrap();
+    let mut names: Vec<&String> = agents.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .filter_map(|name| agents[name].manager.as_ref().map(|manager| manager.task_states(name)))
+        .flatten()
+        .collect()
+}
+
+fn run_task(agent: &str, task: &str) -> anyhow::Result<()> {
+    let agents = AGENTS.lock().unwrap();
+    match agents.get(agent) {
+        Some(running) if running.is_running() => match &running.manager {
+            Some(manager) => manager.run_task(task),
+            None => Err(anyhow::anyhow!("agent `{}` is not running", agent)),
+        },
+        _ => Err(anyhow::anyhow!("agent `{}` is not running", agent)),
+    }
+}
+
+// Upgrades the stored entries to the current version of their schema, see `migration`.
+fn migrate(dry_run: bool) -> anyhow::Result<()> {
+    if !INIT.is_completed() {
+        return Err(anyhow::anyhow!("Plugin not yet initialized"));
+    }
+    let migrated = agent::migrate(&agent_schemas()?, dry_run)?;
+    info!(
+        "{} {} revisions, {} are unversioned under keys without schema and left as they are, {} failed",
+        if dry_run { "would upgrade" } else { "upgraded" },
+        migrated.upgraded,
+        migrated.unversioned,
+        migrated.failed
+    );
+    if migrated.failed > 0 {
+        return Err(anyhow::anyhow!("{} revisions could not be upgraded, see the log", migrated.failed));
+    }
+    Ok(())
+}
+
+// What each agent stores under its key prefixes. Those of all agents, not only of this build's:
+// `migrate` upgrades the whole store whichever plugin runs it.
+fn agent_schemas() -> anyhow::Result<Vec<(String, &'static str)>> {
+    use crate::plugin::interface::agent::chain_registry::ChainRegistryAgent;
+    use crate::plugin::interface::agent::fraud_detection::FraudDetectionAgent;
+    use crate::plugin::interface::agent::governance::params::ParamsAgent;
+    use crate::plugin::interface::agent::governance::proposals::fetch::GovernanceProposalFetchAgent;
+    use crate::plugin::interface::agent::governance::proposals::update::GovernanceProposalViewAgent;
+    use crate::plugin::interface::agent::governance::tally_results::TallyResultsAgent;
+    use crate::plugin::interface::agent::governance::validators::ValidatorsAgent;
+    use crate::plugin::interface::agent::janitor::JanitorAgent;
+    use crate::plugin::interface::agent::staking::pool::PoolAgent;
+
+    Ok([
+        configured::<ChainRegistryAgent>()?.schemas(),
+        configured::<ParamsAgent>()?.schemas(),
+        configured::<TallyResultsAgent>()?.schemas(),
+        configured::<PoolAgent>()?.schemas(),
+        configured::<FraudDetectionAgent>()?.schemas(),
+        configured::<ValidatorsAgent>()?.schemas(),
+        configured::<GovernanceProposalFetchAgent>()?.schemas(),
+        configured::<GovernanceProposalViewAgent>()?.schemas(),
+        configured::<JanitorAgent>()?.schemas(),
+    ]
+    .concat())
+}
+
+fn stop() -> anyhow::Result<()> {
     info!("stop called");
-    CANCELLATION_FLAG.store(true, Ordering::SeqCst);
+    for running in AGENTS.lock().unwrap().values() {
+        running.cancellation_token.cancel();
+    }
     info!("goodbye!");
+    Ok(())
 }
 
-#[no_mangle]
-pub fn shutdown() {
+fn shutdown(timeout: Duration) -> anyhow::Result<()> {
     info!("shutdown called");
-    CANCELLATION_FLAG.store(true, Ordering::SeqCst);
+    let handles = AGENTS
+        .lock()
+        .unwrap()
+        .drain()
+        .flat_map(|(_, running)| {
+            running.cancellation_token.cancel();
+            running.handle
+        })
+        .collect::<Vec<_>>();
+
+    let deadline = Instant::now() + timeout;
+    while handles.iter().any(|handle| !handle.is_finished()) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    if handles.iter().any(|handle| !handle.is_finished()) {
+        warn!("in-flight tasks did not finish within {:?}, aborting them", timeout);
+    }
 
     let rt_clone = RT.clone();
     // Obtain a mutable reference to the `Option<Runtime>` inside the `Mutex`
@@ -170,4 +655,5 @@ pub fn shutdown() {
     }
 
     info!("goodbye for good!");
+    Ok(())
 }
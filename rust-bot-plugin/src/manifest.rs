@@ -0,0 +1,42 @@
+pub use rust_bot_common::manifest::{AgentManifest, Dependency, PluginManifest};
+
+pub fn enabled_features() -> Vec<String> {
+    let mut features: Vec<&str> = Vec::new();
+    #[cfg(feature = "EnvLogger")]
+    features.push("EnvLogger");
+    #[cfg(feature = "ChainRegistry")]
+    features.push("ChainRegistry");
+    #[cfg(feature = "Params")]
+    features.push("Params");
+    #[cfg(feature = "TallyResults")]
+    features.push("TallyResults");
+    #[cfg(feature = "Pool")]
+    features.push("Pool");
+    #[cfg(feature = "FraudDetection")]
+    features.push("FraudDetection");
+    #[cfg(feature = "Validators")]
+    features.push("Validators");
+    #[cfg(feature = "GovernanceProposalFetch")]
+    features.push("GovernanceProposalFetch");
+    #[cfg(feature = "GovernanceProposalView")]
+    features.push("GovernanceProposalView");
+    #[cfg(feature = "Dummy")]
+    features.push("Dummy");
+    #[cfg(feature = "Janitor")]
+    features.push("Janitor");
+    features.into_iter().map(|x| x.to_string()).collect()
+}
+
+// The agent features of this build, e.g. `GovernanceProposalFetch` for libGovernanceProposalFetch.so of build.sh,
+// joined by `+` if there are several. Builds without any agent are named after the crate.
+pub fn plugin_name() -> String {
+    let agents: Vec<String> = enabled_features()
+        .into_iter()
+        .filter(|feature| feature != "EnvLogger")
+        .collect();
+    if agents.is_empty() {
+        env!("CARGO_PKG_NAME").to_string()
+    } else {
+        agents.join("+")
+    }
+}
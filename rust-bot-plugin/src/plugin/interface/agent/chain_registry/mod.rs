@@ -5,25 +5,30 @@ use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 
+use crate::manifest::AgentManifest;
+use crate::plugin::interface::schedule::Schedule;
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
 
 use crate::plugin::interface::agent::AGENT_STORE;
 use cosmos_rust_package::api::core::cosmos::channels;
 use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchainType;
 
 use crate::plugin::store::fallback_entry_store::RetrievalMethod;
+use serde::Deserialize;
 
-const STORE_KEY: &str = "chain_registry";
+pub const CHAIN_REGISTRY_KEY: &str = "chain_registry";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ChainRegistryAgent {
     pub git_path: String,
     pub json_path: String,
     pub git_pull: bool,
     pub sync_interval_in_secs: Option<u64>,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<ChainRegistryTasks,i64>,
-    initial_retry_delay: i64,
+    // replaces `update_interval_in_secs`, e.g. `{ daily = "03:00" }` for a nightly sync
+    pub schedule: Option<Schedule>,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -39,8 +44,7 @@ impl Default for ChainRegistryAgent {
             git_pull: false,             // TODO: PROD: true
             sync_interval_in_secs: None, // TODO: PROD: Some(60*60*1)
             update_interval_in_secs: 60 * 30,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
+            schedule: None,
         }
     }
 }
@@ -62,7 +66,8 @@ impl ChainRegistryAgent {
         let data: Result<SupportedBlockchainType, EntryError> = Ok(result);
 
         let task_store = AGENT_STORE.clone();
-        task_store.insert(STORE_KEY, data)?;
+        // unchanged registries are not written, every write triggers the tasks of all agents that use it
+        task_store.insert_if_not_exists(CHAIN_REGISTRY_KEY, data)?;
         info!("done");
         Ok(())
     }
@@ -110,22 +115,23 @@ impl Agent for ChainRegistryAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_schedule(&self, _task_type: &Self::TaskType) -> Schedule {
+        self.schedule.clone().unwrap_or(Schedule::interval(self.update_interval_in_secs))
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new("ChainRegistryAgent", &[], &[CHAIN_REGISTRY_KEY])
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(CHAIN_REGISTRY_KEY.to_string(), SupportedBlockchainType::NAME)]
     }
 }
 
 pub fn try_get_chain_registry() -> Option<SupportedBlockchainType> {
     let task_store = AGENT_STORE.clone();
 
-    match task_store.get::<SupportedBlockchainType>(STORE_KEY, &RetrievalMethod::GetOk) {
+    match task_store.get::<SupportedBlockchainType>(CHAIN_REGISTRY_KEY, &RetrievalMethod::GetOk) {
         Ok(item) => {
             if let Ok(data) = item.data {
                 Some(data)
@@ -4,15 +4,17 @@ use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 
+use crate::manifest::AgentManifest;
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::interface::retry::RetryPolicy;
 
 use crate::plugin::interface::agent::AGENT_STORE;
+use serde::Deserialize;
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct DummyAgent {
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<DummyTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -23,8 +25,6 @@ impl Default for DummyAgent {
     fn default() -> Self {
         Self {
             update_interval_in_secs: 3,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 1,
         }
     }
 }
@@ -87,14 +87,13 @@ impl Agent for DummyAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
-    }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+
+    fn get_retry_policy(&self, _task_type: &Self::TaskType) -> RetryPolicy {
+        RetryPolicy::fixed(1)
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn manifest(&self) -> AgentManifest {
+        // error_iter(None) scans the whole store
+        AgentManifest::new("DummyAgent", &[""], &[])
     }
 }
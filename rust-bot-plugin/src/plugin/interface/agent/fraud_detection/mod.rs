@@ -6,7 +6,10 @@ use std::future::Future;
 use std::hash::{Hash, Hasher};
 use std::pin::Pin;
 
+use crate::manifest::AgentManifest;
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::retry::RetryPolicy;
 
 use cosmos_rust_package::api::custom::types::gov::proposal_ext::{ProposalExt, ProposalStatus};
 
@@ -22,13 +25,12 @@ use sled::Event;
 
 pub const FRAUD_DETECTION_PREFIX: &str = "fraud_detection_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct FraudDetectionAgent {
     pub unix_socket: Option<String>,
     pub csv_file: Option<String>,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<FraudDetectionTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -42,8 +44,6 @@ impl Default for FraudDetectionAgent {
             unix_socket: Some("./tmp/rust_bert_fraud_detection_socket".to_string()),
             csv_file: Some("./tmp/governance_proposal_spam_likelihood.csv".to_string()),
             update_interval_in_secs: 0,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 0,
         }
     }
 }
@@ -202,15 +202,22 @@ impl Agent for FraudDetectionAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+
+    fn get_retry_policy(&self, _task_type: &Self::TaskType) -> RetryPolicy {
+        // the work is local, retry right away
+        RetryPolicy::fixed(0)
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "FraudDetectionAgent",
+            &[&format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX)],
+            &[&format!("{}{}", GOVERNANCE_PREFIX, FRAUD_DETECTION_PREFIX)],
+        )
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(format!("{}{}", GOVERNANCE_PREFIX, FRAUD_DETECTION_PREFIX), GovernanceProposalFraudClassificationType::NAME)]
     }
 }
 
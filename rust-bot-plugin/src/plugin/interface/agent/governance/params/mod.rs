@@ -1,5 +1,9 @@
-use crate::plugin::interface::agent::chain_registry::try_get_chain_registry;
+use crate::manifest::{AgentManifest, Dependency};
+use crate::plugin::interface::agent::chain_registry::{try_get_chain_registry, CHAIN_REGISTRY_KEY};
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::upstream::Upstream;
+use crate::plugin::interface::rate_limit::{limited, GOV_ENDPOINT};
 use crate::plugin::store::fallback_entry_store::{EntryError, RetrievalMethod};
 
 use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchain;
@@ -15,15 +19,15 @@ use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
 use crate::plugin::interface::agent::AGENT_STORE;
 use cosmos_rust_package::api::custom::types::ParamsType;
 use std::pin::Pin;
+use serde::Deserialize;
 
 pub const PARAMS_PREFIX: &str = "params_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ParamsAgent {
     pub params_types: Vec<String>,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<ParamsTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -41,8 +45,6 @@ impl Default for ParamsAgent {
                 "deposit".to_string(),
             ],
             update_interval_in_secs: 60 * 60, // 1h
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
         }
     }
 }
@@ -57,7 +59,7 @@ impl ParamsAgent {
 
         let task_store = AGENT_STORE.clone();
 
-        let result = get_params_v1beta1(blockchain.clone(), params_type.clone()).await;
+        let result = limited(&blockchain.name, GOV_ENDPOINT, get_params_v1beta1(blockchain.clone(), params_type.clone())).await;
 
         let mut output: Result<(), anyhow::Error> = Ok(());
         let data: Result<ParamsType, EntryError> = match result {
@@ -132,15 +134,24 @@ impl Agent for ParamsAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_blockchain_name(&self, task_type: &Self::TaskType) -> Option<String> {
+        Some(task_type.blockchain_name.clone())
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+    fn get_upstream(&self, _task_type: &Self::TaskType) -> Vec<Upstream> {
+        vec![Upstream::Key(CHAIN_REGISTRY_KEY.to_string())]
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "ParamsAgent",
+            &[CHAIN_REGISTRY_KEY],
+            &[&format!("{}{}", GOVERNANCE_PREFIX, PARAMS_PREFIX)],
+        )
+        .depends_on(Dependency::Key(CHAIN_REGISTRY_KEY.to_string()))
+    }
+
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(format!("{}{}", GOVERNANCE_PREFIX, PARAMS_PREFIX), ParamsType::NAME)]
     }
 }
 
@@ -162,24 +173,3 @@ pub fn try_get_params(blockchain: &SupportedBlockchain, params_type: &str) -> Op
         Err(_) => None,
     }
 }
-
-/*if let Some(tonic_status) = err.downcast_ref::<tonic::Status>() {
-    if tonic_status.code() == tonic::Code::Unavailable {
-        for each in tonic_status.metadata().get_all("ratelimit-policy"){
-
-            // Split the string into two parts based on the semicolon
-            let parts: Vec<&str> = each.to_str().unwrap_or("").split(';').collect();
-
-            if let Some(rate_limit) = parts.get(0) {
-                let rate_limit_value: u64 = rate_limit.trim().parse().unwrap_or(0);
-                error!("Rate Limit: {}", rate_limit_value);
-            }
-
-            if let Some(window) = parts.get(1) {
-                // Split the second part based on the equal sign to get the window value
-                let window_value: u64 = window.split('=').nth(1).unwrap_or("0").trim().parse().unwrap_or(0);
-                error!("Window: {}", window_value);
-            }
-        }
-    }
-}else */
@@ -1,9 +1,14 @@
-use crate::plugin::interface::agent::chain_registry::try_get_chain_registry;
+use crate::manifest::{AgentManifest, Dependency};
+use crate::plugin::interface::agent::chain_registry::{try_get_chain_registry, CHAIN_REGISTRY_KEY};
 use crate::plugin::interface::agent::{get_next_key, set_next_key, AGENT_STORE};
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::upstream::Upstream;
+use crate::plugin::interface::rate_limit::{limited, GOV_ENDPOINT};
 
 use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchain;
 use cosmos_rust_package::api::custom::types::gov::proposal_ext::{ProposalExt, ProposalStatus};
+use cosmos_rust_package::api::custom::types::NextKeyType;
 use log::info;
 
 use std::collections::{HashMap, HashSet};
@@ -15,15 +20,15 @@ use cosmos_rust_package::api::custom::query::gov::{get_proposals};
 
 use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
 use strum::IntoEnumIterator;
+use serde::Deserialize;
 
 pub const PROPOSAL_PREFIX: &str = "proposal_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GovernanceProposalFetchAgent {
     pub continue_at_key_prefix: String,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<GovernanceProposalFetchTask,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -37,8 +42,6 @@ impl Default for GovernanceProposalFetchAgent {
         Self {
             continue_at_key_prefix: "fetch_proposals_for".to_string(),
             update_interval_in_secs: 60 * 5,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
         }
     }
 }
@@ -62,10 +65,10 @@ impl GovernanceProposalFetchAgent {
 
         let mut next_key = get_next_key(&continue_at_key);
 
-        let result = get_proposals(
-            blockchain.clone(),
-            proposal_status.clone(),
-            next_key.clone(),
+        let result = limited(
+            &blockchain.name,
+            GOV_ENDPOINT,
+            get_proposals(blockchain.clone(), proposal_status.clone(), next_key.clone()),
         )
         .await;
 
@@ -164,15 +167,30 @@ impl Agent for GovernanceProposalFetchAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_blockchain_name(&self, task_type: &Self::TaskType) -> Option<String> {
+        Some(task_type.blockchain_name.clone())
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+    fn get_upstream(&self, _task_type: &Self::TaskType) -> Vec<Upstream> {
+        vec![Upstream::Key(CHAIN_REGISTRY_KEY.to_string())]
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "GovernanceProposalFetchAgent",
+            &[CHAIN_REGISTRY_KEY],
+            &[
+                &format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX),
+                &self.continue_at_key_prefix,
+            ],
+        )
+        .depends_on(Dependency::Key(CHAIN_REGISTRY_KEY.to_string()))
+    }
+
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![
+            (format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX), ProposalExt::NAME),
+            (self.continue_at_key_prefix.clone(), NextKeyType::NAME),
+        ]
     }
 }
 
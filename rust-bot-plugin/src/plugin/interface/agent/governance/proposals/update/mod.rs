@@ -1,4 +1,7 @@
+use crate::manifest::AgentManifest;
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::retry::RetryPolicy;
 
 use log::{error, info};
 
@@ -24,11 +27,9 @@ use crate::plugin::interface::agent::governance::validators::VALIDATOR_PREFIX;
 use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
 use crate::plugin::interface::agent::staking::pool::POOL_PREFIX;
 use crate::plugin::store::fallback_entry_store::Entry;
-use cosmos_rust_package::api::custom::types::gov::params_ext::ParamsExt;
 use cosmos_rust_package::api::custom::types::gov::proposal_ext::ProposalExt;
-use cosmos_rust_package::api::custom::types::gov::tally_ext::TallyResultExt;
-use cosmos_rust_package::api::custom::types::staking::pool_ext::PoolExt;
-use cosmos_rust_package::api::custom::types::staking::validators_ext::ValidatorsExt;
+use cosmos_rust_package::api::custom::types::{ParamsType, PoolType, TallyResultType, ValidatorsType};
+use serde::Deserialize;
 
 // watch one prefix that is prefix of all governance agents, inserts.
 // handle each type .ie. identify which proposal view needs to be updated.
@@ -37,13 +38,12 @@ use cosmos_rust_package::api::custom::types::staking::validators_ext::Validators
 
 // we can then simply listen for governance proposal views update events, and can then send the notification.
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GovernanceProposalViewAgent {
     pub continue_at_key_prefix: String,
     pub rate_limit_delay_in_secs: u64,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<GovernanceProposalViewTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -57,8 +57,6 @@ impl Default for GovernanceProposalViewAgent {
             continue_at_key_prefix: "update_proposal_views_for".to_string(),
             rate_limit_delay_in_secs: 10u64,
             update_interval_in_secs: 0,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 0,
         }
     }
 }
@@ -79,17 +77,17 @@ impl GovernanceProposalViewAgent {
                         let mut proposal_views = Vec::new();
 
                         if key.contains(PROPOSAL_PREFIX) {
-                            if let Some::<Entry<ProposalExt>>(Entry {
+                            if let Ok(Entry::<ProposalExt> {
                                 data: Ok(proposal), ..
-                            }) = value.to_vec().try_into().ok()
+                            }) = Entry::try_from(value.to_vec())
                             {
                                 proposal_views.push(proposal_to_view(proposal));
                             }
                         } else if key.contains(FRAUD_DETECTION_PREFIX) {
-                            if let Some::<Entry<GovernanceProposalFraudClassification>>(Entry {
+                            if let Ok(Entry::<GovernanceProposalFraudClassification> {
                                 data: Ok(fraud_classification),
                                 ..
-                            }) = value.to_vec().try_into().ok()
+                            }) = Entry::try_from(value.to_vec())
                             {
                                 if let Some(proposal_view) = get_proposal_view_by(
                                     &fraud_classification.blockchain,
@@ -99,10 +97,10 @@ impl GovernanceProposalViewAgent {
                                 }
                             }
                         } else if key.contains(TALLY_RESULT_PREFIX) {
-                            if let Some::<Entry<TallyResultExt>>(Entry {
+                            if let Ok(Entry::<TallyResultType> {
                                 data: Ok(tally_result),
                                 ..
-                            }) = value.to_vec().try_into().ok()
+                            }) = Entry::try_from(value.to_vec())
                             {
                                 if let Some(proposal_view) = get_proposal_view_by(
                                     &tally_result.blockchain,
@@ -112,9 +110,9 @@ impl GovernanceProposalViewAgent {
                                 }
                             }
                         } else if key.contains(PARAMS_PREFIX) {
-                            if let Some::<Entry<ParamsExt>>(Entry {
+                            if let Ok(Entry::<ParamsType> {
                                 data: Ok(params), ..
-                            }) = value.to_vec().try_into().ok()
+                            }) = Entry::try_from(value.to_vec())
                             {
                                 proposal_views.append(&mut get_proposal_views_by(
                                     Some(vec![params.blockchain]),
@@ -122,10 +120,10 @@ impl GovernanceProposalViewAgent {
                                 ));
                             }
                         } else if key.contains(VALIDATOR_PREFIX) {
-                            if let Some::<Entry<ValidatorsExt>>(Entry {
+                            if let Ok(Entry::<ValidatorsType> {
                                 data: Ok(validator),
                                 ..
-                            }) = value.to_vec().try_into().ok()
+                            }) = Entry::try_from(value.to_vec())
                             {
                                 proposal_views.append(&mut get_proposal_views_by(
                                     Some(vec![validator.blockchain]),
@@ -133,8 +131,8 @@ impl GovernanceProposalViewAgent {
                                 ));
                             }
                         } else if key.contains(POOL_PREFIX) {
-                            if let Some::<Entry<PoolExt>>(Entry { data: Ok(pool), .. }) =
-                                value.to_vec().try_into().ok()
+                            if let Ok(Entry::<PoolType> { data: Ok(pool), .. }) =
+                                Entry::try_from(value.to_vec())
                             {
                                 proposal_views.append(&mut get_proposal_views_by(
                                     Some(vec![pool.blockchain]),
@@ -250,15 +248,23 @@ impl Agent for GovernanceProposalViewAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+
+    fn get_retry_policy(&self, _task_type: &Self::TaskType) -> RetryPolicy {
+        // the work is local, retry right away
+        RetryPolicy::fixed(0)
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+
+    fn manifest(&self) -> AgentManifest {
+        // proposal views are stored under the proposal key, see `get_proposal_view_entry_key`
+        AgentManifest::new(
+            "GovernanceProposalViewAgent",
+            &[GOVERNANCE_PREFIX],
+            &[&format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX)],
+        )
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX), GovernanceProposalView::NAME)]
     }
 }
 
@@ -1,6 +1,10 @@
-use crate::plugin::interface::agent::chain_registry::try_get_chain_registry;
-use crate::plugin::interface::agent::{get_next_index, set_next_index, AGENT_STORE};
+use crate::manifest::{AgentManifest, Dependency};
+use crate::plugin::interface::agent::chain_registry::{try_get_chain_registry, CHAIN_REGISTRY_KEY};
+use crate::plugin::interface::agent::{get_next_index, set_next_index, ContinueAtIndexType, AGENT_STORE};
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::upstream::Upstream;
+use crate::plugin::interface::rate_limit::{limited, GOV_ENDPOINT};
 use crate::plugin::store::fallback_entry_store::{EntryError, RetrievalMethod};
 
 use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchain;
@@ -13,19 +17,20 @@ use std::future::Future;
 use std::hash::Hash;
 
 use crate::plugin::interface::agent::governance::proposals::api::get_proposals_by;
+use crate::plugin::interface::agent::governance::proposals::fetch::PROPOSAL_PREFIX;
 use cosmos_rust_package::api::custom::types::TallyResultType;
 use std::pin::Pin;
 
 use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
+use serde::Deserialize;
 
 pub const TALLY_RESULT_PREFIX: &str = "tally_result_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TallyResultsAgent {
     pub continue_at_key_prefix: String,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<TallyResultsTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -39,8 +44,6 @@ impl Default for TallyResultsAgent {
         Self {
             continue_at_key_prefix: "fetch_tally_results_for".to_string(),
             update_interval_in_secs: 60 * 15,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
         }
     }
 }
@@ -68,7 +71,7 @@ impl TallyResultsAgent {
 
         for each in values {
             let id = each.get_proposal_id();
-            let result = get_tally_v1beta1(blockchain.clone(), id).await;
+            let result = limited(&blockchain.name, GOV_ENDPOINT, get_tally_v1beta1(blockchain.clone(), id)).await;
             match result {
                 Ok(_) => {
                     set_next_index(&continue_at_key, None)?;
@@ -154,15 +157,37 @@ impl Agent for TallyResultsAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_blockchain_name(&self, task_type: &Self::TaskType) -> Option<String> {
+        Some(task_type.blockchain_name.clone())
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+    // new proposals of the blockchain are tallied right away
+    fn get_upstream(&self, task_type: &Self::TaskType) -> Vec<Upstream> {
+        vec![
+            Upstream::Key(CHAIN_REGISTRY_KEY.to_string()),
+            Upstream::Key(format!("{}{}{}_", GOVERNANCE_PREFIX, PROPOSAL_PREFIX, task_type.blockchain_name)),
+        ]
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "TallyResultsAgent",
+            &[
+                CHAIN_REGISTRY_KEY,
+                &format!("{}{}", GOVERNANCE_PREFIX, PROPOSAL_PREFIX),
+            ],
+            &[
+                &format!("{}{}", GOVERNANCE_PREFIX, TALLY_RESULT_PREFIX),
+                &self.continue_at_key_prefix,
+            ],
+        )
+        .depends_on(Dependency::Key(CHAIN_REGISTRY_KEY.to_string()))
+    }
+
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![
+            (format!("{}{}", GOVERNANCE_PREFIX, TALLY_RESULT_PREFIX), TallyResultType::NAME),
+            (self.continue_at_key_prefix.clone(), ContinueAtIndexType::NAME),
+        ]
     }
 }
 
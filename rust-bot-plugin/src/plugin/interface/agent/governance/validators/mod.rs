@@ -1,6 +1,10 @@
-use crate::plugin::interface::agent::chain_registry::try_get_chain_registry;
+use crate::manifest::{AgentManifest, Dependency};
+use crate::plugin::interface::agent::chain_registry::{try_get_chain_registry, CHAIN_REGISTRY_KEY};
 use crate::plugin::interface::agent::{get_next_key, set_next_key, AGENT_STORE};
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::upstream::Upstream;
+use crate::plugin::interface::rate_limit::{limited, STAKING_ENDPOINT};
 
 use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchain;
 use cosmos_rust_package::api::custom::query::gov::get_validators_v1beta1;
@@ -11,19 +15,19 @@ use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::hash::Hash;
 
-use cosmos_rust_package::api::custom::types::ValidatorsType;
+use cosmos_rust_package::api::custom::types::{NextKeyType, ValidatorsType};
 use std::pin::Pin;
 
 use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
+use serde::Deserialize;
 
 pub const VALIDATOR_PREFIX: &str = "validator_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ValidatorsAgent {
     pub continue_at_key_prefix: String,
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<ValidatorsTasks,i64>,
-    initial_retry_delay: i64,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -36,8 +40,6 @@ impl Default for ValidatorsAgent {
         Self {
             continue_at_key_prefix: "fetch_validators_for".to_string(),
             update_interval_in_secs: 60 * 60, // 1h
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
         }
     }
 }
@@ -55,7 +57,12 @@ impl ValidatorsAgent {
 
         let mut next_key = get_next_key(&continue_at_key);
 
-        let result = get_validators_v1beta1(blockchain.clone(), next_key.clone()).await;
+        let result = limited(
+            &blockchain.name,
+            STAKING_ENDPOINT,
+            get_validators_v1beta1(blockchain.clone(), next_key.clone()),
+        )
+        .await;
 
         if let Ok(validators) = result {
             for validator in validators.1 {
@@ -145,14 +152,29 @@ impl Agent for ValidatorsAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_blockchain_name(&self, task_type: &Self::TaskType) -> Option<String> {
+        Some(task_type.blockchain_name.clone())
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+    fn get_upstream(&self, _task_type: &Self::TaskType) -> Vec<Upstream> {
+        vec![Upstream::Key(CHAIN_REGISTRY_KEY.to_string())]
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "ValidatorsAgent",
+            &[CHAIN_REGISTRY_KEY],
+            &[
+                &format!("{}{}", GOVERNANCE_PREFIX, VALIDATOR_PREFIX),
+                &self.continue_at_key_prefix,
+            ],
+        )
+        .depends_on(Dependency::Key(CHAIN_REGISTRY_KEY.to_string()))
+    }
+
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![
+            (format!("{}{}", GOVERNANCE_PREFIX, VALIDATOR_PREFIX), ValidatorsType::NAME),
+            (self.continue_at_key_prefix.clone(), NextKeyType::NAME),
+        ]
     }
 }
@@ -0,0 +1,117 @@
+use chrono::Utc;
+use log::info;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::manifest::AgentManifest;
+use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::store::fallback_entry_store::{Reclaimed, Retention};
+
+use crate::plugin::interface::agent::AGENT_STORE;
+use serde::Deserialize;
+
+// what the last run reclaimed, an `Entry<Reclaimed>`
+pub const JANITOR_REPORT_KEY: &str = "janitor_report";
+
+// Enforces the retention rules on AGENT_STORE, nothing expires without a rule.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JanitorAgent {
+    pub update_interval_in_secs: i64,
+    // by key prefix, a key follows the rule of the longest prefix it starts with
+    pub retention: HashMap<String, Retention>,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+pub enum JanitorTasks {
+    ApplyRetention,
+}
+
+impl Default for JanitorAgent {
+    fn default() -> Self {
+        Self {
+            update_interval_in_secs: 60 * 60,
+            retention: HashMap::new(),
+        }
+    }
+}
+
+impl JanitorAgent {
+    // the prefix of the rule that applies to `key`
+    fn rule_of(&self, key: &str) -> Option<&str> {
+        self.retention
+            .keys()
+            .filter(|prefix| key.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .map(|prefix| prefix.as_str())
+    }
+
+    async fn apply_retention(agent: JanitorAgent) -> anyhow::Result<()> {
+        let task_store = AGENT_STORE.clone();
+        let now = Utc::now().timestamp();
+        // the scan may go through the whole store, it must not hold up the other tasks of the runtime
+        let scanned_store = task_store.clone();
+        let reclaimed = tokio::task::spawn_blocking(move || {
+            let mut reclaimed = Reclaimed::default();
+            for (prefix, retention) in &agent.retention {
+                for key in scanned_store.key_iter(Some(prefix)).collect::<Vec<String>>() {
+                    if agent.rule_of(&key) == Some(prefix.as_str()) {
+                        reclaimed += scanned_store.apply_retention(&key, retention, now)?;
+                    }
+                }
+            }
+            anyhow::Ok(reclaimed)
+        })
+        .await??;
+        info!(
+            "reclaimed {} revisions and {} keys, {} bytes",
+            reclaimed.revisions, reclaimed.keys, reclaimed.bytes
+        );
+        task_store.insert::<Reclaimed>(JANITOR_REPORT_KEY, Ok(reclaimed))
+    }
+
+    fn apply_retention_task(&self) -> Pin<Box<dyn Future<Output = TaskResult<JanitorTasks>> + Send>> {
+        let agent = self.clone();
+
+        Box::pin(async move {
+            TaskResult::new(
+                JanitorTasks::ApplyRetention,
+                JanitorAgent::apply_retention(agent).await,
+            )
+        })
+    }
+}
+
+impl Agent for JanitorAgent {
+    type TaskType = JanitorTasks;
+
+    fn get_tasks(
+        &self,
+        tasks_pending: HashSet<Self::TaskType>,
+    ) -> HashMap<
+                        Self::TaskType,
+                        Pin<Box<dyn Future<Output = TaskResult<Self::TaskType>> + Send>>,
+                    >
+    {
+        let mut fns = HashMap::new();
+        if !tasks_pending.contains(&JanitorTasks::ApplyRetention) {
+            fns.insert(JanitorTasks::ApplyRetention, self.apply_retention_task());
+        }
+        fns
+    }
+
+    fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
+        self.update_interval_in_secs
+    }
+
+    fn manifest(&self) -> AgentManifest {
+        // the retention rules may cover the whole store
+        AgentManifest::new("JanitorAgent", &[""], &[JANITOR_REPORT_KEY])
+    }
+
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(JANITOR_REPORT_KEY.to_string(), Reclaimed::NAME)]
+    }
+}
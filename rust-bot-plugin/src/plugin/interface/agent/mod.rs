@@ -2,21 +2,43 @@ pub mod chain_registry;
 pub mod dummy;
 pub mod fraud_detection;
 pub mod governance;
+pub mod janitor;
 pub mod staking;
 
-use crate::plugin::store::fallback_entry_store::{FallbackEntryStore, RetrievalMethod};
+use crate::plugin::store::fallback_entry_store::{FallbackEntryStore, Migrated, Purged, RetrievalMethod};
+use crate::plugin::store::sled_store::StoreSubscriber;
 use crate::PERSISTENT_SLED;
 use cosmos_rust_package::api::custom::types::NextKeyType;
+use rust_bot_common::store::{GLOBAL_PREFIX_TASK_STORE, REV_INDEX_PREFIX};
 use std::sync::Arc;
 
-static GLOBAL_PREFIX_TASK_STORE: &str = "task_store_";
-
 lazy_static::lazy_static! {
-    static ref AGENT_STORE: Arc<FallbackEntryStore> = Arc::new(FallbackEntryStore::new(&PERSISTENT_SLED.lock().unwrap().as_ref().unwrap(), GLOBAL_PREFIX_TASK_STORE));
+    static ref AGENT_STORE: Arc<FallbackEntryStore> = Arc::new(FallbackEntryStore::new(PERSISTENT_SLED.lock().unwrap().as_ref().unwrap(), GLOBAL_PREFIX_TASK_STORE));
 }
 
 type ContinueAtIndexType = Option<u64>;
 
+// Raw sled key prefix under which AGENT_STORE keeps one revision index per key.
+// Does not touch AGENT_STORE, so it is available before `init`.
+pub fn agent_store_index_prefix() -> String {
+    format!("{}{}", GLOBAL_PREFIX_TASK_STORE, REV_INDEX_PREFIX)
+}
+
+// Events of the AGENT_STORE entries under `prefix`, see `upstream::watch`.
+pub fn watch_prefix(prefix: &str) -> StoreSubscriber {
+    AGENT_STORE.watch_prefix(&mut prefix.as_bytes().to_vec())
+}
+
+// Removes the AGENT_STORE keys under `prefix`, see `FallbackEntryStore::purge`.
+pub fn purge(prefix: &str, dry_run: bool) -> anyhow::Result<Purged> {
+    AGENT_STORE.purge(prefix, dry_run)
+}
+
+// Upgrades the AGENT_STORE entries, see `FallbackEntryStore::migrate`.
+pub fn migrate(schemas: &[(String, &'static str)], dry_run: bool) -> anyhow::Result<Migrated> {
+    AGENT_STORE.migrate(schemas, dry_run)
+}
+
 pub fn get_next_key(continue_at_key: &str) -> Option<Vec<u8>> {
     let task_store = AGENT_STORE.clone();
 
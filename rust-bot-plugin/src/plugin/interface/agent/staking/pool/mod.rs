@@ -1,5 +1,10 @@
-use crate::plugin::interface::agent::chain_registry::try_get_chain_registry;
+use crate::manifest::{AgentManifest, Dependency};
+use crate::plugin::interface::agent::chain_registry::{try_get_chain_registry, CHAIN_REGISTRY_KEY};
+use crate::plugin::interface::schedule::Schedule;
 use crate::plugin::interface::{Agent, TaskResult};
+use crate::plugin::store::migration::Schema;
+use crate::plugin::interface::upstream::Upstream;
+use crate::plugin::interface::rate_limit::{limited, STAKING_ENDPOINT};
 use crate::plugin::store::fallback_entry_store::{EntryError, RetrievalMethod};
 use cosmos_rust_package::api::custom::types::PoolType;
 
@@ -16,14 +21,18 @@ use std::hash::Hash;
 use crate::plugin::interface::agent::governance::GOVERNANCE_PREFIX;
 use crate::plugin::interface::agent::AGENT_STORE;
 use std::pin::Pin;
+use serde::Deserialize;
 
 pub const POOL_PREFIX: &str = "pool_";
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct PoolAgent {
     pub update_interval_in_secs: i64,
-    pub retry_delay_in_secs: HashMap<PoolTasks,i64>,
-    initial_retry_delay: i64,
+    // replaces `update_interval_in_secs`
+    pub schedule: Option<Schedule>,
+    // gives every blockchain its own phase offset, so the interval's runs do not all start at once
+    pub spread_tasks: bool,
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
@@ -35,8 +44,8 @@ impl Default for PoolAgent {
     fn default() -> Self {
         Self {
             update_interval_in_secs: 60 * 30,
-            retry_delay_in_secs: HashMap::new(),
-            initial_retry_delay: 60,
+            schedule: None,
+            spread_tasks: false,
         }
     }
 }
@@ -51,7 +60,7 @@ impl PoolAgent {
         let task_store = AGENT_STORE.clone();
 
         let key = get_pool_entry_key(&blockchain);
-        let result = get_pool(blockchain).await?;
+        let result = limited(&blockchain.name, STAKING_ENDPOINT, get_pool(blockchain.clone())).await?;
         let data: Result<PoolType, EntryError> = Ok(result);
 
         task_store.insert_if_not_exists(&key, data)?;
@@ -106,15 +115,37 @@ impl Agent for PoolAgent {
     fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
         self.update_interval_in_secs
     }
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64 {
-        *self.retry_delay_in_secs.get(task_type).unwrap_or(&self.initial_retry_delay)
+    fn get_blockchain_name(&self, task_type: &Self::TaskType) -> Option<String> {
+        Some(task_type.blockchain_name.clone())
     }
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_delay: i64) {
-        self.retry_delay_in_secs.insert(task_type.clone(), retry_delay);
+    fn get_upstream(&self, _task_type: &Self::TaskType) -> Vec<Upstream> {
+        vec![Upstream::Key(CHAIN_REGISTRY_KEY.to_string())]
+    }
+    fn get_schedule(&self, task_type: &Self::TaskType) -> Schedule {
+        let schedule = self.schedule.clone().unwrap_or(Schedule::interval(self.update_interval_in_secs));
+        if self.spread_tasks {
+            // stable across restarts, unlike `DefaultHasher`
+            let phase_offset = task_type
+                .blockchain_name
+                .bytes()
+                .fold(0i64, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as i64));
+            schedule.with_phase_offset(phase_offset)
+        } else {
+            schedule
+        }
+    }
+
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(
+            "PoolAgent",
+            &[CHAIN_REGISTRY_KEY],
+            &[&format!("{}{}", GOVERNANCE_PREFIX, POOL_PREFIX)],
+        )
+        .depends_on(Dependency::Key(CHAIN_REGISTRY_KEY.to_string()))
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType) {
-        self.set_retry_delay_in_secs(task_type, self.initial_retry_delay);
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        vec![(format!("{}{}", GOVERNANCE_PREFIX, POOL_PREFIX), PoolType::NAME)]
     }
 }
 
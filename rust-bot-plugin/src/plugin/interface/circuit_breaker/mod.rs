@@ -0,0 +1,146 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::plugin::store::circuit_breaker_store::{self, Circuit, CircuitState};
+
+// Key of the settings the host passes to `init`, next to the sections of the agents.
+pub const CIRCUIT_BREAKER_KEY: &str = "circuit_breaker";
+
+// When the circuit of a blockchain opens, and how long it stays open before a probe.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerSettings {
+    pub failure_threshold: u32,
+    pub cooldown_in_secs: i64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_in_secs: 60,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SETTINGS: Mutex<CircuitBreakerSettings> = Mutex::new(CircuitBreakerSettings::default());
+}
+
+pub fn configure(settings: CircuitBreakerSettings) -> anyhow::Result<()> {
+    if settings.failure_threshold == 0 || settings.cooldown_in_secs <= 0 {
+        return Err(anyhow::anyhow!("invalid circuit breaker settings: the threshold and cooldown must be positive"));
+    }
+    *SETTINGS.lock().unwrap() = settings;
+    Ok(())
+}
+
+// The error of a request that was not sent because the blockchain's circuit is open.
+// `AgentManager` retries the task once the cooldown has passed instead of counting it as a failed attempt.
+#[derive(Debug)]
+pub struct CircuitOpen {
+    pub blockchain_name: String,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit of {} is open, retry in {}s", self.blockchain_name, self.retry_after.as_secs())
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+// when to retry a task that failed with `err`, none if it was not held back by an open circuit
+pub fn retry_after(err: &anyhow::Error) -> Option<Duration> {
+    err.downcast_ref::<CircuitOpen>().map(|circuit_open| circuit_open.retry_after)
+}
+
+// Whether the error says the blockchain's endpoint is down, rather than that it rejected the request.
+// Errors that never reached the endpoint (e.g. a request that could not be built) do not count.
+pub fn is_endpoint_down(err: &anyhow::Error) -> bool {
+    match err.chain().find_map(|err| err.downcast_ref::<tonic::Status>()) {
+        Some(status) => matches!(
+            status.code(),
+            tonic::Code::Unavailable | tonic::Code::DeadlineExceeded | tonic::Code::Unknown
+        ),
+        // no answer at all
+        None => err.chain().any(|err| err.is::<tonic::transport::Error>()),
+    }
+}
+
+// Checks the circuit before a request. An open circuit whose cooldown has passed lets this request through as its probe.
+// Of all agents checking an open circuit, only the one whose change is written becomes the probe.
+pub fn allow(blockchain_name: &str) -> Result<(), CircuitOpen> {
+    let settings = SETTINGS.lock().unwrap().clone();
+    let now = Utc::now().timestamp();
+    let probe = update(blockchain_name, |circuit| match circuit.state {
+        CircuitState::Closed => Ok(false),
+        // a probe whose task was dropped (e.g. timed out) does not keep the circuit half-open
+        CircuitState::Open | CircuitState::HalfOpen if now - circuit.since >= settings.cooldown_in_secs => {
+            circuit.state = CircuitState::HalfOpen;
+            circuit.since = now;
+            Ok(true)
+        }
+        CircuitState::Open | CircuitState::HalfOpen => Err(CircuitOpen {
+            blockchain_name: blockchain_name.to_string(),
+            retry_after: Duration::from_secs((circuit.since + settings.cooldown_in_secs - now).max(1) as u64),
+        }),
+    });
+    match probe {
+        Some(Ok(true)) => {
+            info!("circuit half-open: {}, probing", blockchain_name);
+            Ok(())
+        }
+        Some(Err(circuit_open)) => Err(circuit_open),
+        Some(Ok(false)) | None => Ok(()),
+    }
+}
+
+pub fn record_success(blockchain_name: &str) {
+    let closes = update(blockchain_name, |circuit| {
+        let state = circuit.state;
+        *circuit = Circuit::default();
+        state != CircuitState::Closed
+    });
+    if closes == Some(true) {
+        info!("circuit closed: {}", blockchain_name);
+    }
+}
+
+pub fn record_failure(blockchain_name: &str) {
+    let settings = SETTINGS.lock().unwrap().clone();
+    let now = Utc::now().timestamp();
+    let opened_after = update(blockchain_name, |circuit| {
+        circuit.failures += 1;
+        let opens = match circuit.state {
+            CircuitState::Closed => circuit.failures >= settings.failure_threshold,
+            CircuitState::HalfOpen => true,
+            // a request that was sent before the circuit opened
+            CircuitState::Open => false,
+        };
+        if opens {
+            circuit.state = CircuitState::Open;
+            circuit.since = now;
+        }
+        opens.then_some(circuit.failures)
+    });
+    if let Some(Some(failures)) = opened_after {
+        warn!(
+            "circuit open: {} after {} failed requests, next probe in {}s",
+            blockchain_name, failures, settings.cooldown_in_secs
+        );
+    }
+}
+
+// `circuit_breaker_store::update`, none if the circuit could not be read or written (it is then treated as closed)
+fn update<T>(blockchain_name: &str, change: impl FnMut(&mut Circuit) -> T) -> Option<T> {
+    circuit_breaker_store::update(blockchain_name, change)
+        .map_err(|err| error!("failed to update the circuit of {}: {}", blockchain_name, err))
+        .ok()
+}
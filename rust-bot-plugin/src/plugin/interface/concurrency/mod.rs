@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+// Key of the limits in the settings the host passes to `init`, next to the sections of the agents.
+pub const CONCURRENCY_KEY: &str = "concurrency";
+
+// Upper bounds for the number of tasks that run at once, unlimited if not set.
+// Tasks waiting for a permit are `pending` already.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyLimits {
+    // across all agents of the plugin
+    pub max_tasks: Option<usize>,
+    pub max_tasks_per_agent: Option<usize>,
+    // across all agents, the tasks of a blockchain query the same public endpoints
+    pub max_tasks_per_blockchain: Option<usize>,
+}
+
+lazy_static::lazy_static! {
+    static ref LIMITS: Mutex<ConcurrencyLimits> = Mutex::new(ConcurrencyLimits::default());
+    static ref GLOBAL_SEMAPHORE: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+    static ref BLOCKCHAIN_SEMAPHORES: Mutex<HashMap<String, Arc<Semaphore>>> = Mutex::new(HashMap::new());
+}
+
+// Applies to the agent managers started afterwards.
+pub fn configure(limits: ConcurrencyLimits) -> anyhow::Result<()> {
+    if [limits.max_tasks, limits.max_tasks_per_agent, limits.max_tasks_per_blockchain].contains(&Some(0)) {
+        return Err(anyhow::anyhow!("invalid concurrency limits: a limit must be at least 1"));
+    }
+    *GLOBAL_SEMAPHORE.lock().unwrap() = limits.max_tasks.map(|max| Arc::new(Semaphore::new(max)));
+    BLOCKCHAIN_SEMAPHORES.lock().unwrap().clear();
+    *LIMITS.lock().unwrap() = limits;
+    Ok(())
+}
+
+// Each `AgentManager` has its own.
+pub fn agent_semaphore() -> Option<Arc<Semaphore>> {
+    LIMITS.lock().unwrap().max_tasks_per_agent.map(|max| Arc::new(Semaphore::new(max)))
+}
+
+fn blockchain_semaphore(blockchain_name: &str) -> Option<Arc<Semaphore>> {
+    let max = LIMITS.lock().unwrap().max_tasks_per_blockchain?;
+    Some(
+        BLOCKCHAIN_SEMAPHORES
+            .lock()
+            .unwrap()
+            .entry(blockchain_name.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(max)))
+            .clone(),
+    )
+}
+
+// Runs the task once it holds a permit of every limit that applies to it.
+// Permits are taken from the most specific limit to the global one, so no two tasks wait on each other crosswise.
+pub async fn limited<F: Future>(
+    task: F,
+    agent_semaphore: Option<Arc<Semaphore>>,
+    blockchain_name: Option<String>,
+) -> F::Output {
+    let global_semaphore = GLOBAL_SEMAPHORE.lock().unwrap().clone();
+    let semaphores = [
+        blockchain_name.and_then(|name| blockchain_semaphore(&name)),
+        agent_semaphore,
+        global_semaphore,
+    ];
+    let mut permits = Vec::new();
+    for semaphore in semaphores.into_iter().flatten() {
+        // the semaphores are never closed
+        if let Ok(permit) = semaphore.acquire_owned().await {
+            permits.push(permit);
+        }
+    }
+    let output = task.await;
+    drop(permits);
+    output
+}
@@ -1,22 +1,41 @@
 pub mod agent;
+pub mod circuit_breaker;
+pub mod concurrency;
+pub mod rate_limit;
+pub mod retry;
+pub mod schedule;
+pub mod settings;
+pub mod upstream;
+
+#[cfg(test)]
+mod simulation;
 
 use std::collections::{HashMap, HashSet};
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::future::Future;
 use std::hash::Hash;
 use std::ops::Deref;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 use std::time::Duration;
 
 use chrono::Utc;
 use log::{error, info};
-use rand::Rng;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot, Semaphore};
 use tokio::task::{JoinError, JoinSet};
-use crate::{CANCELLATION_FLAG, RT, TaskState};
+use crate::{RT, TaskState};
+use crate::manifest::AgentManifest;
+use rust_bot_common::abi::TaskStatus;
+use crate::plugin::store::fallback_entry_store::Purged;
+use crate::plugin::store::schedule_store::{self, TaskSchedule};
+use concurrency::limited;
+use retry::{GiveUp, RetryPolicy, RetryState};
+use schedule::Schedule;
+use settings::ManagerSettings;
+use upstream::{Upstream, UPSTREAM_DELAY};
+use tokio_util::sync::CancellationToken;
+use tokio_util::time::delay_queue::Key;
 use tokio_util::time::DelayQueue;
 
 
@@ -38,9 +57,49 @@ impl<T> TaskResult<T> {
 
 struct DelayedResultQueue<T> {
     pub inner: DelayQueue<TaskResult<T>>,
+    // key of each queued task, so `run_now` can move it to the front
+    keys: HashMap<T, Key>,
 }
 
-impl<T> Future for DelayedResultQueue<T> {
+impl<T: Clone + Hash + Eq> DelayedResultQueue<T> {
+    fn insert(&mut self, task_result: TaskResult<T>, delay: Duration) {
+        let task_type = task_result.task_type.clone();
+        let key = self.inner.insert(task_result, delay);
+        self.keys.insert(task_type, key);
+    }
+
+    fn is_queued(&self, task_type: &T) -> bool {
+        self.keys.contains_key(task_type)
+    }
+
+    // false if the task is not waiting for its next run
+    fn run_now(&mut self, task_type: &T) -> bool {
+        match self.keys.get(task_type) {
+            Some(key) => {
+                self.inner.reset(key, Duration::ZERO);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // brings the next run of a queued task forward to `delay` from now, false if it is not queued or due soon anyway
+    fn run_within(&mut self, task_type: &T, delay: Duration) -> bool {
+        match self.keys.get(task_type) {
+            // not just `delay`, a task that was brought forward would be again by the next write of the same burst
+            Some(key) if self.inner.deadline(key) > tokio::time::Instant::now() + delay * 2 => {
+                self.inner.reset(key, delay);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// nothing in the queue is pinned, the task types are only kept as map keys
+impl<T> Unpin for DelayedResultQueue<T> {}
+
+impl<T: Hash + Eq> Future for DelayedResultQueue<T> {
     type Output = TaskResult<T>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -49,6 +108,7 @@ impl<T> Future for DelayedResultQueue<T> {
             Poll::Ready(Some(expired_entry)) => {
                 // An entry in the delay queue has expired
                 let task_result = expired_entry.into_inner();
+                self.keys.remove(&task_result.task_type);
 
                 // Return the task result
                 Poll::Ready(task_result)
@@ -65,185 +125,389 @@ impl<T> Future for DelayedResultQueue<T> {
     }
 }
 
+// Requests to the loop of a running `AgentManager`, it owns the delay queue and the join set.
+enum Command<T> {
+    // answers whether the task was waiting for its next run
+    RunNow(T, oneshot::Sender<bool>),
+}
+
 pub struct AgentManager<T> where
     T: Clone + Send + Sync + Hash + Eq + Debug + 'static {
-    agent: Arc<RwLock<Box<dyn Agent<TaskType = T>>>>,
-    delay_queue: Arc<Mutex<DelayedResultQueue<T>>>,
-    join_set: Arc<Mutex<JoinSet<TaskResult<T>>>>,
+    // moved into the loop by `run`
+    agent: Option<Box<dyn Agent<TaskType = T>>>,
+    commands: mpsc::UnboundedSender<Command<T>>,
+    command_receiver: Option<mpsc::UnboundedReceiver<Command<T>>>,
+    // written by the loop, read by `TaskControl`
     task_registry: Arc<Mutex<HashMap<T, TaskState>>>,
+    // cancelled to stop this manager only, see `stop_agent` in lib.rs
+    cancellation_token: CancellationToken,
+    // limits the tasks of this manager, see `concurrency`
+    semaphore: Option<Arc<Semaphore>>,
+    settings: Arc<ManagerSettings>,
 }
 
 impl <T>AgentManager<T>
     where
     T: Clone + Send + Sync + Hash + Eq + Debug + 'static
     {
-    pub fn new(agent: Box<dyn Agent<TaskType = T>>)  -> Self
+    pub fn new(agent: Box<dyn Agent<TaskType = T>>, settings: ManagerSettings, cancellation_token: CancellationToken)  -> Self
    {
+       let (commands, command_receiver) = mpsc::unbounded_channel();
        AgentManager::<T> {
-            agent: Arc::new(RwLock::new(agent)),
-            delay_queue: Arc::new(Mutex::new(DelayedResultQueue { inner: DelayQueue::new() })),
-            join_set: Arc::new(Mutex::new(JoinSet::new())),
+            agent: Some(agent),
+            commands,
+            command_receiver: Some(command_receiver),
             task_registry: Arc::new(Mutex::new(HashMap::new())),
+            cancellation_token,
+            semaphore: concurrency::agent_semaphore(),
+            settings: Arc::new(settings),
         }
     }
-    pub fn run(&mut self) -> Option<Vec<tokio::task::JoinHandle<()>>>
+
+    // Spawns the event loop on the plugin's runtime.
+    pub fn run(&mut self) -> Option<tokio::task::JoinHandle<()>>
     {
         let rt_clone = RT.clone();
         let runtime = rt_clone.read().unwrap();
 
-        runtime.as_ref().map(|runtime| {
-
-            CANCELLATION_FLAG.store(false, Ordering::SeqCst);
-
-            let agent = self.agent.clone();
-            let delay_queue = self.delay_queue.clone();
-            let join_set = self.join_set.clone();
-            let task_registry = self.task_registry.clone();
-
-
-            let handle_delay_queue = runtime.spawn(async move {
-
-                {
-                    let fns = agent.read().await.get_tasks(HashSet::new());
-
-                    if fns.len() != 0 {
-                        info!("tasks added: {:#?}", fns.keys());
-
-                        let mut tr = task_registry.lock().await;
-                        let mut j_s = join_set.lock().await;
+        let runtime = runtime.as_ref()?;
+        let event_loop = self.event_loop()?;
+        Some(runtime.spawn(event_loop))
+    }
 
-                        for (task_type, func) in fns {
-                            tr.insert(
-                                task_type,
-                                TaskState::Pending(process_join_set(&mut j_s, func)),
+    // The loop that runs the agent's tasks, it only wakes up when a task completes,
+    // a scheduled run is due, a command arrives or the manager is cancelled.
+    fn event_loop(&mut self) -> Option<impl Future<Output = ()> + Send + 'static>
+    {
+        let agent = self.agent.take()?;
+        let mut commands = self.command_receiver.take()?;
+        let task_registry = self.task_registry.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let semaphore = self.semaphore.clone();
+        let settings = self.settings.clone();
+
+        Some(async move {
+            let agent_name = agent.manifest().name;
+            let mut delay_queue = DelayedResultQueue { inner: DelayQueue::new(), keys: HashMap::new() };
+            let mut join_set: JoinSet<TaskResult<T>> = JoinSet::new();
+            let mut written = upstream::subscribe();
+            // pending tasks whose upstream wrote while they ran, they run again right after
+            let mut triggered: HashSet<T> = HashSet::new();
+
+            // tasks that ran before a restart wait until they are due again
+            let schedules = schedule_store::load(&agent_name);
+            // failed runs of each task since it last resolved, keyed by task name, carried over a restart
+            let mut retries: HashMap<String, RetryState> = schedules
+                .iter()
+                .map(|(task, schedule)| {
+                    let retry = RetryState { attempts: schedule.attempts, delay_in_secs: schedule.retry_delay_in_secs };
+                    (task.clone(), retry)
+                })
+                .collect();
+
+            {
+                let now = Utc::now().timestamp();
+                let mut tr = task_registry.lock().unwrap();
+
+                let mut tasks_added = Vec::new();
+                for (task_type, func) in agent.get_tasks(HashSet::new()) {
+                    agent.get_upstream(&task_type).iter().for_each(upstream::watch);
+                    match schedules.get(&format!("{:?}", task_type)) {
+                        Some(schedule) if schedule.next_due > now => {
+                            info!("task restored: {:?}, due in {}s", task_type, schedule.next_due - now);
+                            let task_result = if schedule.resolved {
+                                tr.insert(task_type.clone(), TaskState::Resolved(schedule.last_run));
+                                Ok(())
+                            } else {
+                                tr.insert(task_type.clone(), TaskState::Failed(schedule.last_run));
+                                Err(anyhow::anyhow!("failed before the restart"))
+                            };
+                            delay_queue.insert(
+                                TaskResult { task_type, task_result, timestamp: schedule.last_run },
+                                Duration::from_secs((schedule.next_due - now) as u64),
                             );
                         }
+                        Some(_) => {
+                            tasks_added.push(format!("{:?}", task_type));
+                            let func = prepare_task(&*agent, &settings, &semaphore, &task_type, func);
+                            tr.insert(task_type, TaskState::Pending(process_join_set(&mut join_set, func), now));
+                        }
+                        // never ran, the schedule decides when it runs first
+                        None => match agent.get_schedule(&task_type).first_run(now) {
+                            Some(first_run) if first_run <= now => {
+                                tasks_added.push(format!("{:?}", task_type));
+                                let func = prepare_task(&*agent, &settings, &semaphore, &task_type, func);
+                                tr.insert(task_type, TaskState::Pending(process_join_set(&mut join_set, func), now));
+                            }
+                            Some(first_run) => {
+                                info!("task scheduled: {:?}, due in {}s", task_type, first_run - now);
+                                tr.insert(task_type.clone(), TaskState::Scheduled(first_run));
+                                delay_queue.insert(
+                                    TaskResult::new(task_type, Ok(())),
+                                    Duration::from_secs((first_run - now) as u64),
+                                );
+                            }
+                            None => info!("task has no scheduled run: {:?}", task_type),
+                        },
                     }
                 }
+                if !tasks_added.is_empty() {
+                    info!("tasks added: {:#?}", tasks_added);
+                }
+            }
 
-
-                while !CANCELLATION_FLAG.load(Ordering::SeqCst) {
-
-                    let mut delay_queue = delay_queue.lock().await;
-
-                    if let Ok(expired) = tokio::time::timeout(Duration::from_millis(100), (&mut *delay_queue)).await {
-                        let mut tr = task_registry.lock().await;
-
-                        match expired.task_result {
-                            Ok(_) => {
-                                tr.insert(
-                                    expired.task_type,
-                                    TaskState::Resolved(expired.timestamp),
-                                );
-                            },
-                            Err(_) => {
-                                tr.insert(
-                                    expired.task_type,
-                                    TaskState::Failed(expired.timestamp),
-                                );;
+            loop {
+                // The delay queue is polled again on every iteration, an empty queue does not wake the loop
+                // but everything that is inserted into it is inserted by this loop.
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    Some(joined) = join_set.join_next(), if !join_set.is_empty() => match joined {
+                        Ok(task_result) => {
+                            let rerun = triggered.remove(&task_result.task_type);
+                            if let Some((task_result, delay_duration)) = handle_task_result(&*agent, &agent_name, &settings, &task_registry, &mut retries, task_result) {
+                                let delay_duration = if rerun { delay_duration.min(UPSTREAM_DELAY) } else { delay_duration };
+                                delay_queue.insert(task_result, delay_duration);
                             }
-                        };
+                        }
+                        Err(err) => handle_join_error(&task_registry, err),
+                    },
+                    // its state was set when it finished (or was scheduled)
+                    expired = &mut delay_queue => {
+                        let expired_task_type = expired.task_type;
+                        let mut tr = task_registry.lock().unwrap();
 
                         let tasks_pending = tr
                             .iter()
                             .filter_map(|(task_type, task_status)| match task_status {
-                                TaskState::Pending(_) => Some(task_type.clone()),
+                                TaskState::Pending(..) => Some(task_type.clone()),
                                 _ => None
                             })
                             .collect::<HashSet<T>>();
 
-                        let fns = agent.read().await.get_tasks(tasks_pending);
+                        // the other tasks wait for their own turn in the delay queue,
+                        // panicked and cancelled ones for the agent to be restarted (see `handle_join_error`)
+                        let fns = agent
+                            .get_tasks(tasks_pending)
+                            .into_iter()
+                            .filter(|(task_type, _)| {
+                                task_type == &expired_task_type
+                                    || !(delay_queue.is_queued(task_type)
+                                        || matches!(
+                                            tr.get(task_type),
+                                            Some(TaskState::Resolved(_))
+                                                | Some(TaskState::Failed(_))
+                                                | Some(TaskState::TimedOut(_))
+                                                | Some(TaskState::Panicked(_))
+                                                | Some(TaskState::Cancelled(_))
+                                        ))
+                            })
+                            .collect::<HashMap<_, _>>();
                         if fns.len() != 0 {
                             info!("tasks added: {:#?}", fns.keys());
 
-                            let mut j_s = join_set.lock().await;
                             for (task_type, func) in fns {
+                                agent.get_upstream(&task_type).iter().for_each(upstream::watch);
+                                let func = prepare_task(&*agent, &settings, &semaphore, &task_type, func);
                                 tr.insert(
                                     task_type,
-                                    TaskState::Pending(process_join_set(&mut j_s, func)),
+                                    TaskState::Pending(process_join_set(&mut join_set, func), Utc::now().timestamp()),
                                 );
                             }
                         }
                     }
-
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                }
-                let mut j_s = join_set.lock().await;
-                j_s.shutdown().await;
-            });
-
-
-            let agent = self.agent.clone();
-            let delay_queue = self.delay_queue.clone();
-            let join_set = self.join_set.clone();
-            let task_registry = self.task_registry.clone();
-
-            let handle_tasks = runtime.spawn(async move {
-
-                while !CANCELLATION_FLAG.load(Ordering::SeqCst) {
-
-                    // wait for one task to complete!
-                    let mut j_s = join_set.lock().await;
-
-                    for each in poll_completed_tasks(&mut j_s).await {
-                        match each {
-                            Ok(task_result) => match task_result.task_result {
-                                Ok(_) => {
-                                    info!("task resolved: {:?}", &task_result.task_type);
-                                    let mut tmp_agent = agent.write().await;
-                                    tmp_agent.reset_retry_delay(&task_result.task_type);
-                                    let mut tmp_delay_queue = delay_queue.lock().await;
-
-                                    let delay_duration = Duration::from_secs(tmp_agent.get_update_interval_in_secs(&task_result.task_type) as u64);
-                                    tmp_delay_queue.inner.insert(task_result, delay_duration);
-                                }
-                                Err(ref err) => {
-                                    error!("task failed: {:?}", (&task_result.task_type, err));
-                                    let mut tmp_agent = agent.write().await;
-                                    tmp_agent.exponential_backoff_with_jitter(&task_result.task_type);
-                                    let mut tmp_delay_queue = delay_queue.lock().await;
-                                    let delay_duration = Duration::from_secs(tmp_agent.get_retry_delay_in_secs(&task_result.task_type) as u64);
-                                    tmp_delay_queue.inner.insert(task_result, delay_duration);
-                                }
-                            },
-                            Err(err) => {
-                                let task_id = err.id();
-                                let mut tr = task_registry.lock().await;
-
-                                let _ = tr
-                                    .iter_mut()
-                                    .filter(|(_, task_state)| {
-                                        if let TaskState::Pending(id) = task_state {
-                                            &task_id == id
-                                        } else {
-                                            false
-                                        }
-                                    })
-                                    .map(|(task_type, task_state)| {
-                                        let timestamp = Utc::now().timestamp();
-                                        if err.is_cancelled() {
-                                            error!("task cancelled: {:?}", (&task_type, &err));
-                                            *task_state = TaskState::Cancelled(timestamp);
-                                        } else if err.is_panic() {
-                                            error!("task panicked: {:?}", (&task_type, &err));
-                                            *task_state = TaskState::Panicked(timestamp);
-                                        } else {
-                                            unreachable!()
-                                        }
-                                    });
+                    Ok(written) = written.recv() => {
+                        let tr = task_registry.lock().unwrap();
+                        for (task_type, task_state) in tr.iter() {
+                            if !agent.get_upstream(task_type).contains(&written) {
+                                continue;
+                            }
+                            if matches!(task_state, TaskState::Pending(..)) {
+                                triggered.insert(task_type.clone());
+                            } else if delay_queue.run_within(task_type, UPSTREAM_DELAY) {
+                                info!("task triggered by {:?}: {:?}", written, task_type);
                             }
                         }
                     }
-                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Some(command) = commands.recv() => match command {
+                        Command::RunNow(task_type, reply) => {
+                            let _ = reply.send(delay_queue.run_now(&task_type));
+                        }
+                    },
                 }
-                let mut j_s = join_set.lock().await;
-                j_s.shutdown().await;
-            });
-            vec![handle_delay_queue,handle_tasks]
+            }
+            // no new tasks are started, let the in-flight ones finish (`shutdown` aborts them at its deadline)
+            while let Some(joined) = join_set.join_next().await {
+                match joined {
+                    Ok(task_result) => {
+                        handle_task_result(&*agent, &agent_name, &settings, &task_registry, &mut retries, task_result);
+                    }
+                    Err(err) => handle_join_error(&task_registry, err),
+                }
+            }
         })
     }
 }
 
+// Records the result of a run, the task and when it runs next, none if it does not run again.
+fn handle_task_result<T>(
+    agent: &dyn Agent<TaskType = T>,
+    agent_name: &str,
+    settings: &ManagerSettings,
+    task_registry: &Mutex<HashMap<T, TaskState>>,
+    retries: &mut HashMap<String, RetryState>,
+    task_result: TaskResult<T>,
+) -> Option<(TaskResult<T>, Duration)>
+where
+    T: Clone + Send + Sync + Hash + Eq + Debug + 'static,
+{
+    match task_result.task_result {
+        Ok(_) => {
+            info!("task resolved: {:?}", &task_result.task_type);
+            task_registry.lock().unwrap().insert(
+                task_result.task_type.clone(),
+                TaskState::Resolved(task_result.timestamp),
+            );
+            retries.remove(&format!("{:?}", task_result.task_type));
+            upstream::resolved(agent_name, &format!("{:?}", task_result.task_type));
+
+            let now = Utc::now().timestamp();
+            match agent.get_schedule(&task_result.task_type).next_run(now) {
+                Some(next_run) => {
+                    let delay_duration = Duration::from_secs((next_run - now).max(0) as u64);
+                    save_schedule(agent_name, &task_result, delay_duration, RetryState::default());
+                    Some((task_result, delay_duration))
+                }
+                None => {
+                    info!("task has no further scheduled run: {:?}", &task_result.task_type);
+                    None
+                }
+            }
+        }
+        Err(ref err) => {
+            let task_state = if err.is::<TimedOut>() {
+                error!("task timed out: {:?}", (&task_result.task_type, err));
+                TaskState::TimedOut(task_result.timestamp)
+            } else {
+                error!("task failed: {:?}", (&task_result.task_type, err));
+                TaskState::Failed(task_result.timestamp)
+            };
+            task_registry.lock().unwrap().insert(task_result.task_type.clone(), task_state);
+            let task = format!("{:?}", task_result.task_type);
+            let retry = retries.entry(task.clone()).or_default();
+            // a rate limited endpoint or an open circuit tell when to retry, the retry policy is for all other errors
+            let delay_duration = match rate_limit::retry_after(err).or_else(|| circuit_breaker::retry_after(err)) {
+                Some(retry_after) => Some(retry_after),
+                None => {
+                    let retry_policy = settings
+                        .retry_policy(&task)
+                        .unwrap_or_else(|| agent.get_retry_policy(&task_result.task_type));
+                    match retry_policy.next_retry(retry) {
+                        Some(delay_in_secs) => Some(Duration::from_secs(delay_in_secs as u64)),
+                        None if retry_policy.give_up == GiveUp::NextRun => {
+                            error!("task gave up after {} attempts, waiting for its next run: {:?}", retry.attempts, &task_result.task_type);
+                            *retry = RetryState::default();
+                            let now = Utc::now().timestamp();
+                            agent
+                                .get_schedule(&task_result.task_type)
+                                .next_run(now)
+                                .map(|next_run| Duration::from_secs((next_run - now).max(0) as u64))
+                        }
+                        None => {
+                            error!("task gave up after {} attempts: {:?}", retry.attempts, &task_result.task_type);
+                            None
+                        }
+                    }
+                }
+            };
+            let retry = *retry;
+            delay_duration.map(|delay_duration| {
+                save_schedule(agent_name, &task_result, delay_duration, retry);
+                (task_result, delay_duration)
+            })
+        }
+    }
+}
+
+// A task that was aborted or panicked, it is not run again until the agent is restarted.
+fn handle_join_error<T>(task_registry: &Mutex<HashMap<T, TaskState>>, err: JoinError)
+where
+    T: Clone + Send + Sync + Hash + Eq + Debug + 'static,
+{
+    let task_id = err.id();
+    let mut tr = task_registry.lock().unwrap();
+    let timestamp = Utc::now().timestamp();
+    for (task_type, task_state) in tr.iter_mut() {
+        if !matches!(task_state, TaskState::Pending(id, _) if *id == task_id) {
+            continue;
+        }
+        if err.is_cancelled() {
+            error!("task cancelled: {:?}", (&task_type, &err));
+            *task_state = TaskState::Cancelled(timestamp);
+        } else if err.is_panic() {
+            error!("task panicked: {:?}", (&task_type, &err));
+            *task_state = TaskState::Panicked(timestamp);
+        }
+    }
+}
+
+// Object safe access to the tasks of an `AgentManager`, independent of its task type.
+pub trait TaskControl: Send + Sync {
+    fn task_states(&self, agent: &str) -> Vec<TaskStatus>;
+    // reschedules a task that waits for its next run to run right away
+    fn run_task(&self, task: &str) -> anyhow::Result<()>;
+}
+
+impl<T> TaskControl for AgentManager<T>
+where
+    T: Clone + Send + Sync + Hash + Eq + Debug + 'static,
+{
+    fn task_states(&self, agent: &str) -> Vec<TaskStatus> {
+        let now = Utc::now().timestamp();
+        let mut task_states: Vec<TaskStatus> = self
+            .task_registry
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(task_type, task_state)| TaskStatus {
+                agent: agent.to_string(),
+                task: format!("{:?}", task_type),
+                state: match task_state {
+                    TaskState::Pending(_, since) if now - since > self.settings.hung_after_in_secs => "hung",
+                    _ => task_state.name(),
+                }
+                .to_string(),
+                timestamp: task_state.timestamp(),
+            })
+            .collect();
+        task_states.sort_by(|a, b| a.task.cmp(&b.task));
+        task_states
+    }
+
+    fn run_task(&self, task: &str) -> anyhow::Result<()> {
+        let task_type = self
+            .task_registry
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(task_type, _)| format!("{:?}", task_type) == task)
+            .map(|(task_type, task_state)| (task_type.clone(), matches!(task_state, TaskState::Pending(..))));
+        match task_type {
+            None => Err(anyhow::anyhow!("unknown task `{}`", task)),
+            Some((_, true)) => Err(anyhow::anyhow!("task `{}` is already running", task)),
+            Some((task_type, false)) => {
+                let (reply, answer) = oneshot::channel();
+                self.commands
+                    .send(Command::RunNow(task_type, reply))
+                    .map_err(|_| anyhow::anyhow!("task `{}` is not scheduled, its agent stopped", task))?;
+                match answer.blocking_recv() {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(anyhow::anyhow!("task `{}` is not scheduled", task)),
+                    Err(_) => Err(anyhow::anyhow!("task `{}` is not scheduled, its agent stopped", task)),
+                }
+            }
+        }
+    }
+}
 
 pub trait Agent: Send + Sync {
     type TaskType: Clone + Hash + Eq + Debug + Send + 'static;
@@ -255,29 +519,96 @@ pub trait Agent: Send + Sync {
         Pin<Box<dyn Future<Output = TaskResult<Self::TaskType>> + Send>>
     >;
     fn get_update_interval_in_secs(&self, task_type: &Self::TaskType) -> i64;
-    fn get_retry_delay_in_secs(&self, task_type: &Self::TaskType) -> i64;
-    fn set_retry_delay_in_secs(&mut self, task_type: &Self::TaskType, retry_interval: i64);
-
-    fn exponential_backoff_with_jitter(&mut self, task_type: &Self::TaskType) {
-        let current_retry_delay =self.get_retry_delay_in_secs(task_type);
-        let max_interval = 60 * 5;
-        let backoff_factor = 2;
-        let jitter = rand::thread_rng().gen_range(0..=current_retry_delay / 2);
-
-        let val = (current_retry_delay * backoff_factor + jitter).min(max_interval);
-        self.set_retry_delay_in_secs(task_type,val);
+    // when a resolved task runs next, `get_update_interval_in_secs` after the last run by default
+    fn get_schedule(&self, task_type: &Self::TaskType) -> Schedule {
+        Schedule::interval(self.get_update_interval_in_secs(task_type))
+    }
+    // tasks of the same blockchain share its limit, see `concurrency`
+    fn get_blockchain_name(&self, _task_type: &Self::TaskType) -> Option<String> {
+        None
+    }
+    // how a failed task is retried unless the agent's settings choose another `retry_policy`
+    fn get_retry_policy(&self, _task_type: &Self::TaskType) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+    // what the task reads that others write, it runs right after they did instead of waiting for its next run
+    fn get_upstream(&self, _task_type: &Self::TaskType) -> Vec<Upstream> {
+        Vec::new()
     }
 
-    fn reset_retry_delay(&mut self, task_type: &Self::TaskType);
+    // name of the agent and the AGENT_STORE key prefixes it reads and writes
+    fn manifest(&self) -> AgentManifest;
+    // the `Schema` name of what it stores under each of its `writes` prefixes,
+    // `migrate` seals the entries written before they had an envelope with it
+    fn schemas(&self) -> Vec<(String, &'static str)> {
+        Vec::new()
+    }
 
+    // Removes what the agent wrote, every key under the `writes` prefixes of its manifest.
+    fn clear_store(&self, dry_run: bool) -> anyhow::Result<Purged> {
+        let writes = self.manifest().writes;
+        let mut purged = Purged::default();
+        // a prefix within another one is purged with it
+        for prefix in writes.iter().filter(|prefix| {
+            !writes.iter().any(|other| other != *prefix && prefix.starts_with(other.as_str()))
+        }) {
+            purged += agent::purge(prefix, dry_run)?;
+        }
+        Ok(purged)
+    }
+}
 
+// The error of a run that took longer than the task's timeout, see `ManagerSettings`.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
 
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {}s", self.0.as_secs())
+    }
+}
 
-    // clear all data from store
-    // export all data from store
-    // import all data from file to store
+impl std::error::Error for TimedOut {}
+
+// Wraps a task of `get_tasks` with its timeout and the concurrency limits that apply to it.
+// The timeout starts once the task holds its permits.
+fn prepare_task<T>(
+    agent: &dyn Agent<TaskType = T>,
+    settings: &ManagerSettings,
+    semaphore: &Option<Arc<Semaphore>>,
+    task_type: &T,
+    func: Pin<Box<dyn Future<Output = TaskResult<T>> + Send>>,
+) -> impl Future<Output = TaskResult<T>> + Send
+where
+    T: Clone + Send + Sync + Hash + Eq + Debug + 'static,
+{
+    let timeout = settings.task_timeout(&format!("{:?}", task_type));
+    let timed_out_task_type = task_type.clone();
+    let task = async move {
+        match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, func).await {
+                Ok(task_result) => task_result,
+                Err(_) => TaskResult::new(timed_out_task_type, Err(TimedOut(timeout).into())),
+            },
+            None => func.await,
+        }
+    };
+    limited(task, semaphore.clone(), agent.get_blockchain_name(task_type))
+}
 
-    // task meta data / process information might be written into temporary_sled!
+// Written after every run, `AgentManager::run` restores the schedule from it after a restart.
+fn save_schedule<T: Debug>(agent: &str, task_result: &TaskResult<T>, delay: Duration, retry: RetryState) {
+    let schedule = TaskSchedule {
+        resolved: task_result.task_result.is_ok(),
+        last_run: task_result.timestamp,
+        next_due: task_result.timestamp + delay.as_secs() as i64,
+        retry_delay_in_secs: retry.delay_in_secs,
+        attempts: retry.attempts,
+    };
+    let task = format!("{:?}", task_result.task_type);
+    if let Err(err) = schedule_store::save(agent, &task, &schedule) {
+        error!("failed to save the schedule of {}: {}", task, err);
+    }
 }
 
 pub fn process_join_set<T, F>(join_set: &mut JoinSet<TaskResult<T>>, f: F) -> tokio::task::Id
@@ -289,34 +620,3 @@ where
         f.await
     }).id()
 }
-
-pub fn poll_completed_tasks<T>(
-    join_set: &mut JoinSet<T>,
-) -> Pin<Box<dyn Future<Output = Vec<Result<T, JoinError>>> + Send + '_>>
-where
-    T: Send + 'static,
-{
-    Box::pin(async move {
-        let mut completed: Vec<Result<T, JoinError>> = Vec::new();
-
-        // The following removes all completed tasks from the set.
-        // Unresolved tasks are unaffected.
-        for _ in 0..join_set.len() {
-            let result =
-                tokio::time::timeout(std::time::Duration::from_millis(0), join_set.join_next())
-                    .await;
-            // join_set.join_next()
-            // If this returns `Poll::Ready(Some(_))`, then the task that completed is removed from the set.
-            match result {
-                Ok(Some(res)) => {
-                    completed.push(res);
-                }
-                Err(_) | Ok(None) => {
-                    // timeout returned an error (currently all tasks pending) or join_set empty
-                    break;
-                }
-            };
-        }
-        completed
-    })
-}
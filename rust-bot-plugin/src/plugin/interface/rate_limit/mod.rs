@@ -0,0 +1,160 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, warn};
+
+use crate::plugin::interface::circuit_breaker;
+use crate::plugin::store::rate_limit_store::{self, EndpointRateLimit};
+
+// gRPC services the agents query, public nodes limit the requests per service and blockchain.
+pub const GOV_ENDPOINT: &str = "cosmos.gov.v1beta1.Query";
+pub const STAKING_ENDPOINT: &str = "cosmos.staking.v1beta1.Query";
+
+// At most `limit` requests per `window_in_secs`, as announced by the `ratelimit-policy` metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub window_in_secs: u64,
+}
+
+impl RateLimit {
+    // e.g. "100;w=60"
+    pub fn parse(policy: &str) -> Option<Self> {
+        let mut parts = policy.split(';');
+        let limit: u64 = parts.next()?.trim().parse().ok()?;
+        let window_in_secs: u64 = parts
+            .find_map(|part| part.trim().strip_prefix("w="))
+            .and_then(|window| window.trim().parse().ok())?;
+        if limit == 0 || window_in_secs == 0 {
+            return None;
+        }
+        Some(RateLimit { limit, window_in_secs })
+    }
+
+    // The policy of a rate limit rejection anywhere in the error chain.
+    pub fn from_error(err: &anyhow::Error) -> Option<Self> {
+        let status = err.chain().find_map(|err| err.downcast_ref::<tonic::Status>())?;
+        if !matches!(status.code(), tonic::Code::Unavailable | tonic::Code::ResourceExhausted) {
+            return None;
+        }
+        status
+            .metadata()
+            .get_all("ratelimit-policy")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(RateLimit::parse)
+    }
+
+    // requests this far apart stay within the limit
+    fn spacing(&self) -> Duration {
+        Duration::from_millis(self.window_in_secs * 1000 / self.limit)
+    }
+}
+
+// The error of a request that the endpoint rejected because of its rate limit.
+// `AgentManager` retries the task once the window has passed instead of backing off.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub blockchain_name: String,
+    pub endpoint: &'static str,
+    pub rate_limit: RateLimit,
+    pub retry_after: Duration,
+    message: String,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rate limited by {} ({} requests per {}s): {}",
+            self.endpoint, self.blockchain_name, self.rate_limit.limit, self.rate_limit.window_in_secs, self.message
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// when to retry a task that failed with `err`, none if it was not rate limited
+pub fn retry_after(err: &anyhow::Error) -> Option<Duration> {
+    err.downcast_ref::<RateLimited>().map(|rate_limited| rate_limited.retry_after)
+}
+
+// Sends the request no sooner than the last recorded rate limit of the endpoint allows,
+// and not at all while the blockchain's circuit is open (`CircuitOpen`).
+// A rate limit rejection is recorded for all agents and returned as `RateLimited`.
+pub async fn limited<F, T>(blockchain_name: &str, endpoint: &'static str, request: F) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    circuit_breaker::allow(blockchain_name)?;
+    if let Some(wait) = reserve(blockchain_name, endpoint) {
+        tokio::time::sleep(wait).await;
+    }
+    match request.await {
+        Err(err) => match RateLimit::from_error(&err) {
+            Some(rate_limit) => Err(record(blockchain_name, endpoint, rate_limit, &err).into()),
+            None => {
+                if circuit_breaker::is_endpoint_down(&err) {
+                    circuit_breaker::record_failure(blockchain_name);
+                } else {
+                    circuit_breaker::record_success(blockchain_name);
+                }
+                Err(err)
+            }
+        },
+        result => {
+            circuit_breaker::record_success(blockchain_name);
+            result
+        }
+    }
+}
+
+// How long to wait for the next free slot of all plugins, none if the endpoint never announced a rate limit.
+fn reserve(blockchain_name: &str, endpoint: &'static str) -> Option<Duration> {
+    let recorded = match rate_limit_store::load(blockchain_name, endpoint) {
+        Ok(recorded) => recorded?,
+        Err(err) => {
+            error!("failed to load the rate limit of {} {}: {}", blockchain_name, endpoint, err);
+            return None;
+        }
+    };
+    let rate_limit = RateLimit {
+        limit: recorded.limit,
+        window_in_secs: recorded.window_in_secs,
+    };
+    let now = Utc::now().timestamp_millis();
+    let spacing_in_ms = rate_limit.spacing().as_millis() as i64;
+    match rate_limit_store::reserve_slot(blockchain_name, endpoint, now.max(recorded.blocked_until), spacing_in_ms) {
+        Ok(slot) => Some(Duration::from_millis((slot - now).max(0) as u64)),
+        Err(err) => {
+            error!("failed to reserve a request to {} {}: {}", blockchain_name, endpoint, err);
+            None
+        }
+    }
+}
+
+// The window starts over, nothing is sent to the endpoint until it has passed.
+fn record(blockchain_name: &str, endpoint: &'static str, rate_limit: RateLimit, err: &anyhow::Error) -> RateLimited {
+    let retry_after = Duration::from_secs(rate_limit.window_in_secs);
+    warn!(
+        "rate limited: {} {}, {} requests per {}s",
+        blockchain_name, endpoint, rate_limit.limit, rate_limit.window_in_secs
+    );
+    let recorded = EndpointRateLimit {
+        limit: rate_limit.limit,
+        window_in_secs: rate_limit.window_in_secs,
+        blocked_until: Utc::now().timestamp_millis() + retry_after.as_millis() as i64,
+    };
+    if let Err(err) = rate_limit_store::save(blockchain_name, endpoint, &recorded) {
+        error!("failed to save the rate limit of {} {}: {}", blockchain_name, endpoint, err);
+    }
+    RateLimited {
+        blockchain_name: blockchain_name.to_string(),
+        endpoint,
+        rate_limit,
+        retry_after,
+        message: err.to_string(),
+    }
+}
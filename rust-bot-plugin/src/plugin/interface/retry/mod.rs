@@ -0,0 +1 @@
+pub use rust_bot_common::retry::{GiveUp, RetryPolicy, RetryState};
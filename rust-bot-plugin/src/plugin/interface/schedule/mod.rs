@@ -0,0 +1,208 @@
+use std::str::FromStr;
+
+use chrono::{NaiveTime, TimeZone, Timelike, Utc};
+use serde::Deserialize;
+
+// When a resolved task runs next, see `Agent::get_schedule`.
+// Failed tasks are retried with their `RetryPolicy` regardless of their schedule.
+// In the agent settings: `schedule = { cron = "0 0 3 * * *" }`, `schedule = { daily = "03:00" }`
+// or `schedule = { interval = { every_in_secs = 1800, initial_delay_in_secs = 60, phase_offset_in_secs = 300 } }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "ScheduleSettings")]
+pub enum Schedule {
+    // `every_in_secs` after the last run, the first run `initial_delay_in_secs` after the start.
+    // With a phase offset the runs are aligned to the wall clock instead,
+    // to unix times `t` with `t % every_in_secs == phase_offset_in_secs`.
+    Interval {
+        every_in_secs: i64,
+        initial_delay_in_secs: i64,
+        phase_offset_in_secs: Option<i64>,
+    },
+    // the first run waits for the next matching time as well
+    Cron(Box<cron::Schedule>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ScheduleSettings {
+    Interval {
+        every_in_secs: i64,
+        #[serde(default)]
+        initial_delay_in_secs: i64,
+        #[serde(default)]
+        phase_offset_in_secs: Option<i64>,
+    },
+    // "sec min hour day-of-month month day-of-week [year]", in UTC
+    Cron(String),
+    // "HH:MM" or "HH:MM:SS", in UTC
+    Daily(String),
+}
+
+impl TryFrom<ScheduleSettings> for Schedule {
+    type Error = String;
+
+    fn try_from(settings: ScheduleSettings) -> Result<Self, Self::Error> {
+        match settings {
+            ScheduleSettings::Interval { every_in_secs, initial_delay_in_secs, phase_offset_in_secs } => {
+                if every_in_secs <= 0 {
+                    return Err(format!("every_in_secs has to be positive, got {}", every_in_secs));
+                }
+                if initial_delay_in_secs < 0 {
+                    return Err(format!("initial_delay_in_secs must not be negative, got {}", initial_delay_in_secs));
+                }
+                Ok(Schedule::Interval {
+                    every_in_secs,
+                    initial_delay_in_secs,
+                    phase_offset_in_secs: phase_offset_in_secs.map(|x| x.rem_euclid(every_in_secs)),
+                })
+            }
+            ScheduleSettings::Cron(expression) => cron::Schedule::from_str(&expression)
+                .map(|schedule| Schedule::Cron(Box::new(schedule)))
+                .map_err(|err| format!("invalid cron expression `{}`: {}", expression, err)),
+            ScheduleSettings::Daily(time) => {
+                let time = NaiveTime::parse_from_str(&time, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M"))
+                    .map_err(|err| format!("invalid time of day `{}`: {}", time, err))?;
+                let expression = format!("{} {} {} * * *", time.second(), time.minute(), time.hour());
+                ScheduleSettings::Cron(expression).try_into()
+            }
+        }
+    }
+}
+
+impl Schedule {
+    pub fn interval(every_in_secs: i64) -> Self {
+        Schedule::Interval {
+            every_in_secs,
+            initial_delay_in_secs: 0,
+            phase_offset_in_secs: None,
+        }
+    }
+
+    // Same schedule with runs shifted by `phase_offset_in_secs`, cron schedules are left as they are.
+    pub fn with_phase_offset(self, phase_offset_in_secs: i64) -> Self {
+        match self {
+            Schedule::Interval { every_in_secs, initial_delay_in_secs, .. } => Schedule::Interval {
+                every_in_secs,
+                initial_delay_in_secs,
+                phase_offset_in_secs: Some(phase_offset_in_secs.rem_euclid(every_in_secs)),
+            },
+            cron => cron,
+        }
+    }
+
+    // Unix time of the first run of a task that never ran, `now` is a unix time as well.
+    pub fn first_run(&self, now: i64) -> Option<i64> {
+        match self {
+            Schedule::Interval { every_in_secs, initial_delay_in_secs, phase_offset_in_secs } => {
+                let earliest = now + initial_delay_in_secs;
+                Some(match phase_offset_in_secs {
+                    Some(offset) => aligned(earliest - 1, *every_in_secs, *offset),
+                    None => earliest,
+                })
+            }
+            Schedule::Cron(schedule) => next_cron(schedule, now),
+        }
+    }
+
+    // Unix time of the run after one that finished at `now`, none if the schedule has no further runs.
+    pub fn next_run(&self, now: i64) -> Option<i64> {
+        match self {
+            Schedule::Interval { every_in_secs, phase_offset_in_secs, .. } => Some(match phase_offset_in_secs {
+                Some(offset) => aligned(now, *every_in_secs, *offset),
+                None => now + every_in_secs,
+            }),
+            Schedule::Cron(schedule) => next_cron(schedule, now),
+        }
+    }
+}
+
+// first `t > now` with `t % every == offset`
+fn aligned(now: i64, every: i64, offset: i64) -> i64 {
+    ((now - offset).div_euclid(every) + 1) * every + offset
+}
+
+fn next_cron(schedule: &cron::Schedule, now: i64) -> Option<i64> {
+    let now = Utc.timestamp_opt(now, 0).single()?;
+    schedule.after(&now).next().map(|next| next.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn parse(settings: serde_json::Value) -> Result<Schedule, String> {
+        Schedule::deserialize(&settings).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn aligned_is_strictly_after_now() {
+        assert_eq!(aligned(100, 60, 10), 130);
+        assert_eq!(aligned(130, 60, 10), 190);
+        assert_eq!(aligned(129, 60, 10), 130);
+        assert_eq!(aligned(-5, 60, 10), 10);
+        assert_eq!(aligned(-60, 60, 0), 0);
+    }
+
+    #[test]
+    fn phase_offset_is_within_the_interval() {
+        let schedule = parse(json!({ "interval": { "every_in_secs": 60, "phase_offset_in_secs": 130 } })).unwrap();
+        assert_eq!(schedule.next_run(100), Some(130));
+        let schedule = Schedule::interval(60).with_phase_offset(-10);
+        assert_eq!(schedule.next_run(100), Some(110));
+        assert!(matches!(schedule, Schedule::Interval { phase_offset_in_secs: Some(50), .. }));
+    }
+
+    #[test]
+    fn interval_without_phase_offset() {
+        let schedule = parse(json!({ "interval": { "every_in_secs": 60, "initial_delay_in_secs": 15 } })).unwrap();
+        assert_eq!(schedule.first_run(1000), Some(1015));
+        assert_eq!(schedule.next_run(1000), Some(1060));
+    }
+
+    #[test]
+    fn interval_with_phase_offset() {
+        let schedule = Schedule::interval(60).with_phase_offset(10);
+        // a first run that is due right now is not pushed back by a whole interval
+        assert_eq!(schedule.first_run(130), Some(130));
+        assert_eq!(schedule.first_run(131), Some(190));
+        assert_eq!(schedule.next_run(130), Some(190));
+
+        let schedule = parse(json!({ "interval": { "every_in_secs": 60, "initial_delay_in_secs": 45, "phase_offset_in_secs": 10 } }));
+        assert_eq!(schedule.unwrap().first_run(100), Some(190));
+    }
+
+    #[test]
+    fn daily_is_a_cron_schedule() {
+        let schedule = parse(json!({ "daily": "03:00" })).unwrap();
+        assert!(matches!(schedule, Schedule::Cron(_)));
+        assert_eq!(schedule.first_run(0), Some(3 * 3600));
+        assert_eq!(schedule.next_run(3 * 3600), Some(DAY + 3 * 3600));
+
+        let schedule = parse(json!({ "daily": "23:59:30" })).unwrap();
+        assert_eq!(schedule.next_run(DAY), Some(2 * DAY - 30));
+    }
+
+    #[test]
+    fn cron() {
+        let schedule = parse(json!({ "cron": "0 0 */6 * * *" })).unwrap();
+        assert_eq!(schedule.first_run(1), Some(6 * 3600));
+        assert_eq!(schedule.next_run(6 * 3600), Some(12 * 3600));
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        for invalid in [
+            json!({ "interval": { "every_in_secs": 0 } }),
+            json!({ "interval": { "every_in_secs": 60, "initial_delay_in_secs": -1 } }),
+            json!({ "cron": "every day" }),
+            json!({ "daily": "25:00" }),
+            json!({ "weekly": "monday" }),
+        ] {
+            assert!(parse(invalid.clone()).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// Runs `AgentManager` with scripted agents on tokio's paused clock and an in-memory sled.
+// Time only advances while every task waits, so the task states are the same on every run.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::retry::{GiveUp, RetryPolicy};
+use rust_bot_common::retry::Backoff;
+use super::settings::ManagerSettings;
+use super::{handle_join_error, Agent, AgentManager, TaskResult};
+use crate::manifest::AgentManifest;
+use crate::plugin::store::sled_store::StoreBackend;
+use crate::{TaskState, PERSISTENT_SLED, TEMPORARY_SLED};
+
+static STORES: Once = Once::new();
+
+fn in_memory_stores() {
+    STORES.call_once(|| {
+        let open = || StoreBackend::from(sled::Config::new().temporary(true).open().unwrap());
+        *PERSISTENT_SLED.lock().unwrap() = Some(open());
+        *TEMPORARY_SLED.lock().unwrap() = Some(open());
+    });
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    // the run takes that many seconds
+    Resolve(u64),
+    Fail(u64),
+    Panic(u64),
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum SimTask {
+    Task,
+    Other,
+}
+
+struct Script {
+    steps: Vec<Step>,
+    runs: AtomicUsize,
+}
+
+// An agent whose tasks each run through their script, the last step repeats.
+#[derive(Clone)]
+struct ScriptedAgent {
+    // the schedules of all simulations share the temporary sled, every agent needs its own name
+    name: &'static str,
+    scripts: Arc<HashMap<SimTask, Script>>,
+    every_in_secs: i64,
+    retry_policy: RetryPolicy,
+}
+
+impl ScriptedAgent {
+    // with the single task `SimTask::Task`
+    fn new(name: &'static str, script: &[Step]) -> Self {
+        Self {
+            name,
+            scripts: Arc::new(HashMap::new()),
+            every_in_secs: 100,
+            retry_policy: RetryPolicy::fixed(1),
+        }
+        .task(SimTask::Task, script)
+    }
+
+    fn task(mut self, task: SimTask, script: &[Step]) -> Self {
+        let script = Script {
+            steps: script.to_vec(),
+            runs: AtomicUsize::new(0),
+        };
+        Arc::get_mut(&mut self.scripts).expect("tasks are added before the agent is cloned").insert(task, script);
+        self
+    }
+
+    fn every_in_secs(mut self, every_in_secs: i64) -> Self {
+        self.every_in_secs = every_in_secs;
+        self
+    }
+
+    fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn runs(&self) -> usize {
+        self.runs_of(&SimTask::Task)
+    }
+
+    fn runs_of(&self, task: &SimTask) -> usize {
+        self.scripts[task].runs.load(Ordering::SeqCst)
+    }
+}
+
+impl Agent for ScriptedAgent {
+    type TaskType = SimTask;
+
+    fn get_tasks(
+        &self,
+        tasks_pending: HashSet<Self::TaskType>,
+    ) -> HashMap<Self::TaskType, Pin<Box<dyn Future<Output = TaskResult<Self::TaskType>> + Send>>> {
+        let mut fns: HashMap<Self::TaskType, Pin<Box<dyn Future<Output = TaskResult<Self::TaskType>> + Send>>> =
+            HashMap::new();
+        for task in self.scripts.keys().filter(|task| !tasks_pending.contains(*task)) {
+            let scripts = self.scripts.clone();
+            let task = task.clone();
+            fns.insert(task.clone(), Box::pin(async move {
+                let script = &scripts[&task];
+                let run = script.runs.fetch_add(1, Ordering::SeqCst);
+                let step = script.steps[run.min(script.steps.len() - 1)];
+                let result = match step {
+                    Step::Resolve(secs) => {
+                        tokio::time::sleep(Duration::from_secs(secs)).await;
+                        Ok(())
+                    }
+                    Step::Fail(secs) => {
+                        tokio::time::sleep(Duration::from_secs(secs)).await;
+                        Err(anyhow::anyhow!("scripted failure"))
+                    }
+                    Step::Panic(secs) => {
+                        tokio::time::sleep(Duration::from_secs(secs)).await;
+                        panic!("scripted panic")
+                    }
+                };
+                TaskResult::new(task, result)
+            }));
+        }
+        fns
+    }
+
+    fn get_update_interval_in_secs(&self, _task_type: &Self::TaskType) -> i64 {
+        self.every_in_secs
+    }
+
+    fn get_retry_policy(&self, _task_type: &Self::TaskType) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn manifest(&self) -> AgentManifest {
+        AgentManifest::new(self.name, &[], &[])
+    }
+}
+
+struct Simulation {
+    manager: AgentManager<SimTask>,
+    handle: JoinHandle<()>,
+    started: Instant,
+}
+
+impl Simulation {
+    fn start(agent: &ScriptedAgent, settings: ManagerSettings) -> Self {
+        in_memory_stores();
+        let mut manager = AgentManager::new(Box::new(agent.clone()), settings, CancellationToken::new());
+        let event_loop = manager.event_loop().expect("the event loop is only taken once");
+        Simulation {
+            manager,
+            handle: tokio::spawn(event_loop),
+            started: Instant::now(),
+        }
+    }
+
+    // Cancels the run of `task` at `at_in_ms` as the runtime's shutdown would, the manager keeps no handle to abort a single run.
+    fn cancel_at(&self, task: SimTask, at_in_ms: u64) {
+        let task_registry = self.manager.task_registry.clone();
+        let at = self.started + Duration::from_millis(at_in_ms);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            let aborted = tokio::spawn(std::future::pending::<()>());
+            aborted.abort();
+            let err = aborted.await.unwrap_err();
+            task_registry.lock().unwrap().insert(task, TaskState::Pending(err.id(), Utc::now().timestamp()));
+            handle_join_error(&task_registry, err);
+        });
+    }
+
+    async fn timeline(&self, until_in_secs: u64) -> Vec<(u64, &'static str)> {
+        self.timeline_of(&SimTask::Task, until_in_secs).await
+    }
+
+    // State changes of the task until `until_in_secs`, by the second they happened in.
+    // Samples at the half second, runs start and end on full seconds.
+    async fn timeline_of(&self, task: &SimTask, until_in_secs: u64) -> Vec<(u64, &'static str)> {
+        let mut timeline: Vec<(u64, &'static str)> = Vec::new();
+        for second in 0..until_in_secs {
+            tokio::time::sleep_until(self.started + Duration::from_millis(second * 1000 + 500)).await;
+            let state = self
+                .manager
+                .task_registry
+                .lock()
+                .unwrap()
+                .get(task)
+                .map(|task_state| task_state.name());
+            if let Some(state) = state {
+                if timeline.last().map(|(_, last)| *last != state).unwrap_or(true) {
+                    timeline.push((second, state));
+                }
+            }
+        }
+        timeline
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn resolved_task_runs_again_after_its_interval() {
+    let agent = ScriptedAgent::new("SimResolveAgent", &[Step::Resolve(2)]).every_in_secs(10);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    assert_eq!(
+        simulation.timeline(30).await,
+        vec![(0, "pending"), (2, "resolved"), (12, "pending"), (14, "resolved"), (24, "pending"), (26, "resolved")]
+    );
+    assert_eq!(agent.runs(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_task_is_retried_with_backoff() {
+    let retry_policy = RetryPolicy {
+        backoff: Backoff::Linear {
+            initial_delay_in_secs: 1,
+            increment_in_secs: 2,
+            max_delay_in_secs: 10,
+        },
+        ..RetryPolicy::default()
+    };
+    let agent = ScriptedAgent::new("SimBackoffAgent", &[Step::Fail(1), Step::Fail(1), Step::Resolve(1)])
+        .retry_policy(retry_policy);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    // retried after 1s, then 3s, the interval starts over once it resolved
+    assert_eq!(
+        simulation.timeline(110).await,
+        vec![
+            (0, "pending"),
+            (1, "failed"),
+            (2, "pending"),
+            (3, "failed"),
+            (6, "pending"),
+            (7, "resolved"),
+            (107, "pending"),
+            (108, "resolved"),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn task_that_gives_up_stops() {
+    let retry_policy = RetryPolicy {
+        max_attempts: Some(2),
+        give_up: GiveUp::Stop,
+        ..RetryPolicy::fixed(1)
+    };
+    let agent = ScriptedAgent::new("SimStopAgent", &[Step::Fail(1)]).retry_policy(retry_policy);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    assert_eq!(
+        simulation.timeline(300).await,
+        vec![(0, "pending"), (1, "failed"), (2, "pending"), (3, "failed")]
+    );
+    assert_eq!(agent.runs(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn task_that_gives_up_waits_for_its_next_run() {
+    let retry_policy = RetryPolicy {
+        max_attempts: Some(2),
+        give_up: GiveUp::NextRun,
+        ..RetryPolicy::fixed(1)
+    };
+    let agent = ScriptedAgent::new("SimNextRunAgent", &[Step::Fail(1)])
+        .every_in_secs(20)
+        .retry_policy(retry_policy);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    // the attempts start over with the next run
+    assert_eq!(
+        simulation.timeline(30).await,
+        vec![
+            (0, "pending"),
+            (1, "failed"),
+            (2, "pending"),
+            (3, "failed"),
+            (23, "pending"),
+            (24, "failed"),
+            (25, "pending"),
+            (26, "failed"),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn timed_out_task_is_retried() {
+    let agent = ScriptedAgent::new("SimTimeoutAgent", &[Step::Resolve(3600)]).retry_policy(RetryPolicy::fixed(2));
+    let settings = ManagerSettings {
+        task_timeout_in_secs: Some(5),
+        ..ManagerSettings::default()
+    };
+    let simulation = Simulation::start(&agent, settings);
+
+    assert_eq!(
+        simulation.timeline(15).await,
+        vec![(0, "pending"), (5, "timed_out"), (7, "pending"), (12, "timed_out"), (14, "pending")]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn panicked_task_is_not_run_again() {
+    let agent = ScriptedAgent::new("SimPanicAgent", &[Step::Panic(1)]).every_in_secs(1);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    assert_eq!(simulation.timeline(10).await, vec![(0, "pending"), (1, "panicked")]);
+    assert_eq!(agent.runs(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn panicked_task_is_not_run_again_when_another_task_expires() {
+    let agent = ScriptedAgent::new("SimPanicBesideAgent", &[Step::Panic(1)])
+        .task(SimTask::Other, &[Step::Resolve(1)])
+        .every_in_secs(2);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+
+    // the other task runs every 3s
+    assert_eq!(simulation.timeline(20).await, vec![(0, "pending"), (1, "panicked")]);
+    assert_eq!(agent.runs(), 1);
+    assert_eq!(agent.runs_of(&SimTask::Other), 7);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancelled_task_is_not_run_again_when_another_task_expires() {
+    let agent = ScriptedAgent::new("SimCancelBesideAgent", &[Step::Resolve(3600)])
+        .task(SimTask::Other, &[Step::Resolve(1)])
+        .every_in_secs(2);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+    simulation.cancel_at(SimTask::Task, 1200);
+
+    assert_eq!(simulation.timeline(20).await, vec![(0, "pending"), (1, "cancelled")]);
+    assert_eq!(agent.runs(), 1);
+    assert_eq!(agent.runs_of(&SimTask::Other), 7);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancelled_manager_lets_in_flight_tasks_finish() {
+    let agent = ScriptedAgent::new("SimCancelAgent", &[Step::Resolve(10)]).every_in_secs(1);
+    let simulation = Simulation::start(&agent, ManagerSettings::default());
+    let cancellation_token = simulation.manager.cancellation_token.clone();
+    let started = simulation.started;
+    tokio::spawn(async move {
+        tokio::time::sleep_until(started + Duration::from_millis(3500)).await;
+        cancellation_token.cancel();
+    });
+
+    assert_eq!(simulation.timeline(20).await, vec![(0, "pending"), (10, "resolved")]);
+    assert!(simulation.handle.is_finished());
+    assert_eq!(agent.runs(), 1);
+}
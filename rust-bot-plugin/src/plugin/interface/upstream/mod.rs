@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::info;
+use sled::Event;
+use tokio::sync::broadcast;
+
+use crate::plugin::interface::agent::watch_prefix;
+
+// how long a triggered task waits for more writes of its upstream before it runs
+pub const UPSTREAM_DELAY: Duration = Duration::from_secs(1);
+
+// Data a task is derived from, see `Agent::get_upstream`.
+// A task runs as soon as its upstream wrote new data instead of waiting for its next run.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    // a write to AGENT_STORE under the key prefix, by any agent that shares the store
+    Key(String),
+    // a resolved run of a task of another agent of this plugin, named as `rust-bot-ctl tasks` shows them
+    Task { agent: String, task: String },
+}
+
+lazy_static::lazy_static! {
+    // every upstream write, each `AgentManager` picks the ones its tasks depend on
+    static ref WRITTEN: broadcast::Sender<Upstream> = broadcast::channel(1024).0;
+    // key prefixes that are watched already, one subscription per prefix for all agents
+    static ref WATCHED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+pub fn subscribe() -> broadcast::Receiver<Upstream> {
+    WRITTEN.subscribe()
+}
+
+pub fn resolved(agent: &str, task: &str) {
+    // no subscriber, no downstream task
+    let _ = WRITTEN.send(Upstream::Task {
+        agent: agent.to_string(),
+        task: task.to_string(),
+    });
+}
+
+// Starts to watch the key prefix of an upstream, must be called from within the runtime.
+pub fn watch(upstream: &Upstream) {
+    let prefix = match upstream {
+        Upstream::Key(prefix) => prefix.clone(),
+        Upstream::Task { .. } => return,
+    };
+    if !WATCHED.lock().unwrap().insert(prefix.clone()) {
+        return;
+    }
+    info!("watching upstream: {}", prefix);
+    tokio::spawn(async move {
+        let mut subscriber = watch_prefix(&prefix);
+        while let Some(event) = subscriber.next_event().await {
+            if let Event::Insert { .. } = event {
+                let _ = WRITTEN.send(Upstream::Key(prefix.clone()));
+            }
+        }
+        WATCHED.lock().unwrap().remove(&prefix);
+    });
+}
@@ -0,0 +1,59 @@
+use super::sled_store::SledStore;
+
+use serde::{Deserialize, Serialize};
+
+use crate::TEMPORARY_SLED;
+
+static GLOBAL_PREFIX_CIRCUIT_BREAKER_STORE: &str = "circuit_breaker_store_";
+
+// Circuit breaker of a blockchain, written to TEMPORARY_SLED so the agents of every plugin share it,
+// see `interface::circuit_breaker`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Circuit {
+    pub state: CircuitState,
+    // failed requests in a row
+    pub failures: u32,
+    // unix time the circuit opened, or the probe of a half-open circuit started
+    pub since: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    // no requests are sent until the cooldown has passed
+    Open,
+    // a single request probes whether the blockchain is back
+    HalfOpen,
+}
+
+fn circuit_breaker_store() -> anyhow::Result<SledStore> {
+    match TEMPORARY_SLED.lock().unwrap().as_ref() {
+        Some(backend) => Ok(SledStore::new(backend, GLOBAL_PREFIX_CIRCUIT_BREAKER_STORE)),
+        None => Err(anyhow::anyhow!("Plugin not yet initialized")),
+    }
+}
+
+// Applies `change` to the circuit of the blockchain (closed if nothing was recorded) and writes it if it changed.
+// If another agent wrote the circuit in between, `change` runs again on the new one.
+pub fn update<T>(blockchain_name: &str, mut change: impl FnMut(&mut Circuit) -> T) -> anyhow::Result<T> {
+    let store = circuit_breaker_store()?;
+    let key = blockchain_name.as_bytes().to_vec();
+    loop {
+        let current = store.get(key.clone())?;
+        let circuit: Circuit = match &current {
+            Some(value) => bincode::deserialize(value)?,
+            None => Circuit::default(),
+        };
+        let mut changed = circuit.clone();
+        let result = change(&mut changed);
+        if changed == circuit {
+            return Ok(result);
+        }
+        let expected = vec![(key.clone(), current.map(|value| value.to_vec()))];
+        let batch = vec![(key.clone(), Some(bincode::serialize(&changed)?))];
+        if store.apply_if(expected, batch)? {
+            return Ok(result);
+        }
+    }
+}
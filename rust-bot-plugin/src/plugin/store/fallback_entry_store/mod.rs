@@ -1,12 +1,15 @@
-use super::sled_store::SledStore;
+use super::migration::{self, Schema, UNVERSIONED};
+use super::sled_store::{SledStore, StoreBackend, StoreSubscriber};
 
-use log::{debug, trace};
-use sled::{IVec, Subscriber};
+use log::{debug, trace, warn};
+use rust_bot_common::store::{self, ENVELOPE_MAGIC, KEY_PREFIX, REV_INDEX_PREFIX};
+use sled::IVec;
 
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::HashSet,
     error::Error as StdError,
     fmt::{self, Display},
 };
@@ -20,10 +23,38 @@ pub struct Entry<T> {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct IgnoreEntry {}
 
-impl TryFrom<Vec<u8>> for IgnoreEntry {
-    type Error = ();
-    fn try_from(_item: Vec<u8>) -> Result<Self, Self::Error> {
-        Ok(IgnoreEntry {})
+// Entries are stored in an envelope with the name and version of their schema, see `migration`.
+// Entries written before start with the `Result` variant (0 or 1) instead of ENVELOPE_MAGIC.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    magic: [u8; 4],
+    schema: String,
+    version: u32,
+    // the bincode encoded `Entry<T>`, last so that the stored value still ends with its timestamp
+    entry: Vec<u8>,
+}
+
+impl Envelope {
+    fn seal(schema: &str, version: u32, entry: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(&Envelope {
+            magic: ENVELOPE_MAGIC,
+            schema: schema.to_string(),
+            version,
+            entry,
+        })?)
+    }
+
+    // an entry without envelope has an empty schema name and is `UNVERSIONED`
+    fn open(value: &[u8]) -> anyhow::Result<Self> {
+        if value.starts_with(&ENVELOPE_MAGIC) {
+            return Ok(bincode::deserialize(value)?);
+        }
+        Ok(Envelope {
+            magic: ENVELOPE_MAGIC,
+            schema: String::new(),
+            version: UNVERSIONED,
+            entry: value.to_vec(),
+        })
     }
 }
 
@@ -39,17 +70,23 @@ impl<T: std::cmp::PartialEq> Entry<T> {
     }
 }
 
-impl<T: for<'a> Deserialize<'a>> TryFrom<Vec<u8>> for Entry<T> {
+// Entries of an older version of the schema are migrated as they are read.
+impl<T: for<'a> Deserialize<'a> + Schema> TryFrom<Vec<u8>> for Entry<T> {
     type Error = anyhow::Error;
     fn try_from(item: Vec<u8>) -> anyhow::Result<Self> {
-        Ok(bincode::deserialize(&item[..])?)
+        let envelope = Envelope::open(&item)?;
+        if !envelope.schema.is_empty() && envelope.schema != T::NAME {
+            return Err(anyhow::anyhow!("{} entry read as {}", envelope.schema, T::NAME));
+        }
+        let entry = migration::migrate_entry(T::NAME, envelope.version, T::VERSION, envelope.entry)?;
+        Ok(bincode::deserialize(&entry[..])?)
     }
 }
 
-impl<T: Serialize> TryFrom<Entry<T>> for Vec<u8> {
+impl<T: Serialize + Schema> TryFrom<Entry<T>> for Vec<u8> {
     type Error = anyhow::Error;
     fn try_from(item: Entry<T>) -> anyhow::Result<Self> {
-        Ok(bincode::serialize(&item)?)
+        Envelope::seal(T::NAME, T::VERSION, bincode::serialize(&item)?)
     }
 }
 
@@ -84,8 +121,99 @@ pub enum RetrievalMethod {
 
 pub struct FallbackEntryStore(SledStore);
 
-const REV_INDEX_PREFIX: &str = "rev_index_";
-const KEY_PREFIX: &str = "key_";
+// Keys and revisions removed by `FallbackEntryStore::purge`, or that a dry run would remove.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Purged {
+    pub keys: u64,
+    pub revisions: u64,
+    // revisions no revision index points to, e.g. left by a crash
+    pub orphans: u64,
+}
+
+impl std::ops::AddAssign for Purged {
+    fn add_assign(&mut self, other: Self) {
+        self.keys += other.keys;
+        self.revisions += other.revisions;
+        self.orphans += other.orphans;
+    }
+}
+
+// How long and how many revisions of a key are kept, see `FallbackEntryStore::apply_retention`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    // revisions written longer ago are removed, the key with them once all are
+    pub max_age_in_secs: Option<i64>,
+    // the number of latest revisions that are kept
+    pub max_revisions: Option<u64>,
+    // the latest `Ok` revision is kept regardless, `RetrievalMethod::GetOk` still finds it
+    pub keep_last_ok: bool,
+}
+
+// Removed by `FallbackEntryStore::apply_retention`, `bytes` counts the keys and values in sled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reclaimed {
+    pub keys: u64,
+    pub revisions: u64,
+    pub bytes: u64,
+}
+
+impl std::ops::AddAssign for Reclaimed {
+    fn add_assign(&mut self, other: Self) {
+        self.keys += other.keys;
+        self.revisions += other.revisions;
+        self.bytes += other.bytes;
+    }
+}
+
+// Counted by `FallbackEntryStore::migrate`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Migrated {
+    // revisions upgraded to the current version of their schema, sealed in an envelope if they had none
+    pub upgraded: u64,
+    // revisions without envelope under a key no agent declares a schema for, e.g. those of WASM plugins
+    pub unversioned: u64,
+    // revisions of an unknown schema, of a newer version, without the migrations they need
+    // or without envelope and none of the schemas of their key
+    pub failed: u64,
+}
+
+// The stored `Entry<T>` starts with the `Result` variant (a u32, 0 is `Ok`) and ends with the timestamp (an i64),
+// both can be read without knowing `T`.
+fn is_ok_entry(value: &[u8]) -> bool {
+    Envelope::open(value)
+        .map(|envelope| envelope.entry.starts_with(&[0, 0, 0, 0]))
+        .unwrap_or(false)
+}
+
+// The schemas stored under the longest of the prefixes of `key`, none if no agent declares one.
+fn schemas_of(key: &str, schemas: &[(String, &'static str)]) -> Option<Vec<&'static str>> {
+    let longest = schemas
+        .iter()
+        .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+        .map(|(prefix, _)| prefix.len())
+        .max()?;
+    let mut candidates: Vec<&'static str> = schemas
+        .iter()
+        .filter(|(prefix, _)| prefix.len() == longest && key.starts_with(prefix.as_str()))
+        .map(|(_, schema)| *schema)
+        .collect();
+    candidates.dedup();
+    Some(candidates)
+}
+
+fn entry_timestamp(entry: &[u8]) -> Option<i64> {
+    let bytes = entry.get(entry.len().checked_sub(8)?..)?;
+    Some(i64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn rev_index_key(key: &str) -> Vec<u8> {
+    store::rev_index_key(key).into_bytes()
+}
+
+fn rev_key(key: &str, index: u64) -> Vec<u8> {
+    store::revision_key(key, index).into_bytes()
+}
 
 impl Clone for FallbackEntryStore {
     fn clone(&self) -> Self {
@@ -95,8 +223,8 @@ impl Clone for FallbackEntryStore {
 }
 
 impl FallbackEntryStore {
-    pub fn new(sled_db: &sled::Db, global_prefix: &str) -> Self {
-        FallbackEntryStore(SledStore::new(sled_db, global_prefix))
+    pub fn new(backend: &StoreBackend, global_prefix: &str) -> Self {
+        FallbackEntryStore(SledStore::new(backend, global_prefix))
     }
 
     // Get: returns the max revision.
@@ -104,7 +232,7 @@ impl FallbackEntryStore {
     // the item stored and found with the given key must impl Deserialize for T, else an Error is returned.
     pub fn get<T>(&self, key: &str, retrieval_method: &RetrievalMethod) -> anyhow::Result<Entry<T>>
     where
-        T: for<'a> Deserialize<'a> + Serialize,
+        T: for<'a> Deserialize<'a> + Serialize + Schema,
     {
         let current_rev: Option<IVec> = self
             .0
@@ -161,7 +289,7 @@ impl FallbackEntryStore {
 
     pub fn get_index_of_ok_result<T>(&self, key: &str, index: u64) -> anyhow::Result<u64>
     where
-        T: for<'a> Deserialize<'a> + Serialize,
+        T: for<'a> Deserialize<'a> + Serialize + Schema,
     {
         for i in (0..=index).rev() {
             let key = format!("{}{}_rev_{}", KEY_PREFIX, key, i);
@@ -198,33 +326,36 @@ impl FallbackEntryStore {
     // This method is typically used to clean up the revision history and remove older entries that are no longer needed, improving performance and reducing storage space.
     pub fn cleanup_revision_history<T>(&self, key: &str, max_index: u64) -> anyhow::Result<()>
     where
-        T: for<'a> Deserialize<'a> + Serialize,
+        T: for<'a> Deserialize<'a> + Serialize + Schema,
     {
         trace!(
             "remove_historic_entries: key: {}, max_index: {}",
             key,
             max_index
         );
-        let smallest_required_index = self
-            .get_index_of_ok_result::<T>(key, max_index)
-            .unwrap_or(max_index);
-        for i in (0..smallest_required_index).rev() {
-            if self
-                .0
-                .remove(
-                    format!("{}{}_rev_{}", KEY_PREFIX, &key, i)
-                        .as_bytes()
-                        .to_vec(),
-                )?
-                .is_none()
-            {
-                trace!("key does not exist: key: {}, index: {}", key, i);
-                break;
-            } else {
-                trace!("removed: key: {}, index: {}", key, i);
+        loop {
+            let current_rev = self.current_rev(key)?;
+            let smallest_required_index = self
+                .get_index_of_ok_result::<T>(key, max_index)
+                .unwrap_or(max_index);
+            let mut batch = Vec::new();
+            for i in (0..smallest_required_index).rev() {
+                let rev_key = rev_key(key, i);
+                if !self.0.contains_key(rev_key.clone())? {
+                    trace!("key does not exist: key: {}, index: {}", key, i);
+                    break;
+                }
+                trace!("removing: key: {}, index: {}", key, i);
+                batch.push((rev_key, None));
+            }
+            if batch.is_empty() {
+                return Ok(());
+            }
+            // the entries to remove were chosen as of `current_rev`
+            if self.0.apply_if(vec![(rev_index_key(key), current_rev.map(|x| x.to_vec()))], batch)? {
+                return Ok(());
             }
         }
-        Ok(())
     }
 
     // Function to insert if data doesn't exist for the given key
@@ -234,32 +365,41 @@ impl FallbackEntryStore {
         data: Result<T, EntryError>,
     ) -> anyhow::Result<bool>
     where
-        T: for<'a> Deserialize<'a> + Serialize + std::cmp::PartialEq,
+        T: for<'a> Deserialize<'a> + Serialize + Schema + std::cmp::PartialEq,
     {
-        // Check if data already exists for the given key
-        let existing_data: anyhow::Result<Entry<T>> = self.get::<T>(key, &RetrievalMethod::GetOk);
-
-        let mut insert = false;
-        if let Ok(my_data) = existing_data {
-            if !my_data.is_same_data(&data) {
+        let value = Entry::new(data);
+        let serialized = Envelope::seal(T::NAME, T::VERSION, bincode::serialize(&value)?)?;
+        loop {
+            let current_rev = self.current_rev(key)?;
+            // Check if data already exists for the given key
+            let existing_data: anyhow::Result<Entry<T>> = self.get::<T>(key, &RetrievalMethod::GetOk);
+
+            let mut insert = false;
+            if let Ok(my_data) = existing_data {
+                if !my_data.is_same_data(&value.data) {
+                    insert = true;
+                }
+            } else {
                 insert = true;
             }
-        } else {
-            insert = true;
-        }
-        if insert {
-            self.insert(key, data)?;
+            if !insert {
+                return Ok(false);
+            }
+            // only if nothing was inserted since the check
+            if self.try_insert::<T>(key, &serialized, current_rev)? {
+                debug!("push: key: {}", key);
+                return Ok(true);
+            }
         }
-        Ok(insert)
     }
 
     // increases revision and adds key/value pair to it.
     // uses `remove_historic_entries` to clean up the history.
     //
-    // called in async/parallel from multiple threads
+    // called in async/parallel from multiple threads, every writer gets a revision of its own.
     pub fn insert<T>(&self, key: &str, data: Result<T, EntryError>) -> anyhow::Result<()>
     where
-        T: for<'a> Deserialize<'a> + Serialize + std::cmp::PartialEq,
+        T: for<'a> Deserialize<'a> + Serialize + Schema + std::cmp::PartialEq,
     {
         let value = Entry::new(data);
         debug!("push: key: {}", key);
@@ -272,71 +412,250 @@ impl FallbackEntryStore {
             "push: value: {}",
             serde_json::to_string_pretty(&value).unwrap_or("Formatting Error".to_string())
         );
-        let current_rev: Option<IVec> = self
-            .0
-            .get(format!("{}{}", REV_INDEX_PREFIX, key).as_bytes().to_vec())?;
-        let next_index = match current_rev {
+        let value: Vec<u8> = value.try_into()?;
+        while !self.try_insert::<T>(key, &value, self.current_rev(key)?)? {
+            trace!("push: key: {}, revision taken by another writer, retrying", key);
+        }
+        Ok(())
+    }
+
+    // Writes `value` as the revision after `current_rev` and moves the revision index to it, both at once.
+    // false if the revision index is no longer `current_rev`.
+    fn try_insert<T>(&self, key: &str, value: &[u8], current_rev: Option<IVec>) -> anyhow::Result<bool>
+    where
+        T: for<'a> Deserialize<'a> + Serialize + Schema,
+    {
+        let next_index = match &current_rev {
             Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?).overflowing_add(1),
             None => (0u64, false),
         };
+        let mut batch = Vec::new();
         if next_index.1 {
             // in case of an overflow, the complete key history is wiped.
             trace!("push key: {}, overflow: {:?}", key, next_index);
             for i in (0..=u64::MAX).rev() {
-                if self
-                    .0
-                    .remove(
-                        format!("{}{}_rev_{}", KEY_PREFIX, key, i)
-                            .as_bytes()
-                            .to_vec(),
-                    )?
-                    .is_none()
-                {
+                let rev_key = rev_key(key, i);
+                if !self.0.contains_key(rev_key.clone())? {
                     break;
-                } else {
-                    trace!("removed: key: {}, index: {}", key, i);
                 }
+                trace!("removing: key: {}, index: {}", key, i);
+                batch.push((rev_key, None));
             }
         }
-        let tmp: Vec<u8> = value.try_into()?;
-        self.0.insert(
-            format!("{}{}_rev_{}", KEY_PREFIX, key, next_index.0)
-                .as_bytes()
-                .to_vec(),
-            tmp,
-        )?;
-        self.0.insert(
-            format!("{}{}", REV_INDEX_PREFIX, key).as_bytes().to_vec(),
-            next_index.0.to_be_bytes().to_vec(),
-        )?;
+        batch.push((rev_key(key, next_index.0), Some(value.to_vec())));
+        batch.push((rev_index_key(key), Some(next_index.0.to_be_bytes().to_vec())));
+        if !self.0.apply_if(vec![(rev_index_key(key), current_rev.map(|x| x.to_vec()))], batch)? {
+            return Ok(false);
+        }
 
         self.cleanup_revision_history::<T>(key, next_index.0)?;
-        Ok(())
+        Ok(true)
     }
 
     pub fn remove_all(&self, key: &str) -> anyhow::Result<u64> {
-        let current_rev: Option<IVec> = self
+        loop {
+            let current_rev = self.current_rev(key)?;
+            let max_index = match &current_rev {
+                Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?),
+                None => return Ok(0),
+            };
+            let mut batch = vec![(rev_index_key(key), None)];
+            batch.extend(self.rev_keys(key, max_index)?.into_iter().map(|rev_key| (rev_key, None)));
+            if self.0.apply_if(vec![(rev_index_key(key), current_rev.map(|x| x.to_vec()))], batch)? {
+                return Ok(max_index + 1);
+            }
+        }
+    }
+
+    // Removes every key that starts with `key_prefix` with all its revisions, only counts them if `dry_run` is set.
+    // Each key is removed on its own, like `remove_all`. Revisions of these keys that no revision index points to
+    // are removed as well, unless their key was written meanwhile.
+    pub fn purge(&self, key_prefix: &str, dry_run: bool) -> anyhow::Result<Purged> {
+        let mut purged = Purged::default();
+        let mut rev_keys = HashSet::new();
+        for key in self.key_iter(Some(key_prefix)).collect::<Vec<String>>() {
+            let max_index = match self.current_rev(&key)? {
+                Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?),
+                None => continue,
+            };
+            purged.keys += 1;
+            let key_rev_keys = self.rev_keys(&key, max_index)?;
+            purged.revisions += key_rev_keys.len() as u64;
+            rev_keys.extend(key_rev_keys);
+            if !dry_run {
+                self.remove_all(&key)?;
+            }
+        }
+        let scanned = self
             .0
-            .get(format!("{}{}", REV_INDEX_PREFIX, key).as_bytes().to_vec())?;
-        let max_index = match current_rev {
+            .scan_prefix(format!("{}{}", KEY_PREFIX, key_prefix).as_bytes())
+            .map(|item| Ok(item?.0))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        for rev_key in scanned {
+            // `key_<prefix>` also holds the revisions of shorter keys, e.g. of `gov` for the prefix `gov_`
+            let key = match store::key_of_revision(&rev_key) {
+                Some(key) if key.starts_with(key_prefix) => key,
+                _ => continue,
+            };
+            if dry_run {
+                if !rev_keys.contains(rev_key.as_bytes()) {
+                    purged.orphans += 1;
+                }
+            } else if self.0.apply_if(vec![(rev_index_key(key), None)], vec![(rev_key.clone().into_bytes(), None)])? {
+                purged.orphans += 1;
+            }
+        }
+        debug!("purge: prefix: {}, dry run: {}, {:?}", key_prefix, dry_run, purged);
+        Ok(purged)
+    }
+
+    // Removes the revisions of `key` that `retention` does not keep as of `now`, always the oldest ones,
+    // so the remaining revisions stay without gaps. A key written meanwhile is left as it is.
+    pub fn apply_retention(&self, key: &str, retention: &Retention, now: i64) -> anyhow::Result<Reclaimed> {
+        let current_rev = self.current_rev(key)?;
+        let max_index = match &current_rev {
             Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?),
-            None => return Ok(0),
+            None => return Ok(Reclaimed::default()),
         };
+        // latest first
+        let mut revisions = Vec::new();
+        for rev_key in self.rev_keys(key, max_index)? {
+            if let Some(val) = self.0.get(rev_key.clone())? {
+                revisions.push((rev_key, val));
+            }
+        }
 
-        for i in 0..=max_index {
-            self.0.remove(
-                format!("{}{}_rev_{}", KEY_PREFIX, key, i)
-                    .as_bytes()
-                    .to_vec(),
-            )?;
+        // the first `keep` revisions are kept
+        let mut keep = revisions.len();
+        if let Some(max_revisions) = retention.max_revisions {
+            keep = keep.min(max_revisions as usize);
+        }
+        if let Some(max_age_in_secs) = retention.max_age_in_secs {
+            let recent = revisions
+                .iter()
+                .take_while(|(_, val)| entry_timestamp(val).map(|timestamp| now - timestamp <= max_age_in_secs).unwrap_or(true))
+                .count();
+            keep = keep.min(recent);
+        }
+        if retention.keep_last_ok {
+            if let Some(last_ok) = revisions.iter().position(|(_, val)| is_ok_entry(val)) {
+                keep = keep.max(last_ok + 1);
+            }
+        }
+        if keep == revisions.len() {
+            return Ok(Reclaimed::default());
         }
-        self.0
-            .remove(format!("{}{}", REV_INDEX_PREFIX, key).as_bytes().to_vec())?;
 
-        Ok(max_index + 1)
+        let mut reclaimed = Reclaimed::default();
+        let mut batch = Vec::new();
+        for (rev_key, val) in revisions.into_iter().skip(keep) {
+            reclaimed.revisions += 1;
+            reclaimed.bytes += (rev_key.len() + val.len()) as u64;
+            batch.push((rev_key, None));
+        }
+        if keep == 0 {
+            reclaimed.keys += 1;
+            reclaimed.bytes += (rev_index_key(key).len() + 8) as u64;
+            batch.push((rev_index_key(key), None));
+        }
+        if !self.0.apply_if(vec![(rev_index_key(key), current_rev.map(|x| x.to_vec()))], batch)? {
+            return Ok(Reclaimed::default());
+        }
+        debug!("apply_retention: key: {}, {:?}", key, reclaimed);
+        Ok(reclaimed)
     }
 
-    pub fn watch_prefix(&self, prefix: &mut Vec<u8>) -> Subscriber {
+    // Upgrades every stored revision to the current version of its schema, only counts them if `dry_run` is set.
+    // Revisions without envelope get the schema of the longest key prefix in `schemas` they are stored under.
+    // A revision that changes meanwhile is left as it is.
+    pub fn migrate(&self, schemas: &[(String, &'static str)], dry_run: bool) -> anyhow::Result<Migrated> {
+        let mut migrated = Migrated::default();
+        for key in self.key_iter(None).collect::<Vec<String>>() {
+            let max_index = match self.current_rev(&key)? {
+                Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?),
+                None => continue,
+            };
+            for rev_key in self.rev_keys(&key, max_index)? {
+                let value = match self.0.get(rev_key.clone())? {
+                    Some(value) => value.to_vec(),
+                    None => continue,
+                };
+                let upgraded = if value.starts_with(&ENVELOPE_MAGIC) {
+                    Self::upgrade(&value)
+                } else if let Some(candidates) = schemas_of(&key, schemas) {
+                    Self::seal_unversioned(&value, &candidates).map(Some)
+                } else {
+                    migrated.unversioned += 1;
+                    continue;
+                };
+                match upgraded {
+                    Ok(None) => {}
+                    Ok(Some(upgraded)) => {
+                        if dry_run || self.0.apply_if(vec![(rev_key.clone(), Some(value))], vec![(rev_key, Some(upgraded))])? {
+                            migrated.upgraded += 1;
+                        }
+                    }
+                    Err(err) => {
+                        warn!("migrate: key: {}, {}", key, err);
+                        migrated.failed += 1;
+                    }
+                }
+            }
+        }
+        debug!("migrate: dry run: {}, {:?}", dry_run, migrated);
+        Ok(migrated)
+    }
+
+    // the value at the current version of its schema, none if it is at that version already
+    fn upgrade(value: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let envelope = Envelope::open(value)?;
+        let version = migration::current_version(&envelope.schema)
+            .ok_or_else(|| anyhow::anyhow!("unknown schema {}", envelope.schema))?;
+        if envelope.version == version {
+            return Ok(None);
+        }
+        let entry = migration::migrate_entry(&envelope.schema, envelope.version, version, envelope.entry)?;
+        Ok(Some(Envelope::seal(&envelope.schema, version, entry)?))
+    }
+
+    // An entry written before entries had an envelope, sealed as `UNVERSIONED` and upgraded like any other
+    // under the first of the candidate schemas it then decodes as.
+    fn seal_unversioned(value: &[u8], candidates: &[&str]) -> anyhow::Result<Vec<u8>> {
+        for schema in candidates {
+            let sealed = Envelope::seal(schema, UNVERSIONED, value.to_vec())?;
+            let upgraded = match Self::upgrade(&sealed) {
+                Ok(upgraded) => upgraded.unwrap_or(sealed),
+                Err(err) => {
+                    debug!("migrate: not a {} entry: {}", schema, err);
+                    continue;
+                }
+            };
+            if migration::decodes(schema, &Envelope::open(&upgraded)?.entry) {
+                return Ok(upgraded);
+            }
+        }
+        Err(anyhow::anyhow!("unversioned entry is none of {}", candidates.join(", ")))
+    }
+
+    // keys of the stored revisions up to `max_index`, older revisions were removed by `cleanup_revision_history`
+    fn rev_keys(&self, key: &str, max_index: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut rev_keys = Vec::new();
+        for i in (0..=max_index).rev() {
+            let rev_key = rev_key(key, i);
+            if !self.0.contains_key(rev_key.clone())? {
+                break;
+            }
+            rev_keys.push(rev_key);
+        }
+        Ok(rev_keys)
+    }
+
+    // the revision index as stored, writes expect it to be unchanged when they apply
+    fn current_rev(&self, key: &str) -> anyhow::Result<Option<IVec>> {
+        self.0.get(rev_index_key(key))
+    }
+
+    pub fn watch_prefix(&self, prefix: &mut Vec<u8>) -> StoreSubscriber {
         *prefix = vec![KEY_PREFIX.as_bytes(), prefix].concat();
         self.0.watch_prefix(prefix)
     }
@@ -363,7 +682,7 @@ impl FallbackEntryStore {
         retrieval_method: &'b RetrievalMethod,
     ) -> impl Iterator<Item = (String, Entry<T>)> + '_
     where
-        T: for<'a> Deserialize<'a> + Serialize,
+        T: for<'a> Deserialize<'a> + Serialize + Schema,
     {
         self.key_iter(key_prefix)
             .map(|key| match self.get::<T>(&key, retrieval_method) {
@@ -389,14 +708,306 @@ impl FallbackEntryStore {
         &'b self,
         key_prefix: Option<&'b str>,
     ) -> impl Iterator<Item = (String, EntryError)> + '_ {
-        self.key_iter(key_prefix).filter_map(|key| {
-            match self.get::<IgnoreEntry>(&key, &RetrievalMethod::Get) {
-                Ok(Entry {
-                    data: Err(error), ..
-                }) => Some((key, error)),
-                Err(err) => Some((key, EntryError::Error(err.to_string()))),
-                _ => None,
-            }
+        self.key_iter(key_prefix).filter_map(|key| match self.get_error(&key) {
+            Ok(error) => error.map(|error| (key, error)),
+            Err(err) => Some((key, EntryError::Error(err.to_string()))),
         })
     }
+
+    // the error of the latest revision, whatever the schema of the key
+    fn get_error(&self, key: &str) -> anyhow::Result<Option<EntryError>> {
+        let index = match self.current_rev(key)? {
+            Some(val) => u64::from_be_bytes(val.to_vec()[..].try_into()?),
+            None => 0u64,
+        };
+        let rev_key = rev_key(key, index);
+        let envelope = match self.0.get(rev_key.clone())? {
+            Some(val) => Envelope::open(&val)?,
+            None => {
+                return Ok(Some(EntryError::KeyDoesNotExist(
+                    String::from_utf8_lossy(&rev_key).into_owned(),
+                )))
+            }
+        };
+        if envelope.entry.starts_with(&[0, 0, 0, 0]) {
+            return Ok(None);
+        }
+        // an error has no value of `T` to read
+        match bincode::deserialize::<Entry<IgnoreEntry>>(&envelope.entry)?.data {
+            Err(error) => Ok(Some(error)),
+            Ok(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    const THREADS: u64 = 8;
+    const WRITES: u64 = 50;
+
+    impl Schema for u64 {
+        const NAME: &'static str = "u64";
+        const VERSION: u32 = 1;
+    }
+
+    fn in_memory_store() -> FallbackEntryStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        FallbackEntryStore::new(&StoreBackend::from(db), "test_")
+    }
+
+    // runs `write(thread, i)` WRITES times on every thread, all threads start at once
+    fn hammer<F>(store: &FallbackEntryStore, write: F)
+    where
+        F: Fn(&FallbackEntryStore, u64, u64) + Send + Sync + 'static,
+    {
+        let write = Arc::new(write);
+        let barrier = Arc::new(Barrier::new(THREADS as usize));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let store = store.clone();
+                let write = write.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0..WRITES {
+                        write(&store, thread, i);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    fn rev_index(store: &FallbackEntryStore, key: &str) -> Option<u64> {
+        store
+            .current_rev(key)
+            .unwrap()
+            .map(|val| u64::from_be_bytes(val.to_vec()[..].try_into().unwrap()))
+    }
+
+    fn revisions(store: &FallbackEntryStore, key: &str) -> Vec<String> {
+        let prefix = format!("{}{}_rev_", KEY_PREFIX, key);
+        store.0.scan_prefix(prefix.as_bytes()).map(|item| item.unwrap().0).collect()
+    }
+
+    #[test]
+    fn concurrent_inserts_get_a_revision_each() {
+        let store = in_memory_store();
+        hammer(&store, |store, thread, i| {
+            store.insert::<u64>("key", Ok(thread * WRITES + i)).unwrap();
+        });
+
+        // no two writers claimed the same revision
+        assert_eq!(rev_index(&store, "key"), Some(THREADS * WRITES - 1));
+        // every revision but the latest ok one was cleaned up, none was left behind
+        assert_eq!(revisions(&store, "key"), vec![format!("{}key_rev_{}", KEY_PREFIX, THREADS * WRITES - 1)]);
+        let latest = store.get::<u64>("key", &RetrievalMethod::Get).unwrap().data.unwrap();
+        assert!(latest < THREADS * WRITES);
+    }
+
+    #[test]
+    fn concurrent_failed_inserts_keep_the_last_ok_revision() {
+        let store = in_memory_store();
+        store.insert::<u64>("key", Ok(42)).unwrap();
+        hammer(&store, |store, thread, i| {
+            let error = EntryError::Error(format!("{} {}", thread, i));
+            store.insert::<u64>("key", Err(error)).unwrap();
+        });
+
+        assert_eq!(rev_index(&store, "key"), Some(THREADS * WRITES));
+        assert_eq!(store.get::<u64>("key", &RetrievalMethod::GetOk).unwrap().data.unwrap(), 42);
+        assert!(store.get::<u64>("key", &RetrievalMethod::Get).unwrap().data.is_err());
+        // revisions from the ok one up to the latest, without gaps
+        assert_eq!(revisions(&store, "key").len() as u64, THREADS * WRITES + 1);
+    }
+
+    #[test]
+    fn concurrent_insert_if_not_exists_inserts_once() {
+        let store = in_memory_store();
+        let inserted = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counter = inserted.clone();
+        hammer(&store, move |store, _, _| {
+            if store.insert_if_not_exists::<u64>("key", Ok(7)).unwrap() {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+
+        assert_eq!(inserted.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(rev_index(&store, "key"), Some(0));
+        assert_eq!(store.get::<u64>("key", &RetrievalMethod::Get).unwrap().data.unwrap(), 7);
+    }
+
+    #[test]
+    fn concurrent_inserts_and_removals_leave_no_orphans() {
+        let store = in_memory_store();
+        hammer(&store, |store, thread, i| {
+            if thread % 2 == 0 {
+                store.remove_all("key").unwrap();
+            } else {
+                store.insert::<u64>("key", Ok(i)).unwrap();
+            }
+        });
+
+        match rev_index(&store, "key") {
+            // the latest revision is the one the index points to
+            Some(index) => assert_eq!(revisions(&store, "key"), vec![format!("{}key_rev_{}", KEY_PREFIX, index)]),
+            None => assert!(revisions(&store, "key").is_empty()),
+        }
+        store.remove_all("key").unwrap();
+        assert_eq!(rev_index(&store, "key"), None);
+        assert!(revisions(&store, "key").is_empty());
+    }
+
+    #[test]
+    fn purge_removes_only_keys_with_the_prefix() {
+        let store = in_memory_store();
+        store.insert::<u64>("gov_tally_result_a", Ok(1)).unwrap();
+        store.insert::<u64>("gov_tally_result_a", Err(EntryError::Error("down".to_string()))).unwrap();
+        store.insert::<u64>("gov_tally_result_b", Ok(2)).unwrap();
+        store.insert::<u64>("gov_proposal_a", Ok(3)).unwrap();
+
+        let expected = Purged { keys: 2, revisions: 3, orphans: 0 };
+        assert_eq!(store.purge("gov_tally_result_", true).unwrap(), expected);
+        // a dry run leaves everything in place
+        assert_eq!(revisions(&store, "gov_tally_result_a").len(), 2);
+        assert_eq!(store.purge("gov_tally_result_", false).unwrap(), expected);
+
+        assert_eq!(rev_index(&store, "gov_tally_result_a"), None);
+        assert!(revisions(&store, "gov_tally_result_a").is_empty());
+        assert!(revisions(&store, "gov_tally_result_b").is_empty());
+        assert_eq!(store.get::<u64>("gov_proposal_a", &RetrievalMethod::Get).unwrap().data.unwrap(), 3);
+        assert_eq!(store.purge("gov_tally_result_", false).unwrap(), Purged::default());
+    }
+
+    #[test]
+    fn purge_removes_orphaned_revisions() {
+        let store = in_memory_store();
+        store.insert::<u64>("gov_a", Ok(1)).unwrap();
+        store.insert::<u64>("gov", Ok(2)).unwrap();
+        // a revision without an index and one above the index of `gov_a`
+        store.0.insert(rev_key("gov_b", 0), bincode::serialize(&Entry::new(Ok(3u64))).unwrap()).unwrap();
+        store.0.insert(rev_key("gov_a", 5), bincode::serialize(&Entry::new(Ok(4u64))).unwrap()).unwrap();
+
+        let expected = Purged { keys: 1, revisions: 1, orphans: 2 };
+        assert_eq!(store.purge("gov_", true).unwrap(), expected);
+        assert_eq!(revisions(&store, "gov_b").len(), 1);
+        assert_eq!(store.purge("gov_", false).unwrap(), expected);
+
+        assert!(revisions(&store, "gov_a").is_empty());
+        assert!(revisions(&store, "gov_b").is_empty());
+        assert_eq!(store.get::<u64>("gov", &RetrievalMethod::Get).unwrap().data.unwrap(), 2);
+        assert_eq!(store.purge("gov_", false).unwrap(), Purged::default());
+    }
+
+    fn insert_at(store: &FallbackEntryStore, key: &str, data: Result<u64, EntryError>, timestamp: i64) {
+        let index = rev_index(store, key).map(|index| index + 1).unwrap_or(0);
+        store.0.insert(rev_key(key, index), bincode::serialize(&Entry { data, timestamp }).unwrap()).unwrap();
+        store.0.insert(rev_index_key(key), index.to_be_bytes().to_vec()).unwrap();
+    }
+
+    fn error() -> Result<u64, EntryError> {
+        Err(EntryError::Error("down".to_string()))
+    }
+
+    #[test]
+    fn retention_removes_the_oldest_revisions() {
+        let store = in_memory_store();
+        insert_at(&store, "key", Ok(1), 100);
+        insert_at(&store, "key", error(), 200);
+        insert_at(&store, "key", error(), 300);
+        insert_at(&store, "key", error(), 400);
+
+        let retention = Retention { max_revisions: Some(3), ..Retention::default() };
+        assert_eq!(store.apply_retention("key", &retention, 1000).unwrap().revisions, 1);
+        assert_eq!(revisions(&store, "key").len(), 3);
+
+        let retention = Retention { max_age_in_secs: Some(750), ..Retention::default() };
+        assert_eq!(store.apply_retention("key", &retention, 1000).unwrap().revisions, 1);
+        assert_eq!(revisions(&store, "key").len(), 2);
+        assert_eq!(rev_index(&store, "key"), Some(3));
+        assert!(store.get::<u64>("key", &RetrievalMethod::Get).unwrap().data.is_err());
+    }
+
+    #[test]
+    fn retention_keeps_the_last_ok_revision() {
+        let store = in_memory_store();
+        insert_at(&store, "key", Ok(1), 100);
+        insert_at(&store, "key", error(), 200);
+
+        let retention = Retention { max_age_in_secs: Some(10), keep_last_ok: true, ..Retention::default() };
+        assert_eq!(store.apply_retention("key", &retention, 1000).unwrap(), Reclaimed::default());
+        assert_eq!(store.get::<u64>("key", &RetrievalMethod::GetOk).unwrap().data.unwrap(), 1);
+    }
+
+    #[test]
+    fn retention_removes_keys_without_revisions_left() {
+        let store = in_memory_store();
+        insert_at(&store, "key", Ok(1), 100);
+        insert_at(&store, "key", Ok(2), 200);
+
+        let retention = Retention { max_age_in_secs: Some(10), ..Retention::default() };
+        let reclaimed = store.apply_retention("key", &retention, 1000).unwrap();
+        assert_eq!((reclaimed.keys, reclaimed.revisions), (1, 2));
+        assert_eq!(rev_index(&store, "key"), None);
+        assert!(revisions(&store, "key").is_empty());
+    }
+
+    #[test]
+    fn entries_are_stored_in_an_envelope() {
+        let store = in_memory_store();
+        store.insert::<u64>("key", Ok(7)).unwrap();
+
+        let value = store.0.get(rev_key("key", 0)).unwrap().unwrap();
+        assert!(value.starts_with(&ENVELOPE_MAGIC));
+        assert_eq!(store.get::<u64>("key", &RetrievalMethod::Get).unwrap().data.unwrap(), 7);
+        // another schema is not silently read as this one
+        assert!(store.get::<Option<u64>>("key", &RetrievalMethod::Get).is_err());
+    }
+
+    #[test]
+    fn migrate_counts_unversioned_and_unknown_entries() {
+        let store = in_memory_store();
+        insert_at(&store, "legacy", Ok(1), 100);
+        store.insert::<Option<u64>>("current", Ok(Some(2))).unwrap();
+        // `u64` is only a schema of these tests
+        store.insert::<u64>("unknown", Ok(3)).unwrap();
+
+        let migrated = store.migrate(&[], false).unwrap();
+        assert_eq!(migrated, Migrated { upgraded: 0, unversioned: 1, failed: 1 });
+        assert_eq!(store.get::<u64>("legacy", &RetrievalMethod::Get).unwrap().data.unwrap(), 1);
+    }
+
+    #[test]
+    fn migrate_seals_unversioned_entries_with_the_schema_of_their_key() {
+        let store = in_memory_store();
+        let report = Reclaimed { keys: 1, revisions: 2, bytes: 3 };
+        // written before entries had an envelope
+        let legacy = bincode::serialize(&Entry { data: Ok(report), timestamp: 100 }).unwrap();
+        store.0.insert(rev_key("janitor_report", 0), legacy).unwrap();
+        store.0.insert(rev_index_key("janitor_report"), 0u64.to_be_bytes().to_vec()).unwrap();
+        // under the same prefix, but not a `Reclaimed`
+        insert_at(&store, "janitor_report_old", Ok(1), 100);
+        let schemas = [("janitor_report".to_string(), Reclaimed::NAME)];
+
+        let migrated = Migrated { upgraded: 1, unversioned: 0, failed: 1 };
+        assert_eq!(store.migrate(&schemas, true).unwrap(), migrated);
+        assert!(!store.0.get(rev_key("janitor_report", 0)).unwrap().unwrap().starts_with(&ENVELOPE_MAGIC));
+        assert_eq!(store.migrate(&schemas, false).unwrap(), migrated);
+
+        let value = store.0.get(rev_key("janitor_report", 0)).unwrap().unwrap();
+        let envelope = Envelope::open(&value).unwrap();
+        assert_eq!((envelope.schema.as_str(), envelope.version), (Reclaimed::NAME, Reclaimed::VERSION));
+        let entry = store.get::<Reclaimed>("janitor_report", &RetrievalMethod::Get).unwrap();
+        assert_eq!((entry.data.unwrap(), entry.timestamp), (report, 100));
+        // sealed already
+        let migrated = Migrated { upgraded: 0, unversioned: 0, failed: 1 };
+        assert_eq!(store.migrate(&schemas, false).unwrap(), migrated);
+    }
 }
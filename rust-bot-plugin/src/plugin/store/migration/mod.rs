@@ -0,0 +1,168 @@
+use cosmos_rust_package::api::core::cosmos::channels::SupportedBlockchainType;
+use cosmos_rust_package::api::custom::types::gov::proposal_ext::ProposalExt;
+use cosmos_rust_package::api::custom::types::{
+    NextKeyType, ParamsType, PoolType, TallyResultType, ValidatorsType,
+};
+
+use crate::plugin::interface::agent::fraud_detection::GovernanceProposalFraudClassificationType;
+use crate::plugin::interface::agent::governance::proposals::api::GovernanceProposalView;
+use crate::plugin::store::fallback_entry_store::{Entry, Reclaimed};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+
+// A type stored in `FallbackEntryStore`, its entries carry `NAME` and `VERSION` in their envelope.
+// Bump `VERSION` whenever the bincode encoding of the type changes, e.g. a new field of `ProposalExt`
+// in cosmos_rust_package, and add the migration from the previous version to `MIGRATIONS`.
+pub trait Schema {
+    const NAME: &'static str;
+    const VERSION: u32;
+}
+
+// the version of entries written before entries had an envelope
+pub const UNVERSIONED: u32 = 1;
+
+// Upgrades the bincode encoded value of a schema from one version to the next.
+pub type Migration = fn(&[u8]) -> anyhow::Result<Vec<u8>>;
+
+// By schema name and the version they upgrade from, e.g.
+// `(ProposalExt::NAME, 1, proposal_ext_v2)` once `ProposalExt` is at version 2.
+const MIGRATIONS: &[(&str, u32, Migration)] = &[];
+
+macro_rules! schemas {
+    ($($type:ty => ($name:literal, $version:literal)),* $(,)?) => {
+        $(
+            impl Schema for $type {
+                const NAME: &'static str = $name;
+                const VERSION: u32 = $version;
+            }
+        )*
+
+        // the current version of a schema by its name, for entries that are read without knowing their type
+        pub fn current_version(name: &str) -> Option<u32> {
+            match name {
+                $($name => Some($version),)*
+                _ => None,
+            }
+        }
+
+        // whether a bincode encoded `Entry<T>` at the current version of a schema decodes as its type, to tell
+        // which schema an entry without envelope has when several are stored under the same key prefix
+        pub fn decodes(name: &str, entry: &[u8]) -> bool {
+            match name {
+                $($name => decodes_as::<$type>(entry),)*
+                _ => false,
+            }
+        }
+    };
+}
+
+schemas! {
+    SupportedBlockchainType => ("SupportedBlockchainType", 1),
+    ProposalExt => ("ProposalExt", 1),
+    ParamsType => ("ParamsType", 1),
+    PoolType => ("PoolType", 1),
+    TallyResultType => ("TallyResultType", 1),
+    ValidatorsType => ("ValidatorsType", 1),
+    GovernanceProposalView => ("GovernanceProposalView", 1),
+    GovernanceProposalFraudClassificationType => ("GovernanceProposalFraudClassification", 1),
+    NextKeyType => ("NextKeyType", 1),
+    // `ContinueAtIndexType`
+    Option<u64> => ("ContinueAtIndexType", 1),
+    Reclaimed => ("Reclaimed", 1),
+}
+
+// strict, unlike `bincode::deserialize` nothing may be left over
+fn decodes_as<T: DeserializeOwned>(entry: &[u8]) -> bool {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize::<Entry<T>>(entry)
+        .is_ok()
+}
+
+// Upgrades a bincode encoded `Entry<T>` of `schema` from version `from` to `to`.
+// Only the value of an `Ok` entry depends on the schema, other entries stay as they are.
+pub fn migrate_entry(schema: &str, from: u32, to: u32, entry: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    migrate_with(MIGRATIONS, schema, from, to, entry)
+}
+
+fn migrate_with(
+    migrations: &[(&str, u32, Migration)],
+    schema: &str,
+    from: u32,
+    to: u32,
+    entry: Vec<u8>,
+) -> anyhow::Result<Vec<u8>> {
+    if from > to {
+        return Err(anyhow::anyhow!(
+            "{} entry is at version {}, newer than version {} of this plugin",
+            schema,
+            from,
+            to
+        ));
+    }
+    if from == to || !entry.starts_with(&[0, 0, 0, 0]) {
+        return Ok(entry);
+    }
+    // the `Result` variant, the value and the timestamp (i64)
+    if entry.len() < 12 {
+        return Err(anyhow::anyhow!("{} entry of {} bytes is truncated", schema, entry.len()));
+    }
+    let (variant, rest) = entry.split_at(4);
+    let (value, timestamp) = rest.split_at(rest.len() - 8);
+    let mut value = value.to_vec();
+    for version in from..to {
+        let (_, _, migration) = migrations
+            .iter()
+            .find(|(name, from, _)| *name == schema && *from == version)
+            .ok_or_else(|| anyhow::anyhow!("no migration of {} from version {} to {}", schema, version, version + 1))?;
+        value = migration(&value)
+            .map_err(|err| anyhow::anyhow!("migration of {} from version {} failed: {}", schema, version, err))?;
+    }
+    Ok([variant, &value, timestamp].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::plugin::store::fallback_entry_store::EntryError;
+
+    // version 1 stored a u32, version 2 a u64, version 3 a (u64, bool)
+    fn widen(value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let value: u32 = bincode::deserialize(value)?;
+        Ok(bincode::serialize(&(value as u64))?)
+    }
+
+    fn add_flag(value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let value: u64 = bincode::deserialize(value)?;
+        Ok(bincode::serialize(&(value, true))?)
+    }
+
+    const TEST_MIGRATIONS: &[(&str, u32, Migration)] = &[("Test", 1, widen), ("Test", 2, add_flag)];
+
+    fn entry<T: serde::Serialize>(data: Result<T, EntryError>) -> Vec<u8> {
+        bincode::serialize(&Entry { data, timestamp: 42 }).unwrap()
+    }
+
+    #[test]
+    fn migrations_are_chained_up_to_the_current_version() {
+        let migrated = migrate_with(TEST_MIGRATIONS, "Test", 1, 3, entry::<u32>(Ok(7))).unwrap();
+        let migrated: Entry<(u64, bool)> = bincode::deserialize(&migrated).unwrap();
+        assert_eq!(migrated.data.unwrap(), (7, true));
+        assert_eq!(migrated.timestamp, 42);
+    }
+
+    #[test]
+    fn errors_are_not_migrated() {
+        let error = entry::<u32>(Err(EntryError::Error("down".to_string())));
+        assert_eq!(migrate_with(TEST_MIGRATIONS, "Test", 1, 3, error.clone()).unwrap(), error);
+    }
+
+    #[test]
+    fn missing_and_newer_versions_are_rejected() {
+        assert!(migrate_with(TEST_MIGRATIONS, "Test", 1, 4, entry::<u32>(Ok(7))).is_err());
+        assert!(migrate_with(TEST_MIGRATIONS, "Test", 3, 2, entry::<u64>(Ok(7))).is_err());
+        assert!(migrate_with(TEST_MIGRATIONS, "Other", 1, 2, entry::<u32>(Ok(7))).is_err());
+    }
+}
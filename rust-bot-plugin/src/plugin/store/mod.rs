@@ -1,2 +1,7 @@
+pub mod circuit_breaker_store;
 pub mod fallback_entry_store;
+pub mod migration;
+pub mod rate_limit_store;
+pub mod remote_store;
+pub mod schedule_store;
 pub mod sled_store;
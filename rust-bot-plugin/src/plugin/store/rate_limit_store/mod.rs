@@ -0,0 +1,61 @@
+use super::sled_store::SledStore;
+
+use serde::{Deserialize, Serialize};
+
+use crate::TEMPORARY_SLED;
+
+static GLOBAL_PREFIX_RATE_LIMIT_STORE: &str = "rate_limit_store_";
+
+// Rate limit an endpoint of a blockchain announced with its last rejection, written to TEMPORARY_SLED
+// so the agents of every plugin keep to it, see `interface::rate_limit`. The next free request slot is kept next to it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EndpointRateLimit {
+    // at most `limit` requests per `window_in_secs`
+    pub limit: u64,
+    pub window_in_secs: u64,
+    // unix time in ms until which no request is sent
+    pub blocked_until: i64,
+}
+
+fn rate_limit_store() -> anyhow::Result<SledStore> {
+    match TEMPORARY_SLED.lock().unwrap().as_ref() {
+        Some(backend) => Ok(SledStore::new(backend, GLOBAL_PREFIX_RATE_LIMIT_STORE)),
+        None => Err(anyhow::anyhow!("Plugin not yet initialized")),
+    }
+}
+
+fn key(blockchain_name: &str, endpoint: &str) -> String {
+    format!("{}::{}", blockchain_name, endpoint)
+}
+
+pub fn save(blockchain_name: &str, endpoint: &str, rate_limit: &EndpointRateLimit) -> anyhow::Result<()> {
+    rate_limit_store()?.insert(key(blockchain_name, endpoint).into_bytes(), bincode::serialize(rate_limit)?)
+}
+
+pub fn load(blockchain_name: &str, endpoint: &str) -> anyhow::Result<Option<EndpointRateLimit>> {
+    match rate_limit_store()?.get(key(blockchain_name, endpoint).into_bytes())? {
+        Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+        None => Ok(None),
+    }
+}
+
+// Takes the next free request slot of the endpoint (unix time in ms), shared by the agents of every plugin:
+// the recorded slot, but not before `earliest`. The slot after it is `spacing_in_ms` later.
+pub fn reserve_slot(blockchain_name: &str, endpoint: &str, earliest: i64, spacing_in_ms: i64) -> anyhow::Result<i64> {
+    let store = rate_limit_store()?;
+    let key = format!("{}::next_request", key(blockchain_name, endpoint)).into_bytes();
+    loop {
+        let current = store.get(key.clone())?;
+        let next_request = match &current {
+            Some(value) => i64::from_be_bytes(value.as_ref().try_into()?),
+            None => earliest,
+        };
+        let slot = next_request.max(earliest);
+        let expected = vec![(key.clone(), current.map(|value| value.to_vec()))];
+        let batch = vec![(key.clone(), Some((slot + spacing_in_ms).to_be_bytes().to_vec()))];
+        // another agent took a slot in between
+        if store.apply_if(expected, batch)? {
+            return Ok(slot);
+        }
+    }
+}
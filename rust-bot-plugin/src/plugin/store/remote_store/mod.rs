@@ -0,0 +1,126 @@
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use rust_bot_common::ipc::{read_frame, write_frame, Channel, DbKind, StoreRequest, StoreResponse, WatchEvent};
+use sled::{Event, IVec};
+
+// Client side of the store proxy, used when the plugin runs in a worker process.
+// Only the host opens the sled databases, every operation is a request over the host's unix socket.
+#[derive(Clone)]
+pub struct RemoteStore {
+    socket_path: String,
+    db: DbKind,
+    connection: Arc<Mutex<UnixStream>>,
+}
+
+impl RemoteStore {
+    pub fn connect(socket_path: &str, db: DbKind) -> anyhow::Result<Self> {
+        let mut stream = UnixStream::connect(socket_path)?;
+        write_frame(&mut stream, &Channel::Store)?;
+        Ok(RemoteStore {
+            socket_path: socket_path.to_string(),
+            db,
+            connection: Arc::new(Mutex::new(stream)),
+        })
+    }
+
+    fn request(&self, request: StoreRequest) -> sled::Result<StoreResponse> {
+        let mut stream = self.connection.lock().unwrap();
+        write_frame(&mut stream, &request)?;
+        match read_frame(&mut stream)? {
+            StoreResponse::Error(err) => Err(sled::Error::Unsupported(err)),
+            response => Ok(response),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> sled::Result<Option<IVec>> {
+        match self.request(StoreRequest::Get { db: self.db, key: key.to_vec() })? {
+            StoreResponse::Value(value) => Ok(value.map(IVec::from)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn insert(&self, key: &[u8], value: IVec) -> sled::Result<Option<IVec>> {
+        match self.request(StoreRequest::Insert { db: self.db, key: key.to_vec(), value: value.to_vec() })? {
+            StoreResponse::Value(value) => Ok(value.map(IVec::from)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) -> sled::Result<Option<IVec>> {
+        match self.request(StoreRequest::Remove { db: self.db, key: key.to_vec() })? {
+            StoreResponse::Value(value) => Ok(value.map(IVec::from)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> sled::Result<bool> {
+        match self.request(StoreRequest::ContainsKey { db: self.db, key: key.to_vec() })? {
+            StoreResponse::Bool(value) => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn apply_if(
+        &self,
+        expected: &[(Vec<u8>, Option<Vec<u8>>)],
+        batch: &[(Vec<u8>, Option<Vec<u8>>)],
+    ) -> sled::Result<bool> {
+        let request = StoreRequest::ApplyIf { db: self.db, expected: expected.to_vec(), batch: batch.to_vec() };
+        match self.request(request)? {
+            StoreResponse::Bool(value) => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> sled::Result<Vec<(IVec, IVec)>> {
+        match self.request(StoreRequest::ScanPrefix { db: self.db, prefix: prefix.to_vec() })? {
+            StoreResponse::Entries(entries) => Ok(entries
+                .into_iter()
+                .map(|(key, value)| (IVec::from(key), IVec::from(value)))
+                .collect()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    // Opens a dedicated connection on which the host forwards every matching event.
+    pub fn watch_prefix(&self, prefix: &[u8]) -> RemoteSubscriber {
+        let stream = UnixStream::connect(&self.socket_path).and_then(|mut stream| {
+            write_frame(&mut stream, &Channel::Watch { db: self.db, prefix: prefix.to_vec() })?;
+            Ok(stream)
+        });
+        if let Err(ref err) = stream {
+            error!("Failed to watch prefix on host: {}", err);
+        }
+        RemoteSubscriber { stream: stream.ok() }
+    }
+}
+
+fn unexpected(response: StoreResponse) -> sled::Error {
+    sled::Error::Unsupported(format!("unexpected store response: {:?}", response))
+}
+
+pub struct RemoteSubscriber {
+    stream: Option<UnixStream>,
+}
+
+impl Iterator for RemoteSubscriber {
+    type Item = Event;
+
+    // Blocks until the next event, ends once the connection to the host is gone.
+    fn next(&mut self) -> Option<Event> {
+        let stream = self.stream.as_mut()?;
+        match read_frame::<WatchEvent>(stream) {
+            Ok(WatchEvent::Insert { key, value }) => Some(Event::Insert {
+                key: IVec::from(key),
+                value: IVec::from(value),
+            }),
+            Ok(WatchEvent::Remove { key }) => Some(Event::Remove { key: IVec::from(key) }),
+            Err(_) => {
+                self.stream = None;
+                None
+            }
+        }
+    }
+}
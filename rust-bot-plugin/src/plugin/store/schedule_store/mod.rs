@@ -0,0 +1,68 @@
+use super::sled_store::SledStore;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::TEMPORARY_SLED;
+
+static GLOBAL_PREFIX_SCHEDULE_STORE: &str = "schedule_store_";
+
+// Schedule of a task as of its last run, written to TEMPORARY_SLED so `AgentManager`
+// can pick up where it left off after a restart instead of running every task at once.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskSchedule {
+    pub resolved: bool,
+    // unix time the last run finished
+    pub last_run: i64,
+    // unix time of the next run
+    pub next_due: i64,
+    pub retry_delay_in_secs: i64,
+    // failed runs since the task last resolved
+    pub attempts: u32,
+}
+
+fn schedule_store() -> anyhow::Result<SledStore> {
+    match TEMPORARY_SLED.lock().unwrap().as_ref() {
+        Some(backend) => Ok(SledStore::new(backend, GLOBAL_PREFIX_SCHEDULE_STORE)),
+        None => Err(anyhow::anyhow!("Plugin not yet initialized")),
+    }
+}
+
+// tasks are named by their `Debug` output
+fn key(agent: &str, task: &str) -> String {
+    format!("{}::{}", agent, task)
+}
+
+pub fn save(agent: &str, task: &str, schedule: &TaskSchedule) -> anyhow::Result<()> {
+    schedule_store()?.insert(key(agent, task).into_bytes(), bincode::serialize(schedule)?)
+}
+
+// Schedules of all tasks of the agent, keyed by task, unreadable ones are skipped.
+pub fn load(agent: &str) -> HashMap<String, TaskSchedule> {
+    let store = match schedule_store() {
+        Ok(store) => store,
+        Err(err) => {
+            error!("failed to load the schedule of {}: {}", agent, err);
+            return HashMap::new();
+        }
+    };
+    let prefix = key(agent, "");
+    store
+        .scan_prefix(prefix.as_bytes())
+        .filter_map(|item| match item {
+            Ok((key, value)) => match bincode::deserialize::<TaskSchedule>(&value) {
+                Ok(schedule) => Some((key[prefix.len()..].to_string(), schedule)),
+                Err(err) => {
+                    error!("invalid schedule for {}: {}", key, err);
+                    None
+                }
+            },
+            Err(err) => {
+                error!("failed to load the schedule of {}: {}", agent, err);
+                None
+            }
+        })
+        .collect()
+}
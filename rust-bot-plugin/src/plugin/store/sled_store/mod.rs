@@ -1,8 +1,117 @@
 use log::{error, trace};
-use sled::{IVec, Subscriber};
+use rust_bot_common::store::apply_if;
+use sled::{Event, IVec, Subscriber};
+
+use super::remote_store::{RemoteStore, RemoteSubscriber};
+
+// The sled database a store operates on.
+// `Remote` is used when the plugin runs in a worker process and the host owns the database.
+#[derive(Clone)]
+pub enum StoreBackend {
+    Local(sled::Db),
+    Remote(RemoteStore),
+}
+
+impl From<sled::Db> for StoreBackend {
+    fn from(db: sled::Db) -> Self {
+        StoreBackend::Local(db)
+    }
+}
+
+impl StoreBackend {
+    pub fn contains_key(&self, key: &[u8]) -> sled::Result<bool> {
+        match self {
+            StoreBackend::Local(db) => db.contains_key(key),
+            StoreBackend::Remote(remote) => remote.contains_key(key),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> sled::Result<Option<IVec>> {
+        match self {
+            StoreBackend::Local(db) => db.get(key),
+            StoreBackend::Remote(remote) => remote.get(key),
+        }
+    }
+
+    pub fn insert<V>(&self, key: &[u8], value: V) -> sled::Result<Option<IVec>>
+    where
+        IVec: From<V>,
+    {
+        match self {
+            StoreBackend::Local(db) => db.insert(key, value),
+            StoreBackend::Remote(remote) => remote.insert(key, IVec::from(value)),
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) -> sled::Result<Option<IVec>> {
+        match self {
+            StoreBackend::Local(db) => db.remove(key),
+            StoreBackend::Remote(remote) => remote.remove(key),
+        }
+    }
+
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+    ) -> Box<dyn Iterator<Item = sled::Result<(IVec, IVec)>> + Send> {
+        match self {
+            StoreBackend::Local(db) => Box::new(db.scan_prefix(prefix)),
+            StoreBackend::Remote(remote) => match remote.scan_prefix(prefix) {
+                Ok(entries) => Box::new(entries.into_iter().map(Ok)),
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            },
+        }
+    }
+
+    // Writes `batch` (`None` removes the key) at once, if every key in `expected` still has the given value.
+    // false if another writer changed one of them since it was read.
+    pub fn apply_if(
+        &self,
+        expected: &[(Vec<u8>, Option<Vec<u8>>)],
+        batch: &[(Vec<u8>, Option<Vec<u8>>)],
+    ) -> sled::Result<bool> {
+        match self {
+            StoreBackend::Local(db) => apply_if(db, expected, batch),
+            StoreBackend::Remote(remote) => remote.apply_if(expected, batch),
+        }
+    }
+
+    pub fn watch_prefix(&self, prefix: &[u8]) -> StoreSubscriber {
+        match self {
+            StoreBackend::Local(db) => StoreSubscriber::Local(db.watch_prefix(prefix)),
+            StoreBackend::Remote(remote) => StoreSubscriber::Remote(remote.watch_prefix(prefix)),
+        }
+    }
+}
+
+pub enum StoreSubscriber {
+    Local(Subscriber),
+    Remote(RemoteSubscriber),
+}
+
+impl Iterator for StoreSubscriber {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        match self {
+            StoreSubscriber::Local(subscriber) => subscriber.next(),
+            StoreSubscriber::Remote(subscriber) => subscriber.next(),
+        }
+    }
+}
+
+impl StoreSubscriber {
+    // Waits for the next event without holding up the other tasks of the runtime.
+    pub async fn next_event(&mut self) -> Option<Event> {
+        match self {
+            StoreSubscriber::Local(subscriber) => subscriber.await,
+            StoreSubscriber::Remote(subscriber) => tokio::task::block_in_place(|| subscriber.next()),
+        }
+    }
+}
 
 pub struct SledStore {
-    db: sled::Db,
+    db: StoreBackend,
     global_prefix: String,
 }
 
@@ -13,9 +122,9 @@ impl Clone for SledStore {
 }
 
 impl SledStore {
-    pub fn new(sled_db: &sled::Db, global_prefix: &str) -> Self {
+    pub fn new(backend: &StoreBackend, global_prefix: &str) -> Self {
         SledStore {
-            db: sled_db.clone(),
+            db: backend.clone(),
             global_prefix: global_prefix.to_string(),
         }
     }
@@ -98,6 +207,24 @@ impl SledStore {
         Ok(self.db.remove(global_key.as_bytes())?)
     }
 
+    // `StoreBackend::apply_if` with the global prefix added to every key
+    pub fn apply_if(
+        &self,
+        expected: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) -> anyhow::Result<bool> {
+        let with_global_prefix = |items: Vec<(Vec<u8>, Option<Vec<u8>>)>| {
+            items
+                .into_iter()
+                .map(|(key, value)| Ok((self.add_global_prefix(key)?.into_bytes(), value)))
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let expected = with_global_prefix(expected)?;
+        let batch = with_global_prefix(batch)?;
+        trace!("apply_if: {} expected, {} in batch", expected.len(), batch.len());
+        Ok(self.db.apply_if(&expected, &batch)?)
+    }
+
     // Helper function to remove the global prefix from a key
     fn remove_global_prefix(&self, key: &str) -> Option<String> {
         if key.starts_with(&self.global_prefix) {
@@ -107,7 +234,7 @@ impl SledStore {
         }
     }
 
-    pub fn watch_prefix(&self, prefix: &mut Vec<u8>) -> Subscriber {
+    pub fn watch_prefix(&self, prefix: &mut Vec<u8>) -> StoreSubscriber {
         *prefix = vec![self.global_prefix.as_bytes(), prefix].concat();
         self.db.watch_prefix(&prefix[..])
     }
@@ -0,0 +1,65 @@
+use libloading::{Library, Symbol};
+
+use rust_bot_common::abi::{read_c_str, PluginDeclaration, PluginVTable, PLUGIN_ABI_VERSION};
+use rust_bot_common::manifest::PluginManifest;
+
+const RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+const SLED_VERSION: &str = env!("SLED_VERSION");
+
+type PluginAbiVersionFn = extern "C" fn() -> u32;
+type PluginDeclarationFn = extern "C" fn() -> *const PluginDeclaration;
+
+// Checks the exported `plugin_abi_version` first, only a matching plugin gets its declaration read.
+// The vtable is handed out only if the plugin was built with the same compiler and sled version,
+// because `init` receives `sled::Db` references that are not FFI-safe.
+// build.rs fails rather than embed an unknown version, which would match any other unknown one.
+pub unsafe fn load_declaration(library: &Library) -> anyhow::Result<(PluginVTable, PluginManifest)> {
+    let abi_version_fn: Symbol<PluginAbiVersionFn> = library
+        .get(b"plugin_abi_version\0")
+        .map_err(|_| anyhow::anyhow!("missing `plugin_abi_version` symbol, not a rust-bot plugin or built against an outdated interface"))?;
+    let abi_version = abi_version_fn();
+    if abi_version != PLUGIN_ABI_VERSION {
+        return Err(anyhow::anyhow!(
+            "plugin ABI version {} does not match host ABI version {}",
+            abi_version,
+            PLUGIN_ABI_VERSION
+        ));
+    }
+
+    let declaration_fn: Symbol<PluginDeclarationFn> = library
+        .get(b"plugin_declaration\0")
+        .map_err(|_| anyhow::anyhow!("missing `plugin_declaration` symbol"))?;
+    let declaration = declaration_fn()
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("`plugin_declaration` returned a null pointer"))?;
+
+    if declaration.abi_version != PLUGIN_ABI_VERSION {
+        return Err(anyhow::anyhow!(
+            "plugin declaration ABI version {} does not match host ABI version {}",
+            declaration.abi_version,
+            PLUGIN_ABI_VERSION
+        ));
+    }
+    let rustc_version = read_c_str(declaration.rustc_version);
+    if rustc_version != RUSTC_VERSION {
+        return Err(anyhow::anyhow!(
+            "plugin was built with `{}`, host was built with `{}`",
+            rustc_version,
+            RUSTC_VERSION
+        ));
+    }
+    let sled_version = read_c_str(declaration.sled_version);
+    if sled_version != SLED_VERSION {
+        return Err(anyhow::anyhow!(
+            "plugin uses sled {}, host uses sled {}",
+            sled_version,
+            SLED_VERSION
+        ));
+    }
+    let manifest = (declaration.manifest)();
+    if manifest.is_null() {
+        return Err(anyhow::anyhow!("plugin did not provide a manifest"));
+    }
+    let manifest = PluginManifest::from_json(&read_c_str(manifest))?;
+    Ok((declaration.vtable, manifest))
+}
@@ -0,0 +1,192 @@
+pub mod protocol;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error, info};
+
+use crate::registry::PluginRegistry;
+use crate::{is_plugin_file, reload_plugin, Plugin, CONFIG, RELOAD_LOCK};
+use protocol::{AdminRequest, AdminResponse, PluginInfo};
+
+struct AdminContext {
+    registry: Arc<Mutex<PluginRegistry>>,
+    library_path: PathBuf,
+    out_of_process: bool,
+}
+
+// Local admin endpoint for `rust-bot-ctl`, only the owner of the socket file can connect.
+// Returns the socket path, the caller removes it on shutdown.
+pub fn serve(
+    socket_path: &str,
+    registry: Arc<Mutex<PluginRegistry>>,
+    library_path: PathBuf,
+    out_of_process: bool,
+) -> anyhow::Result<PathBuf> {
+    let socket_path = PathBuf::from(socket_path);
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if socket_path.exists() {
+        if UnixStream::connect(&socket_path).is_ok() {
+            return Err(anyhow::anyhow!("another rust-bot is listening on it"));
+        }
+        // left behind by a bot that did not shut down cleanly
+        std::fs::remove_file(&socket_path)?;
+    }
+    let listener = UnixListener::bind(&socket_path)?;
+    std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Admin socket listening on {}", socket_path.display());
+
+    let context = Arc::new(AdminContext {
+        registry,
+        library_path,
+        out_of_process,
+    });
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let context = context.clone();
+                    std::thread::spawn(move || serve_connection(stream, &context));
+                }
+                Err(err) => error!("Failed to accept admin connection: {}", err),
+            }
+        }
+    });
+    Ok(socket_path)
+}
+
+fn serve_connection(stream: UnixStream, context: &AdminContext) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => {
+            error!("Failed to serve admin connection: {}", err);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let response = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(request) => {
+                info!("Admin request: {:?}", request);
+                handle(request, context).unwrap_or_else(|err| AdminResponse::Error(format!("{:#}", err)))
+            }
+            Err(err) => AdminResponse::Error(format!("invalid request: {}", err)),
+        };
+        let written = serde_json::to_string(&response)
+            .map_err(std::io::Error::from)
+            .and_then(|json| writeln!(writer, "{}", json));
+        if let Err(err) = written {
+            debug!("Admin connection closed: {}", err);
+            break;
+        }
+    }
+}
+
+fn handle(request: AdminRequest, context: &AdminContext) -> anyhow::Result<AdminResponse> {
+    match request {
+        AdminRequest::Plugins => {
+            let registry = context.registry.lock().unwrap();
+            let plugins = registry
+                .plugins()
+                .into_iter()
+                .map(|(path_str, plugin, started)| PluginInfo {
+                    path: path_str.clone(),
+                    name: plugin.manifest().name.clone(),
+                    version: plugin.manifest().version.clone(),
+                    started,
+                    agents: plugin.manifest().agents.iter().map(|agent| agent.name.clone()).collect(),
+                })
+                .collect();
+            Ok(AdminResponse::Plugins(plugins))
+        }
+        AdminRequest::Tasks { agent } => {
+            let registry = context.registry.lock().unwrap();
+            if let Some(agent) = &agent {
+                if !registry.provides_agent(agent) {
+                    return Err(anyhow::anyhow!("unknown agent {}", agent));
+                }
+            }
+            let mut tasks = Vec::new();
+            for (path_str, plugin, started) in registry.plugins() {
+                let selected = match &agent {
+                    Some(agent) => plugin.manifest().provides_agent(agent),
+                    None => true,
+                };
+                if started && selected {
+                    let task_states = plugin
+                        .task_states()
+                        .map_err(|err| anyhow::anyhow!("{}: {}", path_str, err))?;
+                    tasks.extend(task_states);
+                }
+            }
+            if let Some(agent) = &agent {
+                tasks.retain(|task| &task.agent == agent);
+            }
+            Ok(AdminResponse::Tasks(tasks))
+        }
+        AdminRequest::RunTask { agent, task } => {
+            with_agent(context, &agent, |plugin| plugin.run_task(&agent, &task))
+        }
+        AdminRequest::Pause { agent } => with_agent(context, &agent, |plugin| plugin.stop_agent(&agent)),
+        AdminRequest::Resume { agent } => with_agent(context, &agent, |plugin| plugin.start_agent(&agent)),
+        AdminRequest::Restart { agent } => with_agent(context, &agent, |plugin| plugin.restart_agent(&agent)),
+        AdminRequest::Reload { plugin } => {
+            let path_str = resolve_plugin(&context.registry.lock().unwrap(), &context.library_path, &plugin)?;
+            reload_plugin(&context.registry, &path_str, context.out_of_process)?;
+            Ok(AdminResponse::Ok)
+        }
+    }
+}
+
+// Like `reload_plugin`, the registry is only locked to look up the plugin, so other commands are answered
+// while it is called. A reload of the plugin waits for the call.
+fn with_agent<F>(context: &AdminContext, agent: &str, f: F) -> anyhow::Result<AdminResponse>
+where
+    F: FnOnce(&dyn Plugin) -> anyhow::Result<()>,
+{
+    let _reloading = RELOAD_LOCK.lock().unwrap();
+    let plugin = {
+        let registry = context.registry.lock().unwrap();
+        match registry.active_plugin_of(agent) {
+            Some(plugin) => plugin,
+            None if registry.provides_agent(agent) => {
+                return Err(anyhow::anyhow!("the plugin of agent {} waits for its dependencies", agent));
+            }
+            None => return Err(anyhow::anyhow!("unknown agent {}", agent)),
+        }
+    };
+    f(plugin.as_ref()).map(|_| AdminResponse::Ok)
+}
+
+// A loaded plugin by library path or file name, otherwise a plugin file in the library directory.
+fn resolve_plugin(registry: &PluginRegistry, library_path: &Path, plugin: &str) -> anyhow::Result<String> {
+    let loaded = registry.plugins().into_iter().find(|(path_str, _, _)| {
+        path_str.as_str() == plugin || Path::new(path_str).file_name().map(|x| x == plugin).unwrap_or(false)
+    });
+    if let Some((path_str, _, _)) = loaded {
+        return Ok(path_str.clone());
+    }
+    let path = library_path.join(plugin);
+    let in_library = Path::new(plugin).file_name().map(|x| x == plugin).unwrap_or(false);
+    if !in_library || !path.is_file() || !is_plugin_file(&path) {
+        return Err(anyhow::anyhow!(
+            "{} is neither a loaded plugin nor a plugin file in {}",
+            plugin,
+            library_path.display()
+        ));
+    }
+    if !CONFIG.is_selected(&path) {
+        return Err(anyhow::anyhow!("{} is not selected by the `plugins` setting", plugin));
+    }
+    path.to_str()
+        .map(|x| x.to_string())
+        .ok_or_else(|| anyhow::anyhow!("invalid path {}", path.display()))
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+pub use rust_bot_common::abi::TaskStatus;
+
+// Wire format of the admin socket, shared with the `rust-bot-ctl` binary (src/bin/rust-bot-ctl.rs).
+// Every request and every response is one line of JSON.
+
+pub const DEFAULT_ADMIN_SOCKET: &str = "./bin/tmp/rust-bot.sock";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminRequest {
+    Plugins,
+    // tasks of all started agents, or of `agent` only
+    Tasks { agent: Option<String> },
+    RunTask { agent: String, task: String },
+    Pause { agent: String },
+    Resume { agent: String },
+    Restart { agent: String },
+    // library path or file name of a plugin in the library directory
+    Reload { plugin: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminResponse {
+    Ok,
+    Plugins(Vec<PluginInfo>),
+    Tasks(Vec<TaskStatus>),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginInfo {
+    pub path: String,
+    pub name: String,
+    pub version: String,
+    // false while the plugin waits for its dependencies
+    pub started: bool,
+    pub agents: Vec<String>,
+}
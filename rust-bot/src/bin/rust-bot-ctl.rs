@@ -0,0 +1,136 @@
+// the same modules as in the bot, so the config file is read the same way
+#[path = "../admin"]
+mod admin {
+    pub mod protocol;
+}
+#[allow(dead_code)]
+#[path = "../config.rs"]
+mod config;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use admin::protocol::{AdminRequest, AdminResponse, PluginInfo, TaskStatus};
+use config::HostConfig;
+
+const USAGE: &str = "usage: rust-bot-ctl [--socket <path>] <command>
+
+commands:
+  plugins                 list the loaded plugins and their agents
+  tasks [<agent>]         show the state of every task
+  run <agent> <task>      run a scheduled task right away
+  pause <agent>           stop scheduling the agent's tasks
+  resume <agent>          start a paused agent again
+  restart <agent>         restart the agent with fresh settings
+  reload <plugin>         reload a plugin by library path or file name
+
+The socket defaults to $RUST_BOT_ADMIN_SOCKET, the admin_socket of the bot's config file
+or ./bin/tmp/rust-bot.sock.";
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let socket_path = if args.len() >= 2 && args[0] == "--socket" {
+        args.remove(0);
+        args.remove(0)
+    } else {
+        match HostConfig::load() {
+            Ok(config) if config.admin_socket.is_empty() => {
+                eprintln!("error: the admin socket is disabled in the config, pass --socket <path>");
+                std::process::exit(2);
+            }
+            Ok(config) => config.admin_socket,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    };
+
+    let request = match parse_request(&args) {
+        Some(request) => request,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    match send(&socket_path, &request) {
+        Ok(AdminResponse::Ok) => println!("ok"),
+        Ok(AdminResponse::Plugins(plugins)) => print_plugins(&plugins),
+        Ok(AdminResponse::Tasks(tasks)) => print_tasks(&tasks),
+        Ok(AdminResponse::Error(err)) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("error: {} ({})", err, socket_path);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_request(args: &[String]) -> Option<AdminRequest> {
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+    Some(match args.as_slice() {
+        ["plugins"] => AdminRequest::Plugins,
+        ["tasks"] => AdminRequest::Tasks { agent: None },
+        ["tasks", agent] => AdminRequest::Tasks { agent: Some(agent.to_string()) },
+        ["run", agent, task] => AdminRequest::RunTask {
+            agent: agent.to_string(),
+            task: task.to_string(),
+        },
+        ["pause", agent] => AdminRequest::Pause { agent: agent.to_string() },
+        ["resume", agent] => AdminRequest::Resume { agent: agent.to_string() },
+        ["restart", agent] => AdminRequest::Restart { agent: agent.to_string() },
+        ["reload", plugin] => AdminRequest::Reload { plugin: plugin.to_string() },
+        _ => return None,
+    })
+}
+
+fn send(socket_path: &str, request: &AdminRequest) -> anyhow::Result<AdminResponse> {
+    let mut stream = UnixStream::connect(socket_path)
+        .map_err(|err| anyhow::anyhow!("cannot connect to rust-bot: {}", err))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(anyhow::anyhow!("rust-bot closed the connection"));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn print_plugins(plugins: &[PluginInfo]) {
+    for plugin in plugins {
+        let state = if plugin.started { "started" } else { "waiting for dependencies" };
+        println!("{} v{} ({}): {}", plugin.name, plugin.version, state, plugin.path);
+        for agent in &plugin.agents {
+            println!("  {}", agent);
+        }
+    }
+}
+
+fn print_tasks(tasks: &[TaskStatus]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0);
+    let width = tasks
+        .iter()
+        .map(|task| task.agent.len() + task.task.len() + 2)
+        .max()
+        .unwrap_or(0);
+    for task in tasks {
+        let name = format!("{}::{}", task.agent, task.task);
+        match task.timestamp {
+            Some(timestamp) => println!(
+                "{:width$}  {:9}  {} ({}s ago)",
+                name,
+                task.state,
+                timestamp,
+                now - timestamp,
+                width = width
+            ),
+            None => println!("{:width$}  {}", name, task.state, width = width),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::admin::protocol::DEFAULT_ADMIN_SOCKET;
+
+// Looked up in this order unless `RUST_BOT_CONFIG` points to a file.
+const CONFIG_FILES: [&str; 3] = ["./rust-bot.toml", "./rust-bot.yaml", "./rust-bot.yml"];
+
+// Host configuration, every value has a default so the file and each of its sections are optional.
+// Environment variables (see `apply_env_overrides`) take precedence over the file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    // directory that is scanned and watched for `.so` and `.wasm` plugins
+    pub library_path: String,
+    // file names of the plugins to load, all plugins in `library_path` if not set
+    pub plugins: Option<Vec<String>>,
+    pub out_of_process: bool,
+    // how long in-flight tasks may run after SIGTERM/SIGINT before they are aborted
+    pub shutdown_timeout_in_secs: u64,
+    pub persistent_sled: SledConfig,
+    pub temporary_sled: SledConfig,
+    // unix socket of the admin endpoint used by `rust-bot-ctl`, disabled if empty
+    pub admin_socket: String,
+    // how many tasks of a native plugin may run at once
+    pub concurrency: ConcurrencyConfig,
+    // when native plugins stop querying a blockchain whose endpoints are down
+    pub circuit_breaker: CircuitBreakerConfig,
+    // settings per agent, keyed by the agent name of the plugin manifest
+    pub agents: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SledConfig {
+    // the default path of the database if empty
+    pub path: String,
+    pub cache_capacity: u64,
+    pub flush_every_ms: Option<u64>,
+}
+
+// Passed to native plugins together with the agent settings, unlimited if not set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    // across all agents of a plugin
+    pub max_tasks: Option<usize>,
+    pub max_tasks_per_agent: Option<usize>,
+    // across all agents of a plugin, e.g. to stay below the rate limit of public endpoints
+    pub max_tasks_per_blockchain: Option<usize>,
+}
+
+// Passed to native plugins together with the agent settings.
+// The circuit of a blockchain opens after `failure_threshold` failed requests in a row,
+// after `cooldown_in_secs` a single request probes whether it is back.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown_in_secs: i64,
+}
+
+// The configuration of the running bot. `main` loads it before anything else reads it,
+// so an invalid file or environment variable ends the bot with its error instead of a panic.
+pub struct LoadedConfig(OnceLock<HostConfig>);
+
+impl LoadedConfig {
+    pub const fn new() -> Self {
+        LoadedConfig(OnceLock::new())
+    }
+
+    pub fn load(&self) -> anyhow::Result<()> {
+        let config = HostConfig::load()?;
+        self.0
+            .set(config)
+            .map_err(|_| anyhow::anyhow!("the config was already loaded"))
+    }
+}
+
+impl Deref for LoadedConfig {
+    type Target = HostConfig;
+
+    fn deref(&self) -> &HostConfig {
+        self.0.get().expect("the config is loaded at the start of main")
+    }
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        HostConfig {
+            library_path: "./bin/lib/".to_string(),
+            plugins: None,
+            out_of_process: false,
+            shutdown_timeout_in_secs: 10,
+            persistent_sled: SledConfig::new("./bin/tmp/persistent_sled"),
+            temporary_sled: SledConfig::new("./bin/tmp/temporary_sled"),
+            admin_socket: DEFAULT_ADMIN_SOCKET.to_string(),
+            concurrency: ConcurrencyConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            agents: HashMap::new(),
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            cooldown_in_secs: 60,
+        }
+    }
+}
+
+impl Default for SledConfig {
+    fn default() -> Self {
+        SledConfig {
+            path: String::new(),
+            cache_capacity: 1024 * 1024 * 1024,
+            flush_every_ms: Some(1000),
+        }
+    }
+}
+
+impl SledConfig {
+    fn new(path: &str) -> Self {
+        SledConfig {
+            path: path.to_string(),
+            ..SledConfig::default()
+        }
+    }
+
+    pub fn open(&self) -> sled::Result<sled::Db> {
+        sled::Config::default()
+            .path(&self.path)
+            .cache_capacity(self.cache_capacity)
+            .flush_every_ms(self.flush_every_ms)
+            .mode(sled::Mode::HighThroughput)
+            .open()
+    }
+}
+
+impl HostConfig {
+    // Reads the config file (TOML or YAML, by extension) and applies the environment overrides.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = match std::env::var("RUST_BOT_CONFIG") {
+            Ok(path) => Some(path),
+            Err(_) => CONFIG_FILES
+                .iter()
+                .find(|path| Path::new(path).exists())
+                .map(|path| path.to_string()),
+        };
+        let mut config = match path {
+            Some(path) => HostConfig::from_file(&path)
+                .map_err(|err| anyhow::anyhow!("invalid config file {}: {}", path, err))?,
+            None => HostConfig::default(),
+        };
+        config.apply_env_overrides()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut config: HostConfig = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)?,
+            _ => return Err(anyhow::anyhow!("unsupported format, expected .toml, .yaml or .yml")),
+        };
+        let default = HostConfig::default();
+        for (sled, default) in [
+            (&mut config.persistent_sled, default.persistent_sled),
+            (&mut config.temporary_sled, default.temporary_sled),
+        ] {
+            if sled.path.is_empty() {
+                sled.path = default.path;
+            }
+        }
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) -> anyhow::Result<()> {
+        if let Some(value) = env_var("RUST_BOT_LIBRARY_PATH") {
+            self.library_path = value;
+        }
+        if let Some(value) = env_var("RUST_BOT_PLUGINS") {
+            self.plugins = Some(value.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect());
+        }
+        if let Some(value) = env_var("RUST_BOT_OUT_OF_PROCESS") {
+            self.out_of_process = parse_env("RUST_BOT_OUT_OF_PROCESS", &value)?;
+        }
+        if let Some(value) = env_var("RUST_BOT_SHUTDOWN_TIMEOUT_IN_SECS") {
+            self.shutdown_timeout_in_secs = parse_env("RUST_BOT_SHUTDOWN_TIMEOUT_IN_SECS", &value)?;
+        }
+        if let Some(value) = env_var("RUST_BOT_ADMIN_SOCKET") {
+            self.admin_socket = value;
+        }
+        for (prefix, sled) in [
+            ("RUST_BOT_PERSISTENT_SLED", &mut self.persistent_sled),
+            ("RUST_BOT_TEMPORARY_SLED", &mut self.temporary_sled),
+        ] {
+            if let Some(value) = env_var(&format!("{}_PATH", prefix)) {
+                sled.path = value;
+            }
+            if let Some(value) = env_var(&format!("{}_CACHE_CAPACITY", prefix)) {
+                sled.cache_capacity = parse_env(&format!("{}_CACHE_CAPACITY", prefix), &value)?;
+            }
+            if let Some(value) = env_var(&format!("{}_FLUSH_EVERY_MS", prefix)) {
+                // 0 disables the periodic flush
+                let flush_every_ms: u64 = parse_env(&format!("{}_FLUSH_EVERY_MS", prefix), &value)?;
+                sled.flush_every_ms = Some(flush_every_ms).filter(|x| *x > 0);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_in_secs)
+    }
+
+    pub fn is_selected(&self, library_path: &Path) -> bool {
+        match (&self.plugins, library_path.file_name()) {
+            (None, _) => true,
+            (Some(plugins), Some(file_name)) => plugins.iter().any(|x| file_name == x.as_str()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| anyhow::anyhow!("invalid value `{}` for {}: {}", value, name, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes `content` to a file of the given name in the temp directory and parses it
+    fn parse(file_name: &str, content: &str) -> anyhow::Result<HostConfig> {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), file_name));
+        std::fs::write(&path, content)?;
+        let config = HostConfig::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+        config
+    }
+
+    #[test]
+    fn toml_file() {
+        let config = parse(
+            "rust-bot.toml",
+            r#"
+            library_path = "./plugins/"
+            plugins = ["libChainRegistry.so"]
+            out_of_process = true
+            [temporary_sled]
+            cache_capacity = 1024
+            [circuit_breaker]
+            failure_threshold = 3
+            [agents.PoolAgent]
+            spread_tasks = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.library_path, "./plugins/");
+        assert!(config.is_selected(Path::new("./plugins/libChainRegistry.so")));
+        assert!(!config.is_selected(Path::new("./plugins/libOther.so")));
+        assert!(config.out_of_process);
+        // a section without a path keeps the default one
+        assert_eq!(config.temporary_sled.path, "./bin/tmp/temporary_sled");
+        assert_eq!(config.temporary_sled.cache_capacity, 1024);
+        assert_eq!(config.persistent_sled.path, "./bin/tmp/persistent_sled");
+        assert_eq!(config.circuit_breaker.failure_threshold, 3);
+        assert_eq!(config.circuit_breaker.cooldown_in_secs, 60);
+        assert_eq!(config.agents["PoolAgent"], serde_json::json!({ "spread_tasks": true }));
+        assert_eq!(config.admin_socket, DEFAULT_ADMIN_SOCKET);
+    }
+
+    #[test]
+    fn yaml_file() {
+        let config = parse(
+            "rust-bot.yaml",
+            "
+shutdown_timeout_in_secs: 30
+admin_socket: ''
+persistent_sled:
+  path: /var/lib/rust-bot
+concurrency:
+  max_tasks_per_blockchain: 2
+",
+        )
+        .unwrap();
+        assert_eq!(config.shutdown_timeout(), Duration::from_secs(30));
+        assert_eq!(config.admin_socket, "");
+        assert_eq!(config.persistent_sled.path, "/var/lib/rust-bot");
+        assert_eq!(config.concurrency.max_tasks_per_blockchain, Some(2));
+        assert_eq!(config.concurrency.max_tasks, None);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(parse("unknown.toml", "libary_path = \"./bin/lib/\"").is_err());
+        assert!(parse("wrong_type.yml", "out_of_process: maybe").is_err());
+        assert!(parse("rust-bot.json", "{}").is_err());
+    }
+
+    // the only test that sets environment variables, they are shared by all tests
+    #[test]
+    fn env_overrides() {
+        let vars = [
+            ("RUST_BOT_LIBRARY_PATH", "/opt/plugins"),
+            ("RUST_BOT_PLUGINS", "a.so, b.wasm,"),
+            ("RUST_BOT_SHUTDOWN_TIMEOUT_IN_SECS", "5"),
+            ("RUST_BOT_ADMIN_SOCKET", "/run/rust-bot.sock"),
+            ("RUST_BOT_PERSISTENT_SLED_PATH", "/var/lib/rust-bot"),
+            ("RUST_BOT_TEMPORARY_SLED_FLUSH_EVERY_MS", "0"),
+        ];
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        let mut config = HostConfig::default();
+        let result = config.apply_env_overrides();
+        std::env::set_var("RUST_BOT_OUT_OF_PROCESS", "yes");
+        let invalid = HostConfig::default().apply_env_overrides();
+        for (name, _) in vars {
+            std::env::remove_var(name);
+        }
+        std::env::remove_var("RUST_BOT_OUT_OF_PROCESS");
+
+        result.unwrap();
+        assert_eq!(config.library_path, "/opt/plugins");
+        assert_eq!(config.plugins, Some(vec!["a.so".to_string(), "b.wasm".to_string()]));
+        assert_eq!(config.shutdown_timeout_in_secs, 5);
+        assert_eq!(config.admin_socket, "/run/rust-bot.sock");
+        assert_eq!(config.persistent_sled.path, "/var/lib/rust-bot");
+        assert_eq!(config.temporary_sled.flush_every_ms, None);
+        assert_eq!(config.persistent_sled.flush_every_ms, Some(1000));
+        assert!(invalid.unwrap_err().to_string().contains("RUST_BOT_OUT_OF_PROCESS"));
+    }
+}
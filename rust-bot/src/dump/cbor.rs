@@ -0,0 +1,157 @@
+use std::io::{Read, Write};
+
+use super::Record;
+
+// Just enough CBOR (RFC 8949) for `Record`: a map of text keys to unsigned and negative integers,
+// booleans, text and byte strings, all of definite length. An export is a CBOR sequence (RFC 8742) of these maps.
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+
+const FALSE: u64 = 20;
+const TRUE: u64 = 21;
+
+#[derive(Debug)]
+enum Value {
+    Unsigned(u64),
+    // -1 - n
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Bool(bool),
+}
+
+fn write_head(out: &mut impl Write, major: u8, value: u64) -> std::io::Result<()> {
+    let major = major << 5;
+    match value {
+        0..=23 => out.write_all(&[major | value as u8]),
+        24..=0xff => out.write_all(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.write_all(&[major | 25])?;
+            out.write_all(&(value as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.write_all(&[major | 26])?;
+            out.write_all(&(value as u32).to_be_bytes())
+        }
+        _ => {
+            out.write_all(&[major | 27])?;
+            out.write_all(&value.to_be_bytes())
+        }
+    }
+}
+
+fn write_text(out: &mut impl Write, text: &str) -> std::io::Result<()> {
+    write_head(out, TEXT, text.len() as u64)?;
+    out.write_all(text.as_bytes())
+}
+
+pub fn write_record(out: &mut impl Write, record: &Record) -> std::io::Result<()> {
+    write_head(out, MAP, 5)?;
+    write_text(out, "key")?;
+    write_text(out, &record.key)?;
+    write_text(out, "revision")?;
+    write_head(out, UNSIGNED, record.revision)?;
+    write_text(out, "timestamp")?;
+    if record.timestamp >= 0 {
+        write_head(out, UNSIGNED, record.timestamp as u64)?;
+    } else {
+        write_head(out, NEGATIVE, (-1 - record.timestamp) as u64)?;
+    }
+    write_text(out, "ok")?;
+    write_head(out, SIMPLE, if record.ok { TRUE } else { FALSE })?;
+    write_text(out, "entry")?;
+    write_head(out, BYTES, record.entry.len() as u64)?;
+    out.write_all(&record.entry)
+}
+
+// The major type and argument of the next item, none at the end of the sequence.
+fn read_head(input: &mut impl Read) -> anyhow::Result<Option<(u8, u64)>> {
+    let mut initial = [0u8; 1];
+    if input.read(&mut initial)? == 0 {
+        return Ok(None);
+    }
+    let major = initial[0] >> 5;
+    let value = match initial[0] & 0x1f {
+        info @ 0..=23 => info as u64,
+        24 => read_array::<1>(input)?[0] as u64,
+        25 => u16::from_be_bytes(read_array(input)?) as u64,
+        26 => u32::from_be_bytes(read_array(input)?) as u64,
+        27 => u64::from_be_bytes(read_array(input)?),
+        info => return Err(anyhow::anyhow!("unsupported additional information {} (major type {})", info, major)),
+    };
+    Ok(Some((major, value)))
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_bytes(input: &mut impl Read, len: u64) -> anyhow::Result<Vec<u8>> {
+    // not allocated up front, the length may be corrupt
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(anyhow::anyhow!("truncated string, {} of {} bytes", bytes.len(), len));
+    }
+    Ok(bytes)
+}
+
+fn read_value(input: &mut impl Read) -> anyhow::Result<Value> {
+    let (major, value) = read_head(input)?.ok_or_else(|| anyhow::anyhow!("truncated record"))?;
+    Ok(match (major, value) {
+        (UNSIGNED, value) => Value::Unsigned(value),
+        (NEGATIVE, value) => Value::Negative(value),
+        (BYTES, len) => Value::Bytes(read_bytes(input, len)?),
+        (TEXT, len) => Value::Text(String::from_utf8(read_bytes(input, len)?)?),
+        (SIMPLE, FALSE) => Value::Bool(false),
+        (SIMPLE, TRUE) => Value::Bool(true),
+        (major, value) => return Err(anyhow::anyhow!("unsupported item, major type {} ({})", major, value)),
+    })
+}
+
+// none if the item does not fit into an i64, e.g. in a corrupt file
+fn timestamp_of(value: Option<i64>) -> anyhow::Result<i64> {
+    value.ok_or_else(|| anyhow::anyhow!("timestamp out of range"))
+}
+
+// The next record, none at the end of the sequence.
+pub fn read_record(input: &mut impl Read) -> anyhow::Result<Option<Record>> {
+    let fields = match read_head(input)? {
+        Some((MAP, fields)) => fields,
+        Some((major, _)) => return Err(anyhow::anyhow!("expected a map, found major type {}", major)),
+        None => return Ok(None),
+    };
+    let (mut key, mut revision, mut timestamp, mut ok, mut entry) = (None, None, None, None, None);
+    for _ in 0..fields {
+        let name = match read_value(input)? {
+            Value::Text(name) => name,
+            other => return Err(anyhow::anyhow!("expected a field name, found {:?}", other)),
+        };
+        match (name.as_str(), read_value(input)?) {
+            ("key", Value::Text(value)) => key = Some(value),
+            ("revision", Value::Unsigned(value)) => revision = Some(value),
+            ("timestamp", Value::Unsigned(value)) => timestamp = Some(timestamp_of(i64::try_from(value).ok())?),
+            ("timestamp", Value::Negative(value)) => {
+                timestamp = Some(timestamp_of(i64::try_from(value).ok().and_then(|value| (-1i64).checked_sub(value)))?)
+            }
+            ("ok", Value::Bool(value)) => ok = Some(value),
+            ("entry", Value::Bytes(value)) => entry = Some(value),
+            (name, value) => return Err(anyhow::anyhow!("unexpected field `{}`: {:?}", name, value)),
+        }
+    }
+    let missing = |field: &str| anyhow::anyhow!("missing field `{}`", field);
+    Ok(Some(Record {
+        key: key.ok_or_else(|| missing("key"))?,
+        revision: revision.ok_or_else(|| missing("revision"))?,
+        timestamp: timestamp.ok_or_else(|| missing("timestamp"))?,
+        ok: ok.ok_or_else(|| missing("ok"))?,
+        entry: entry.ok_or_else(|| missing("entry"))?,
+    }))
+}
@@ -0,0 +1,319 @@
+mod cbor;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::wasm::store::EntryStore;
+use rust_bot_common::store::GLOBAL_PREFIX_TASK_STORE;
+use crate::CONFIG;
+
+// `rust-bot export` and `rust-bot import` copy the agents' entries, with all their stored revisions,
+// between the persistent sled and a portable file. Both open the sled themselves, the bot must not be running.
+// The extension of the file selects the format: JSON Lines (`.jsonl`) or a CBOR sequence (`.cbor`), one record per revision.
+const USAGE: &str = "usage: rust-bot export [--prefix <key prefix>] <file.jsonl|file.cbor>
+       rust-bot import <file.jsonl|file.cbor>";
+
+// revisions written by `import` in one batch
+const IMPORT_BATCH: usize = 1000;
+
+// One revision of an entry. `timestamp` and `ok` are read from `entry` for whoever reads the file, import only restores `entry`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub key: String,
+    pub revision: u64,
+    // unix time the revision was written
+    pub timestamp: i64,
+    pub ok: bool,
+    // the bincode encoded `Entry<T>` as stored, base64 in JSON Lines
+    #[serde(with = "base64_entry")]
+    pub entry: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    JsonLines,
+    Cbor,
+}
+
+impl Format {
+    fn of(path: &str) -> anyhow::Result<Self> {
+        match path.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("jsonl") => Ok(Format::JsonLines),
+            Some("cbor") => Ok(Format::Cbor),
+            _ => Err(anyhow::anyhow!("unknown format of {}, use a .jsonl or .cbor file", path)),
+        }
+    }
+
+    fn write(&self, out: &mut impl Write, record: &Record) -> anyhow::Result<()> {
+        match self {
+            Format::JsonLines => {
+                serde_json::to_writer(&mut *out, record)?;
+                out.write_all(b"\n")?;
+            }
+            Format::Cbor => cbor::write_record(out, record)?,
+        }
+        Ok(())
+    }
+
+    // Passes one record after the other to `f`, returns how many there were.
+    fn for_each(&self, mut input: impl BufRead, mut f: impl FnMut(Record) -> anyhow::Result<()>) -> anyhow::Result<u64> {
+        let mut count = 0;
+        match self {
+            Format::JsonLines => {
+                for (number, line) in input.lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record = serde_json::from_str(&line)
+                        .map_err(|err| anyhow::anyhow!("invalid record on line {}: {}", number + 1, err))?;
+                    f(record)?;
+                    count += 1;
+                }
+            }
+            Format::Cbor => {
+                while let Some(record) = cbor::read_record(&mut input)
+                    .map_err(|err| anyhow::anyhow!("invalid record #{}: {}", count + 1, err))?
+                {
+                    f(record)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+// Runs `rust-bot <args>` for `export` and `import`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+    match args.as_slice() {
+        ["export", path] => export(&open_persistent_sled()?, "", path),
+        ["export", "--prefix", prefix, path] => export(&open_persistent_sled()?, prefix, path),
+        ["import", path] => import(&open_persistent_sled()?, path),
+        _ => Err(anyhow::anyhow!("{}", USAGE)),
+    }
+}
+
+// For the commands that work on the store while the bot is stopped, see `purge` as well.
+pub fn open_persistent_sled() -> anyhow::Result<sled::Db> {
+    CONFIG.persistent_sled.open().map_err(|err| {
+        anyhow::anyhow!(
+            "failed to open the persistent sled at {}, is the bot still running? {}",
+            CONFIG.persistent_sled.path,
+            err
+        )
+    })
+}
+
+// Writes every revision of the keys that start with `prefix`, oldest first.
+pub fn export(db: &sled::Db, prefix: &str, path: &str) -> anyhow::Result<()> {
+    let format = Format::of(path)?;
+    let store = EntryStore::new(db, GLOBAL_PREFIX_TASK_STORE);
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut count = 0;
+    for key in store.keys(prefix)? {
+        for (revision, entry) in store.revisions(&key)? {
+            let record = Record {
+                key: key.clone(),
+                revision,
+                timestamp: EntryStore::timestamp(&entry).unwrap_or_default(),
+                ok: EntryStore::is_ok_entry(&entry),
+                entry,
+            };
+            format.write(&mut out, &record)?;
+            count += 1;
+        }
+    }
+    out.flush()?;
+    info!("Exported {} revisions to {}", count, path);
+    Ok(())
+}
+
+// Restores an export. The histories of keys are not merged, none of the keys may exist yet.
+// The file is read twice and never held in memory, only the latest revision of each key: once to check it,
+// then to write IMPORT_BATCH revisions at a time.
+// The keys show up at once at the end, an import that fails before leaves only revisions without an index behind, `purge` removes them.
+pub fn import(db: &sled::Db, path: &str) -> anyhow::Result<()> {
+    let format = Format::of(path)?;
+    let store = EntryStore::new(db, GLOBAL_PREFIX_TASK_STORE);
+
+    // the latest revision of each key
+    let mut rev_indexes: BTreeMap<String, u64> = BTreeMap::new();
+    // revisions come as `export` writes them, those of a key one after the other and oldest first,
+    // so a revision that appears twice is one that does not increase
+    let mut last_key: Option<String> = None;
+    let count = format.for_each(BufReader::new(File::open(path)?), |record| {
+        match rev_indexes.get(&record.key) {
+            Some(index) if last_key.as_ref() != Some(&record.key) => {
+                return Err(anyhow::anyhow!(
+                    "revision {} of `{}` comes after other keys, following revision {}",
+                    record.revision,
+                    record.key,
+                    index
+                ));
+            }
+            Some(index) if *index >= record.revision => {
+                return Err(anyhow::anyhow!(
+                    "revision {} of `{}` follows revision {}, revisions must increase",
+                    record.revision,
+                    record.key,
+                    index
+                ));
+            }
+            Some(_) => {}
+            None if store.contains_key(&record.key)? => {
+                return Err(anyhow::anyhow!("key `{}` exists already, import into a fresh persistent sled", record.key));
+            }
+            None => last_key = Some(record.key.clone()),
+        }
+        rev_indexes.insert(record.key, record.revision);
+        Ok(())
+    })?;
+
+    let mut batch = Vec::with_capacity(IMPORT_BATCH);
+    format.for_each(BufReader::new(File::open(path)?), |record| {
+        batch.push((record.key, record.revision, record.entry));
+        if batch.len() == IMPORT_BATCH {
+            store.restore(&batch)?;
+            batch.clear();
+        }
+        Ok(())
+    })?;
+    store.restore(&batch)?;
+    store.restore_rev_indexes(&rev_indexes)?;
+    db.flush()?;
+    info!("Imported {} revisions of {} keys from {}", count, rev_indexes.len(), path);
+    Ok(())
+}
+
+mod base64_entry {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entry: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(entry))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `Entry<T>` as the plugins store it, ending with its timestamp
+    fn entry(ok: bool, data: &[u8], timestamp: i64) -> Vec<u8> {
+        bincode::serialize(&(if ok { 0u32 } else { 1u32 }, data, timestamp)).unwrap()
+    }
+
+    fn records() -> Vec<Record> {
+        [(i64::MIN, 0), (-1, 1), (0, 23), (1_700_000_000, 0x1_0000), (i64::MAX, 100_000)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (timestamp, len))| {
+                let entry = entry(i % 2 == 0, &vec![i as u8; len], timestamp);
+                Record { key: format!("key_{}", i % 2), revision: i as u64 / 2, timestamp, ok: i % 2 == 0, entry }
+            })
+            .collect()
+    }
+
+    fn read_all(format: Format, input: &[u8]) -> anyhow::Result<Vec<Record>> {
+        let mut records = Vec::new();
+        format.for_each(input, |record| {
+            records.push(record);
+            Ok(())
+        })?;
+        Ok(records)
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rust-bot-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for format in [Format::JsonLines, Format::Cbor] {
+            let mut out = Vec::new();
+            for record in records() {
+                format.write(&mut out, &record).unwrap();
+            }
+            assert_eq!(read_all(format, &out).unwrap(), records(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn out_of_range_cbor_timestamps_are_rejected() {
+        for head in [0x1b, 0x3b] {
+            let mut input = vec![0xa1, 0x69];
+            input.extend_from_slice(b"timestamp");
+            input.push(head);
+            input.extend_from_slice(&u64::MAX.to_be_bytes());
+            let err = read_all(Format::Cbor, &input).unwrap_err();
+            assert!(err.to_string().contains("timestamp out of range"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = EntryStore::new(&db, GLOBAL_PREFIX_TASK_STORE);
+        let revisions: Vec<_> = records().into_iter().map(|record| (record.key, record.revision, record.entry)).collect();
+        store.restore(&revisions).unwrap();
+        store.restore_rev_indexes(&BTreeMap::from([("key_0".to_string(), 2), ("key_1".to_string(), 1)])).unwrap();
+        for extension in ["jsonl", "cbor"] {
+            let path = temp_path(&format!("round-trip.{}", extension));
+            export(&db, "", &path).unwrap();
+            let imported = sled::Config::new().temporary(true).open().unwrap();
+            import(&imported, &path).unwrap();
+            // an import does not merge histories
+            assert!(import(&imported, &path).is_err());
+            std::fs::remove_file(&path).unwrap();
+
+            let imported = EntryStore::new(&imported, GLOBAL_PREFIX_TASK_STORE);
+            assert_eq!(imported.keys("").unwrap(), vec!["key_0", "key_1"]);
+            for key in ["key_0", "key_1"] {
+                assert_eq!(imported.revisions(key).unwrap(), store.revisions(key).unwrap(), "{}", extension);
+            }
+            assert_eq!(imported.revisions("key_0").unwrap()[0].1, records()[0].entry);
+        }
+    }
+
+    #[test]
+    fn import_rejects_repeated_and_scattered_revisions() {
+        let record = |key: &str, revision| Record {
+            key: key.to_string(),
+            revision,
+            timestamp: 0,
+            ok: true,
+            entry: entry(true, &[], 0),
+        };
+        let cases = [
+            (vec![record("key_0", 0), record("key_0", 0)], "revisions must increase"),
+            (vec![record("key_0", 1), record("key_0", 0)], "revisions must increase"),
+            (vec![record("key_0", 0), record("key_1", 0), record("key_0", 1)], "comes after other keys"),
+        ];
+        let path = temp_path("rejected.jsonl");
+        for (records, expected) in cases {
+            let mut out = BufWriter::new(File::create(&path).unwrap());
+            for record in &records {
+                Format::JsonLines.write(&mut out, record).unwrap();
+            }
+            out.flush().unwrap();
+
+            let db = sled::Config::new().temporary(true).open().unwrap();
+            let err = import(&db, &path).unwrap_err();
+            assert!(err.to_string().contains(expected), "{:?}: {}", records, err);
+            assert!(EntryStore::new(&db, GLOBAL_PREFIX_TASK_STORE).keys("").unwrap().is_empty());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::Sender;
+
+use log::{debug, error};
+use rust_bot_common::ipc::{read_frame, write_frame, Channel, DbKind, StoreRequest, StoreResponse, WatchEvent};
+use rust_bot_common::store::apply_if;
+
+use crate::{PERSISTENT_SLED, TEMPORARY_SLED};
+
+// Serves the store and the watch connections of worker processes, see `rust_bot_common::ipc` for the wire format.
+fn sled_db(db: DbKind) -> &'static sled::Db {
+    match db {
+        DbKind::Persistent => &PERSISTENT_SLED,
+        DbKind::Temporary => &TEMPORARY_SLED,
+    }
+}
+
+// Serves one connection of a worker process until it is closed.
+// Control connections are handed to the worker's supervisor instead.
+pub fn serve_connection(mut stream: UnixStream, control: Sender<UnixStream>) {
+    match read_frame::<Channel>(&mut stream) {
+        Ok(Channel::Control) => {
+            let _ = control.send(stream);
+        }
+        Ok(Channel::Store) => serve_store(stream),
+        Ok(Channel::Watch { db, prefix }) => serve_watch(stream, db, prefix),
+        Err(err) => error!("Invalid worker connection: {}", err),
+    }
+}
+
+fn serve_store(mut stream: UnixStream) {
+    while let Ok(request) = read_frame::<StoreRequest>(&mut stream) {
+        let response = handle_store_request(request).unwrap_or_else(|err| StoreResponse::Error(err.to_string()));
+        if let Err(err) = write_frame(&mut stream, &response) {
+            debug!("Store connection closed: {}", err);
+            break;
+        }
+    }
+}
+
+fn handle_store_request(request: StoreRequest) -> sled::Result<StoreResponse> {
+    Ok(match request {
+        StoreRequest::Get { db, key } => {
+            StoreResponse::Value(sled_db(db).get(key)?.map(|value| value.to_vec()))
+        }
+        StoreRequest::Insert { db, key, value } => {
+            StoreResponse::Value(sled_db(db).insert(key, value)?.map(|value| value.to_vec()))
+        }
+        StoreRequest::Remove { db, key } => {
+            StoreResponse::Value(sled_db(db).remove(key)?.map(|value| value.to_vec()))
+        }
+        StoreRequest::ContainsKey { db, key } => StoreResponse::Bool(sled_db(db).contains_key(key)?),
+        StoreRequest::ScanPrefix { db, prefix } => StoreResponse::Entries(
+            sled_db(db)
+                .scan_prefix(prefix)
+                .map(|item| item.map(|(key, value)| (key.to_vec(), value.to_vec())))
+                .collect::<sled::Result<Vec<_>>>()?,
+        ),
+        StoreRequest::ApplyIf { db, expected, batch } => StoreResponse::Bool(apply_if(sled_db(db), &expected, &batch)?),
+    })
+}
+
+// Forwards sled events to the worker, ends with the first event that cannot be delivered.
+fn serve_watch(mut stream: UnixStream, db: DbKind, prefix: Vec<u8>) {
+    for event in sled_db(db).watch_prefix(prefix) {
+        let event = match event {
+            sled::Event::Insert { key, value } => WatchEvent::Insert {
+                key: key.to_vec(),
+                value: value.to_vec(),
+            },
+            sled::Event::Remove { key } => WatchEvent::Remove { key: key.to_vec() },
+        };
+        if write_frame(&mut stream, &event).is_err() {
+            break;
+        }
+    }
+}
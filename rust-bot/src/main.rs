@@ -1,89 +1,213 @@
 
 
 
-use libloading::{Library, Symbol};
+mod abi;
+mod admin;
+mod config;
+mod dump;
+mod ipc;
+mod migrate;
+mod purge;
+mod registry;
+mod wasm;
+mod worker;
+
+use libloading::Library;
 use async_trait::async_trait;
-
+use config::LoadedConfig;
+use rust_bot_common::abi::{PluginVTable, TaskStatus};
+use rust_bot_common::manifest::PluginManifest;
+use registry::PluginRegistry;
+use wasm::WasmPlugin;
+use worker::WorkerPlugin;
+
+use std::ffi::CString;
 use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher,recommended_watcher, EventKind};
 
-use std::collections::HashMap;
 use std::path::Path;
-use log::{info,error};
+use std::time::Duration;
+use log::{info,warn,error};
 
 
 
-lazy_static::lazy_static! {
-    static ref PERSISTENT_SLED: sled::Db = load_sled_db("./bin/tmp/persistent_sled");
-    static ref TEMPORARY_SLED: sled::Db = load_sled_db("./bin/tmp/temporary_sled");
-}
+static CONFIG: LoadedConfig = LoadedConfig::new();
 
-fn load_sled_db(path: &str) -> sled::Db {
-    sled::Config::default()
-        .path(path)
-        .cache_capacity(1024 * 1024 * 1024)
-        .flush_every_ms(Some(1000))
-        .mode(sled::Mode::HighThroughput)
-        .open()
-        .unwrap()
+lazy_static::lazy_static! {
+    static ref PERSISTENT_SLED: sled::Db = CONFIG.persistent_sled.open().unwrap();
+    static ref TEMPORARY_SLED: sled::Db = CONFIG.temporary_sled.open().unwrap();
+    // one reload at a time, so two of them can not load the same library side by side
+    static ref RELOAD_LOCK: Mutex<()> = Mutex::new(());
 }
 
-type InitFn = fn(&sled::Db, &sled::Db);
-type StartFn = fn();
-type StopFn = fn();
-type ShutdownFn = fn();
-
 #[async_trait]
-trait Plugin {
-    fn init(&self, persistent_sled: &sled::Db, temporary_sled: &sled::Db);
-    fn start(&self);
-    fn stop(&self);
-    fn shutdown(&self);
+trait Plugin: Send + Sync {
+    fn manifest(&self) -> &PluginManifest;
+    // `agent_config` is the JSON of the `agents` section of the host config,
+    // for native plugins it also holds the `concurrency` and `circuit_breaker` sections under their keys
+    fn init(&self, persistent_sled: &sled::Db, temporary_sled: &sled::Db, agent_config: &str) -> anyhow::Result<()>;
+    fn start(&self) -> anyhow::Result<()>;
+    fn stop(&self) -> anyhow::Result<()>;
+    // `timeout` bounds how long in-flight tasks may still run
+    fn shutdown(&self, timeout: Duration) -> anyhow::Result<()>;
+    // per-agent commands of the admin socket, `agent` is the manifest name of one of the plugin's agents
+    fn start_agent(&self, agent: &str) -> anyhow::Result<()>;
+    fn stop_agent(&self, agent: &str) -> anyhow::Result<()>;
+    fn restart_agent(&self, agent: &str) -> anyhow::Result<()>;
+    fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>>;
+    fn run_task(&self, agent: &str, task: &str) -> anyhow::Result<()>;
 }
 
 struct DefaultPlugin {
-    library: Library,
+    manifest: PluginManifest,
+    vtable: PluginVTable,
+    // keeps the functions referenced by `vtable` mapped
+    _library: Library,
 }
 
 impl DefaultPlugin {
     fn new(library_path: &str) -> anyhow::Result<Self> {
         unsafe {
             let library = Library::new(library_path)?;
-            Ok(DefaultPlugin { library })
+            let (vtable, manifest) = abi::load_declaration(&library)
+                .map_err(|err| anyhow::anyhow!("refusing to load {}: {}", library_path, err))?;
+            info!("Loaded plugin {}: {}", library_path, manifest);
+            Ok(DefaultPlugin { manifest, vtable, _library: library })
         }
     }
+
+    // Loads and initializes the plugin, `PluginRegistry::start_ready` starts it.
+    fn load(library_path: &str) -> anyhow::Result<Self> {
+        let plugin = DefaultPlugin::new(library_path)?;
+        plugin.init(&PERSISTENT_SLED, &TEMPORARY_SLED, &native_agent_config())?;
+        Ok(plugin)
+    }
+
+    // only offline, see `migrate`
+    fn migrate(&self, dry_run: bool) -> anyhow::Result<()> {
+        self.vtable.migrate(dry_run)
+    }
 }
 #[async_trait]
 impl Plugin for DefaultPlugin {
-    fn init(&self, persistent_sled: &sled::Db, temporary_sled: &sled::Db) {
-        unsafe {
-            let init_fn: Symbol<InitFn> = self.library.get(b"init\0").unwrap();
-            init_fn(persistent_sled, temporary_sled);
-        }
+    fn manifest(&self) -> &PluginManifest {
+        &self.manifest
     }
 
-    fn start(&self) {
-        unsafe {
-            let start_fn: Symbol<StartFn> = self.library.get(b"start\0").unwrap();
-            start_fn()
-        }
+    fn init(&self, persistent_sled: &sled::Db, temporary_sled: &sled::Db, agent_config: &str) -> anyhow::Result<()> {
+        let agent_config = CString::new(agent_config)?;
+        let status = unsafe { (self.vtable.init)(persistent_sled, temporary_sled, agent_config.as_ptr()) };
+        self.vtable.check("init", status)
+    }
 
+    fn start(&self) -> anyhow::Result<()> {
+        self.vtable.check("start", (self.vtable.start)())
     }
 
-    fn stop(&self) {
-        unsafe {
-            let stop_fn: Symbol<StopFn> = self.library.get(b"stop\0").unwrap();
-            stop_fn()
-        }
+    fn stop(&self) -> anyhow::Result<()> {
+        self.vtable.check("stop", (self.vtable.stop)())
     }
-    fn shutdown(&self) {
-        unsafe {
-            let shutdown_fn: Symbol<ShutdownFn> = self.library.get(b"shutdown\0").unwrap();
-            shutdown_fn()
-        }
+
+    fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        self.vtable.check("shutdown", (self.vtable.shutdown)(timeout.as_millis() as u64))
+    }
+
+    fn start_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.vtable.start_agent(agent)
+    }
+
+    fn stop_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.vtable.stop_agent(agent)
+    }
+
+    fn restart_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.vtable.restart_agent(agent)
+    }
+
+    fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>> {
+        self.vtable.task_states()
+    }
+
+    fn run_task(&self, agent: &str, task: &str) -> anyhow::Result<()> {
+        self.vtable.run_task(agent, task)
+    }
+}
+
+// Loads the plugin in-process, or in a supervised worker process with `--out-of-process`.
+// `.wasm` plugins are always sandboxed in the embedded WASM runtime.
+fn load_plugin(library_path: &str, out_of_process: bool) -> anyhow::Result<Box<dyn Plugin>> {
+    if is_wasm_plugin(Path::new(library_path)) {
+        let plugin = WasmPlugin::new(library_path)
+            .map_err(|err| anyhow::anyhow!("refusing to load {}: {}", library_path, err))?;
+        info!("Loaded WASM plugin {}: {}", library_path, plugin.manifest());
+        plugin.init(&PERSISTENT_SLED, &TEMPORARY_SLED, &agent_config())?;
+        Ok(Box::new(plugin))
+    } else if out_of_process {
+        let plugin = WorkerPlugin::load(library_path, &native_agent_config())
+            .map_err(|err| anyhow::anyhow!("refusing to load {}: {}", library_path, err))?;
+        info!("Loaded plugin {} in worker process: {}", library_path, plugin.manifest());
+        Ok(Box::new(plugin))
+    } else {
+        Ok(Box::new(DefaultPlugin::load(library_path)?))
+    }
+}
+
+fn agent_config() -> String {
+    serde_json::to_string(&CONFIG.agents).unwrap_or_else(|_| "{}".to_string())
+}
+
+// The WASM runtime runs one task at a time and does not query blockchains itself,
+// only native plugins get the concurrency limits and circuit breaker settings.
+fn native_agent_config() -> String {
+    let mut agent_config = serde_json::Map::new();
+    for (agent, settings) in &CONFIG.agents {
+        agent_config.insert(agent.clone(), settings.clone());
+    }
+    if let Ok(concurrency) = serde_json::to_value(&CONFIG.concurrency) {
+        agent_config.insert("concurrency".to_string(), concurrency);
+    }
+    if let Ok(circuit_breaker) = serde_json::to_value(&CONFIG.circuit_breaker) {
+        agent_config.insert("circuit_breaker".to_string(), circuit_breaker);
+    }
+    serde_json::to_string(&agent_config).unwrap_or_else(|_| "{}".to_string())
+}
+
+fn is_plugin_file(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "so").unwrap_or(false) || is_wasm_plugin(path)
+}
+
+fn is_wasm_plugin(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "wasm").unwrap_or(false)
+}
+
+fn stop_and_shutdown(path_str: &str, plugin: &dyn Plugin) {
+    if let Err(err) = plugin.stop().and_then(|_| plugin.shutdown(CONFIG.shutdown_timeout())) {
+        error!("Failed to shut down library {}: {}", path_str, err);
     }
 }
 
+// Shuts down the plugin if it is loaded and loads it again, used on file changes and by the admin socket.
+// The registry is only locked to take out the old plugin and to swap in the new one,
+// so the admin socket keeps answering while the plugin shuts down and loads.
+fn reload_plugin(registry: &Mutex<PluginRegistry>, path_str: &str, out_of_process: bool) -> anyhow::Result<()> {
+    let _reloading = RELOAD_LOCK.lock().unwrap();
+    let old_plugin = registry.lock().unwrap().remove(path_str);
+    if let Some(plugin) = old_plugin {
+        // if the plugin is already running, shut it down
+        stop_and_shutdown(path_str, plugin.as_ref());
+        info!("Re-loading library: {}", path_str);
+    }else{
+        info!("Loading library: {}", path_str);
+    }
+
+    let new_plugin = load_plugin(path_str, out_of_process)?;
+    let mut plugins = registry.lock().unwrap();
+    plugins.add(path_str, new_plugin);
+    plugins.start_ready();
+    Ok(())
+}
+
 
 #[tokio::main]
 async fn main() {
@@ -91,36 +215,75 @@ async fn main() {
     // Initialize the logger
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let library_path = "./bin/lib/";
-    let active_plugins: Arc<Mutex<HashMap<String, DefaultPlugin>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    let args: Vec<String> = std::env::args().collect();
+    // `rust-bot worker <library_path> <socket_path>` is spawned by the host for each plugin in `--out-of-process` mode
+    if args.len() == 4 && args[1] == "worker" {
+        if let Err(err) = worker::run_worker(&args[2], &args[3]) {
+            error!("Worker for {} failed: {}", args[2], err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(err) = CONFIG.load() {
+        error!("Failed to load config: {}", err);
+        std::process::exit(1);
+    }
+    // `rust-bot export [--prefix <key prefix>] <file>`, `rust-bot import <file>`,
+    // `rust-bot purge [--prefix <key prefix>] [--dry-run] [--yes]` and `rust-bot migrate [--dry-run]` work on the store and exit
+    if args.len() >= 2 && ["export", "import", "purge", "migrate"].contains(&args[1].as_str()) {
+        let result = match args[1].as_str() {
+            "purge" => purge::run(&args[1..]),
+            "migrate" => migrate::run(&args[1..]),
+            _ => dump::run(&args[1..]),
+        };
+        if let Err(err) = result {
+            error!("{} failed: {}", args[1], err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let out_of_process = CONFIG.out_of_process || args.iter().any(|arg| arg == "--out-of-process");
+
+    // canonical, so the paths of the initial scan match the ones reported by the watcher
+    let library_path = std::fs::canonicalize(&CONFIG.library_path)
+        .expect("Failed to read library directory");
+    let plugin_registry: Arc<Mutex<PluginRegistry>> =
+        Arc::new(Mutex::new(PluginRegistry::default()));
 
     // Load and start all libraries initially
     let library_files = std::fs::read_dir(&library_path)
         .expect("Failed to read library directory")
         .filter_map(Result::ok)
-        .filter(|entry| {
-            entry.path().extension().map(|ext| ext == "so").unwrap_or(false)
-        });
+        .filter(|entry| is_plugin_file(&entry.path()) && CONFIG.is_selected(&entry.path()));
 
     for entry in library_files {
         let path = entry.path();
         let path_str = path.to_str().unwrap();
-        info!("Starting library: {}", path_str);
-
-        let mut plugins = active_plugins.lock().unwrap();
+        info!("Loading library: {}", path_str);
 
-        if let Some(_plugin) = plugins.get_mut(path_str) {
-            // If the plugin is already running, skip starting it again
+        if plugin_registry.lock().unwrap().contains(path_str) {
+            // If the plugin is already loaded, skip loading it again
             continue;
         }
 
-        let new_plugin = DefaultPlugin::new(path_str).expect("Failed to load library");
-        new_plugin.init(&PERSISTENT_SLED, &TEMPORARY_SLED);
-        new_plugin.start();
-
-        plugins.insert(path_str.to_string(), new_plugin);
+        match load_plugin(path_str, out_of_process) {
+            Ok(new_plugin) => {
+                plugin_registry.lock().unwrap().add(path_str, new_plugin);
+            }
+            Err(err) => {
+                error!("Failed to load library {}: {}", path_str, err);
+            }
+        }
     }
+    for agent in CONFIG.agents.keys() {
+        if !plugin_registry.lock().unwrap().provides_agent(agent) {
+            warn!("Config has settings for {}, but no loaded plugin provides this agent", agent);
+        }
+    }
+    // Start all libraries whose dependencies are met, upstream libraries first
+    plugin_registry.lock().unwrap().start_ready();
+
+    let watcher_registry = plugin_registry.clone();
 
     // Start the file watcher after initializing libraries
     let mut watcher: RecommendedWatcher = recommended_watcher(move |res: notify::Result<notify::Event>| {
@@ -130,27 +293,13 @@ async fn main() {
                     EventKind::Create(_) | EventKind::Modify(_) => {
                         let path = &event.paths[0];
                         if let Some(extension) = path.extension() {
-                            if extension == "so" {
+                            if (extension == "so" || extension == "wasm") && CONFIG.is_selected(path) {
                                 let path_str = path.to_str().unwrap();
                                 info!("Detected change in: {}", path_str);
 
-                                let mut plugins = active_plugins.lock().unwrap();
-
-                                if let Some(plugin) = plugins.get_mut(path_str) {
-                                    // if the plugin is already running, shut it down
-                                    plugin.shutdown();
-                                    info!("Re-starting library: {}", path_str);
-                                }else{
-                                    info!("Starting library: {}", path_str);
+                                if let Err(err) = reload_plugin(&watcher_registry, path_str, out_of_process) {
+                                    error!("Failed to load library {}: {}", path_str, err);
                                 }
-
-                                let new_plugin = DefaultPlugin::new(path_str).expect("Failed to load library");
-
-
-                                new_plugin.init(&PERSISTENT_SLED, &TEMPORARY_SLED);
-                                new_plugin.start();
-
-                                plugins.insert(path_str.to_string(), new_plugin);
                             }
                         }
                     },
@@ -158,11 +307,12 @@ async fn main() {
                         let path_str = event.paths[0].to_str().unwrap();
                         info!("Detected change in: {}", path_str);
 
-                        let mut plugins = active_plugins.lock().unwrap();
-                        if let Some(plugin) = plugins.remove(path_str) {
+                        let _reloading = RELOAD_LOCK.lock().unwrap();
+                        let removed = watcher_registry.lock().unwrap().remove(path_str);
+                        if let Some(plugin) = removed {
                             // if the plugin is being removed, shut it down
                             info!("Shutting down library: {}", path_str);
-                            plugin.shutdown();
+                            stop_and_shutdown(path_str, plugin.as_ref());
 
                         }
                     },
@@ -175,9 +325,48 @@ async fn main() {
         }
     }).unwrap();
 
-    watcher.watch(Path::new(&library_path), RecursiveMode::NonRecursive).unwrap();
+    watcher.watch(&library_path, RecursiveMode::NonRecursive).unwrap();
+
+    let admin_socket = if CONFIG.admin_socket.is_empty() {
+        None
+    } else {
+        match admin::serve(&CONFIG.admin_socket, plugin_registry.clone(), library_path.clone(), out_of_process) {
+            Ok(socket_path) => Some(socket_path),
+            Err(err) => {
+                error!("Failed to open admin socket {}: {}", CONFIG.admin_socket, err);
+                None
+            }
+        }
+    };
 
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
     loop {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                // dependents are held back until their upstream data exists
+                plugin_registry.lock().unwrap().start_ready();
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Received SIGINT, shutting down");
+                break;
+            }
+            _ = sigterm.recv() => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            }
+        }
+    }
+
+    // no more reloads while the plugins shut down
+    drop(watcher);
+    if let Some(socket_path) = admin_socket {
+        let _ = std::fs::remove_file(socket_path);
+    }
+    plugin_registry.lock().unwrap().shutdown_all(CONFIG.shutdown_timeout());
+    for (name, db) in [("persistent", &*PERSISTENT_SLED), ("temporary", &*TEMPORARY_SLED)] {
+        if let Err(err) = db.flush() {
+            error!("Failed to flush {} sled: {}", name, err);
+        }
     }
+    info!("Shutdown complete");
 }
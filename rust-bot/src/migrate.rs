@@ -0,0 +1,40 @@
+use log::info;
+
+use crate::dump::open_persistent_sled;
+use crate::{is_plugin_file, is_wasm_plugin, native_agent_config, DefaultPlugin, Plugin, CONFIG};
+
+// `rust-bot migrate` has the native plugins upgrade the stored entries to the current version of their schema,
+// see `migration` in rust-bot-plugin, `--dry-run` only counts them. The entries of WASM plugins have no schema.
+// Like `export` it opens the sleds itself, the bot must not be running.
+const USAGE: &str = "usage: rust-bot migrate [--dry-run]";
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let dry_run = match args {
+        [_] => false,
+        [_, flag] if flag == "--dry-run" => true,
+        _ => return Err(anyhow::anyhow!("{}", USAGE)),
+    };
+    let persistent_sled = open_persistent_sled()?;
+    let temporary_sled = CONFIG.temporary_sled.open()?;
+
+    let mut library_files: Vec<_> = std::fs::read_dir(&CONFIG.library_path)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| is_plugin_file(path) && !is_wasm_plugin(path) && CONFIG.is_selected(path))
+        .collect();
+    library_files.sort();
+    if library_files.is_empty() {
+        return Err(anyhow::anyhow!("no native plugin in {}", CONFIG.library_path));
+    }
+    for path in library_files {
+        let path_str = path.to_string_lossy();
+        let plugin = DefaultPlugin::new(&path_str)?;
+        plugin.init(&persistent_sled, &temporary_sled, &native_agent_config())?;
+        let migrated = plugin.migrate(dry_run);
+        plugin.shutdown(CONFIG.shutdown_timeout())?;
+        migrated.map_err(|err| anyhow::anyhow!("{}: {}", path_str, err))?;
+        info!("Migrated the store with {}", path_str);
+    }
+    persistent_sled.flush()?;
+    Ok(())
+}
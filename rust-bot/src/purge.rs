@@ -0,0 +1,61 @@
+use std::io::Write;
+
+use log::info;
+
+use crate::dump::open_persistent_sled;
+use crate::wasm::store::{EntryStore, Purged};
+use rust_bot_common::store::GLOBAL_PREFIX_TASK_STORE;
+
+// `rust-bot purge` removes the agents' entries under a key prefix, e.g. after a breaking change of what they store.
+// Without `--prefix` it removes all of them. It asks before it removes anything unless `--yes` is given,
+// `--dry-run` only counts. Like `export` it opens the persistent sled itself, the bot must not be running.
+const USAGE: &str = "usage: rust-bot purge [--prefix <key prefix>] [--dry-run] [--yes]";
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let (mut prefix, mut dry_run, mut yes) = ("", false, false);
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prefix" => prefix = args.next().ok_or_else(|| anyhow::anyhow!("{}", USAGE))?,
+            "--dry-run" => dry_run = true,
+            "--yes" => yes = true,
+            _ => return Err(anyhow::anyhow!("{}", USAGE)),
+        }
+    }
+    let described = if prefix.is_empty() {
+        "keys".to_string()
+    } else {
+        format!("keys starting with `{}`", prefix)
+    };
+
+    let db = open_persistent_sled()?;
+    let store = EntryStore::new(&db, GLOBAL_PREFIX_TASK_STORE);
+    let found = store.purge(prefix, true)?;
+    info!("Found {} revisions of {} {} and {} orphaned revisions", found.revisions, found.keys, described, found.orphans);
+    if dry_run || found == Purged::default() {
+        return Ok(());
+    }
+    let question = format!(
+        "Remove {} revisions of {} {} and {} orphaned revisions?",
+        found.revisions, found.keys, described, found.orphans
+    );
+    if !yes && !confirm(&question)? {
+        info!("Nothing removed");
+        return Ok(());
+    }
+    let purged = store.purge(prefix, false)?;
+    db.flush()?;
+    info!(
+        "Removed {} revisions of {} {} and {} orphaned revisions",
+        purged.revisions, purged.keys, described, purged.orphans
+    );
+    Ok(())
+}
+
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
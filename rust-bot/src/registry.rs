@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use rust_bot_common::manifest::{Dependency, PluginManifest};
+use crate::{Plugin, PERSISTENT_SLED};
+
+// All loaded plugins, keyed by library path.
+// A plugin is initialized right after loading but only started once its dependencies are met.
+#[derive(Default)]
+pub struct PluginRegistry {
+    pending: HashMap<String, Arc<dyn Plugin>>,
+    active: HashMap<String, Arc<dyn Plugin>>,
+}
+
+impl PluginRegistry {
+    pub fn contains(&self, path_str: &str) -> bool {
+        self.pending.contains_key(path_str) || self.active.contains_key(path_str)
+    }
+
+    pub fn provides_agent(&self, name: &str) -> bool {
+        self.pending
+            .values()
+            .chain(self.active.values())
+            .any(|plugin| plugin.manifest().provides_agent(name))
+    }
+
+    // every loaded plugin with its library path and whether it was started, sorted by path
+    pub fn plugins(&self) -> Vec<(&String, &dyn Plugin, bool)> {
+        let mut plugins: Vec<(&String, &dyn Plugin, bool)> = self
+            .pending
+            .iter()
+            .map(|(path_str, plugin)| (path_str, plugin.as_ref(), false))
+            .chain(self.active.iter().map(|(path_str, plugin)| (path_str, plugin.as_ref(), true)))
+            .collect();
+        plugins.sort_by(|a, b| a.0.cmp(b.0));
+        plugins
+    }
+
+    // the started plugin that provides the agent, to be called without holding the registry
+    pub fn active_plugin_of(&self, agent: &str) -> Option<Arc<dyn Plugin>> {
+        self.active
+            .values()
+            .find(|plugin| plugin.manifest().provides_agent(agent))
+            .cloned()
+    }
+
+    pub fn remove(&mut self, path_str: &str) -> Option<Arc<dyn Plugin>> {
+        self.active
+            .remove(path_str)
+            .or_else(|| self.pending.remove(path_str))
+    }
+
+    // Adds an initialized plugin, `start_ready` starts it once its dependencies are met.
+    // Warns if another plugin already writes one of its store prefixes.
+    pub fn add(&mut self, path_str: &str, plugin: Box<dyn Plugin>) {
+        for (other_path, other) in self.pending.iter().chain(self.active.iter()) {
+            if other_path == path_str {
+                continue;
+            }
+            for prefix in plugin.manifest().writes().filter(|prefix| other.manifest().writes().any(|x| x == *prefix)) {
+                warn!("{} and {} both write to the store prefix '{}'", path_str, other_path, prefix);
+            }
+        }
+        let dependencies: Vec<String> = plugin
+            .manifest()
+            .external_dependencies()
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        if !dependencies.is_empty() {
+            info!("{} depends on: {}", path_str, dependencies.join(", "));
+        }
+        self.pending.insert(path_str.to_string(), Arc::from(plugin));
+
+        let (_, cycles) = self.start_order();
+        for cycle in cycles.iter().filter(|cycle| cycle.iter().any(|x| x == path_str)) {
+            warn!(
+                "Dependency cycle between {}, starting them without ordering",
+                cycle.join(", ")
+            );
+        }
+    }
+
+    // Starts every pending plugin whose dependencies are met, upstream plugins first.
+    // Within a cycle, dependencies on other members of the cycle are ignored.
+    pub fn start_ready(&mut self) {
+        let (order, cycles) = self.start_order();
+        for path_str in order {
+            let ignore: &[String] = cycles
+                .iter()
+                .find(|cycle| cycle.contains(&path_str))
+                .map(|cycle| cycle.as_slice())
+                .unwrap_or(&[]);
+            let ready = match self.pending.get(&path_str) {
+                Some(plugin) => self.is_ready(&path_str, plugin.manifest(), ignore),
+                None => false,
+            };
+            if !ready {
+                continue;
+            }
+            if let Some(plugin) = self.pending.remove(&path_str) {
+                info!("Starting library: {}", path_str);
+                match plugin.start() {
+                    Ok(()) => {
+                        self.active.insert(path_str, plugin);
+                    }
+                    Err(err) => {
+                        error!("Failed to start library {}: {}", path_str, err);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stops every plugin, downstream plugins first, then shuts them down.
+    // In-flight tasks of all plugins share the `timeout`, they keep running while the others are shut down.
+    pub fn shutdown_all(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let (mut order, _) = self.start_order();
+        order.reverse();
+        for path_str in &order {
+            if let Some(plugin) = self.active.get(path_str) {
+                info!("Stopping library: {}", path_str);
+                if let Err(err) = plugin.stop() {
+                    error!("Failed to stop library {}: {}", path_str, err);
+                }
+            }
+        }
+        for path_str in &order {
+            if let Some(plugin) = self.remove(path_str) {
+                info!("Shutting down library: {}", path_str);
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if let Err(err) = plugin.shutdown(remaining) {
+                    error!("Failed to shut down library {}: {}", path_str, err);
+                }
+            }
+        }
+    }
+
+    // Agent dependencies have to be provided by an active plugin, key dependencies have to exist in the store.
+    // Dependencies provided by one of the `ignore` plugins count as met.
+    fn is_ready(&self, path_str: &str, manifest: &PluginManifest, ignore: &[String]) -> bool {
+        let upstream = self.upstream_of(path_str, manifest);
+        manifest
+            .external_dependencies()
+            .into_iter()
+            .all(|dependency| {
+                let ignored = ignore.iter().filter(|x| upstream.contains(*x)).any(|x| {
+                    match self.pending.get(x).or_else(|| self.active.get(x)) {
+                        Some(plugin) => match dependency {
+                            Dependency::Agent(name) => plugin.manifest().provides_agent(name),
+                            Dependency::Key(key) => plugin.manifest().writes_key(key),
+                        },
+                        None => false,
+                    }
+                });
+                ignored
+                    || match dependency {
+                        Dependency::Agent(name) => self
+                            .active
+                            .values()
+                            .any(|plugin| plugin.manifest().provides_agent(name)),
+                        Dependency::Key(key) => key_exists(manifest, key),
+                    }
+            })
+    }
+
+    // Plugins that provide an agent or write a key `path_str` depends on.
+    fn upstream_of(&self, path_str: &str, manifest: &PluginManifest) -> HashSet<String> {
+        let mut upstream = HashSet::new();
+        for dependency in manifest.external_dependencies() {
+            for (other_path, other) in self.pending.iter().chain(self.active.iter()) {
+                if other_path == path_str {
+                    continue;
+                }
+                let provides = match dependency {
+                    Dependency::Agent(name) => other.manifest().provides_agent(name),
+                    Dependency::Key(key) => other.manifest().writes_key(key),
+                };
+                if provides {
+                    upstream.insert(other_path.clone());
+                }
+            }
+        }
+        upstream
+    }
+
+    // Start order of all plugins and the dependency cycles between them, see `start_order_of`.
+    fn start_order(&self) -> (Vec<String>, Vec<Vec<String>>) {
+        let upstream: HashMap<String, HashSet<String>> = self
+            .pending
+            .iter()
+            .chain(self.active.iter())
+            .map(|(path_str, plugin)| (path_str.clone(), self.upstream_of(path_str, plugin.manifest())))
+            .collect();
+        start_order_of(upstream)
+    }
+}
+
+// Topological order (Kahn's algorithm) of the plugins given with their upstream plugins, ties are broken by path.
+// When only cycles are left, the cycles without upstream plugins outside of themselves go next.
+// Returns the order of all plugins and the members of every cycle, plugins downstream of a cycle are not part of it.
+fn start_order_of(mut upstream: HashMap<String, HashSet<String>>) -> (Vec<String>, Vec<Vec<String>>) {
+    let known: HashSet<String> = upstream.keys().cloned().collect();
+    for dependencies in upstream.values_mut() {
+        dependencies.retain(|x| known.contains(x));
+    }
+
+    let mut order = Vec::new();
+    let mut cycles = Vec::new();
+    while !upstream.is_empty() {
+        let mut ready: Vec<String> = upstream
+            .iter()
+            .filter(|(_, dependencies)| dependencies.is_empty())
+            .map(|(path_str, _)| path_str.clone())
+            .collect();
+        if ready.is_empty() {
+            // every plugin left is on a cycle or downstream of one
+            let mut sources = source_cycles(&upstream);
+            sources.sort();
+            for cycle in sources {
+                ready.extend(cycle.iter().cloned());
+                cycles.push(cycle);
+            }
+        }
+        ready.sort();
+        for path_str in &ready {
+            upstream.remove(path_str);
+        }
+        for dependencies in upstream.values_mut() {
+            for path_str in &ready {
+                dependencies.remove(path_str);
+            }
+        }
+        order.append(&mut ready);
+    }
+    (order, cycles)
+}
+
+// The cycles (strongly connected components with more than one plugin or a self dependency)
+// that only depend on their own members, each sorted by path.
+fn source_cycles(upstream: &HashMap<String, HashSet<String>>) -> Vec<Vec<String>> {
+    let reachable: HashMap<&String, HashSet<&String>> = upstream
+        .keys()
+        .map(|path_str| {
+            let mut seen = HashSet::new();
+            let mut stack: Vec<&String> = upstream[path_str].iter().collect();
+            while let Some(next) = stack.pop() {
+                if seen.insert(next) {
+                    stack.extend(upstream[next].iter());
+                }
+            }
+            (path_str, seen)
+        })
+        .collect();
+
+    let mut cycles: Vec<Vec<String>> = Vec::new();
+    for (path_str, reach) in &reachable {
+        if !reach.contains(path_str) || cycles.iter().any(|cycle| cycle.contains(*path_str)) {
+            continue;
+        }
+        let mut cycle: Vec<String> = reach
+            .iter()
+            .filter(|other| reachable[*other].contains(path_str))
+            .map(|other| (*other).clone())
+            .collect();
+        cycle.sort();
+        let is_source = cycle
+            .iter()
+            .all(|member| upstream[member].iter().all(|x| cycle.contains(x)));
+        if is_source {
+            cycles.push(cycle);
+        }
+    }
+    cycles
+}
+
+fn key_exists(manifest: &PluginManifest, key: &str) -> bool {
+    let index_key = format!("{}{}", manifest.store_index_prefix, key);
+    matches!(PERSISTENT_SLED.scan_prefix(index_key.as_bytes()).next(), Some(Ok(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_bot_common::abi::TaskStatus;
+    use rust_bot_common::manifest::AgentManifest;
+
+    struct FakePlugin(PluginManifest);
+
+    impl Plugin for FakePlugin {
+        fn manifest(&self) -> &PluginManifest {
+            &self.0
+        }
+        fn init(&self, _: &sled::Db, _: &sled::Db, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn start(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn stop(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn shutdown(&self, _: Duration) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn start_agent(&self, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn stop_agent(&self, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn restart_agent(&self, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>> {
+            Ok(Vec::new())
+        }
+        fn run_task(&self, _: &str, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    // a plugin with a single agent `name` that depends on the agents `depends_on`
+    fn plugin(name: &str, depends_on: &[&str]) -> Box<dyn Plugin> {
+        let agent = depends_on
+            .iter()
+            .fold(AgentManifest::new(name, &[], &[]), |agent, x| agent.depends_on(Dependency::Agent(x.to_string())));
+        Box::new(FakePlugin(PluginManifest {
+            name: name.to_string(),
+            version: "0.1.0".to_string(),
+            features: Vec::new(),
+            agents: vec![agent],
+            store_index_prefix: String::new(),
+        }))
+    }
+
+    fn started(registry: &PluginRegistry) -> Vec<&String> {
+        let mut started: Vec<&String> = registry.active.keys().collect();
+        started.sort();
+        started
+    }
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, HashSet<String>> {
+        edges
+            .iter()
+            .map(|(path_str, upstream)| (path_str.to_string(), upstream.iter().map(|x| x.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn chain_starts_upstream_first() {
+        let (order, cycles) = start_order_of(graph(&[("c", &["b"]), ("a", &[]), ("b", &["a"])]));
+        assert_eq!(order, vec!["a", "b", "c"]);
+        assert!(cycles.is_empty());
+    }
+
+    #[test]
+    fn diamond_breaks_ties_by_path() {
+        let (order, cycles) = start_order_of(graph(&[
+            ("d", &["b", "c"]),
+            ("c", &["a"]),
+            ("b", &["a"]),
+            ("a", &[]),
+        ]));
+        assert_eq!(order, vec!["a", "b", "c", "d"]);
+        assert!(cycles.is_empty());
+    }
+
+    #[test]
+    fn cycle_is_reported() {
+        let (order, cycles) = start_order_of(graph(&[("a", &["b"]), ("b", &["a"]), ("c", &[])]));
+        assert_eq!(order, vec!["c", "a", "b"]);
+        assert_eq!(cycles, vec![vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn downstream_of_cycle_is_not_part_of_it() {
+        let (order, cycles) = start_order_of(graph(&[
+            ("a", &["b"]),
+            ("b", &["a"]),
+            ("c", &["b"]),
+            ("d", &["c"]),
+            ("e", &["d", "f"]),
+            ("f", &["e"]),
+        ]));
+        assert_eq!(order, vec!["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(cycles, vec![vec!["a", "b"], vec!["e", "f"]]);
+    }
+
+    #[test]
+    fn is_ready_waits_for_upstream_agents() {
+        let mut registry = PluginRegistry::default();
+        registry.add("b.so", plugin("B", &["A"]));
+        registry.add("c.so", plugin("C", &["Missing"]));
+        let b = registry.pending.get("b.so").unwrap();
+        assert!(!registry.is_ready("b.so", b.manifest(), &[]));
+
+        registry.add("a.so", plugin("A", &[]));
+        registry.start_ready();
+        assert_eq!(started(&registry), vec!["a.so", "b.so"]);
+    }
+
+    #[test]
+    fn is_ready_ignores_dependencies_within_a_cycle() {
+        let mut registry = PluginRegistry::default();
+        registry.add("a.so", plugin("A", &["B"]));
+        registry.add("b.so", plugin("B", &["A"]));
+        registry.add("c.so", plugin("C", &["B", "Missing"]));
+        registry.start_ready();
+        assert_eq!(started(&registry), vec!["a.so", "b.so"]);
+    }
+}
@@ -0,0 +1,611 @@
+pub mod store;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use log::{error, info, log, Level};
+use serde::Deserialize;
+use wasmtime::{Caller, Config, Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::WasiCtxBuilder;
+
+use rust_bot_common::abi::{TaskStatus, PLUGIN_ERROR, PLUGIN_OK};
+use rust_bot_common::manifest::PluginManifest;
+use rust_bot_common::retry::{GiveUp, RetryState};
+use rust_bot_common::settings::ManagerSettings;
+use crate::Plugin;
+use rust_bot_common::store::{GLOBAL_PREFIX_TASK_STORE, REV_INDEX_PREFIX};
+use store::EntryStore;
+
+// Guest interface of a `.wasm` plugin, a WASI (preview1) reactor module.
+// Bump it whenever an export or a `rust_bot` import below changes.
+pub const WASM_ABI_VERSION: i32 = 1;
+
+// Exports:
+//   memory
+//   rust_bot_abi_version() -> i32
+//   rust_bot_alloc(len: i32) -> i32                 buffer the host writes results into, owned by the guest afterwards
+//   rust_bot_manifest() -> i64                      JSON `PluginManifest`
+//   rust_bot_tasks() -> i64                         JSON `[{"agent": .., "task": .., "update_interval_in_secs": ..}]`
+//   rust_bot_run_task(agent, agent_len, task, task_len) -> i32    PLUGIN_OK or PLUGIN_ERROR
+//   rust_bot_configure(config, config_len) -> i32   optional, receives the JSON of the per-agent settings
+//                                                   (without those of `ManagerSettings`, the host applies them),
+//                                                   PLUGIN_ERROR rejects them and the plugin is not loaded
+// Imports from module `rust_bot`:
+//   log(level: i32, msg, msg_len)                   1 = error .. 5 = trace
+//   store_get(key, key_len, ok_only: i32) -> i64    -1 if there is no (ok) entry
+//   store_insert(key, key_len, value, value_len) -> i32
+//   store_remove_all(key, key_len) -> i64           number of removed revisions, -1 on error
+//   store_keys(prefix, prefix_len) -> i64           JSON array of keys
+// An i64 result packs a guest buffer as `ptr << 32 | len`.
+// Store values are bincode encoded `Entry<T>`, the same as in `FallbackEntryStore` but without its envelope.
+
+#[derive(Deserialize, Debug, Clone)]
+struct WasmTask {
+    agent: String,
+    task: String,
+    update_interval_in_secs: u64,
+}
+
+// State of the scheduler, shared with the admin commands. Tasks are referred to by their index.
+#[derive(Default)]
+struct Schedule {
+    // a task has no next run while it is running
+    next_run: HashMap<usize, Instant>,
+    // state name and unix time it was reached (started for `pending`), as in `TaskStatus`
+    task_states: HashMap<usize, (&'static str, Option<i64>)>,
+    stopped_agents: HashSet<String>,
+}
+
+struct HostState {
+    wasi: WasiP1Ctx,
+    entry_store: Option<EntryStore>,
+}
+
+struct WasmInstance {
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl WasmInstance {
+    fn call_for_buffer(&mut self, export: &str) -> anyhow::Result<Vec<u8>> {
+        let packed = self
+            .instance
+            .get_typed_func::<(), i64>(&mut self.store, export)
+            .map_err(|_| anyhow::anyhow!("missing `{}` export", export))?
+            .call(&mut self.store, ())?;
+        let (ptr, len) = unpack(packed);
+        let mut buffer = vec![0u8; len];
+        self.memory.read(&self.store, ptr, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn configure(&mut self, agent_config: &str) -> anyhow::Result<()> {
+        let configure = match self.instance.get_typed_func::<(i32, i32), i32>(&mut self.store, "rust_bot_configure") {
+            Ok(configure) => configure,
+            Err(_) => return Ok(()),
+        };
+        let alloc: TypedFunc<i32, i32> = self.instance.get_typed_func(&mut self.store, "rust_bot_alloc")?;
+        let ptr = alloc.call(&mut self.store, agent_config.len() as i32)?;
+        self.memory.write(&mut self.store, ptr as usize, agent_config.as_bytes())?;
+        match configure.call(&mut self.store, (ptr, agent_config.len() as i32))? {
+            PLUGIN_OK => Ok(()),
+            status => Err(anyhow::anyhow!("plugin rejected the agent settings (status {})", status)),
+        }
+    }
+
+    // copies the agent and task name into guest memory and runs the task
+    fn run_task(&mut self, task: &WasmTask) -> anyhow::Result<i32> {
+        // an interrupted run leaves the deadline behind, the guest calls below would trap at once
+        self.store.set_epoch_deadline(1);
+        let alloc: TypedFunc<i32, i32> = self.instance.get_typed_func(&mut self.store, "rust_bot_alloc")?;
+        let run_task: TypedFunc<(i32, i32, i32, i32), i32> =
+            self.instance.get_typed_func(&mut self.store, "rust_bot_run_task")?;
+        let mut args = Vec::new();
+        for arg in [task.agent.as_bytes(), task.task.as_bytes()] {
+            let ptr = alloc.call(&mut self.store, arg.len() as i32)?;
+            self.memory.write(&mut self.store, ptr as usize, arg)?;
+            args.push((ptr, arg.len() as i32));
+        }
+        run_task.call(&mut self.store, (args[0].0, args[0].1, args[1].0, args[1].1))
+    }
+}
+
+// A sandboxed plugin, the module only reaches the host through WASI (without preopened directories)
+// and the `rust_bot` imports. Every load creates a fresh instance, so nothing leaks across hot reloads.
+// Its tasks are scheduled by the host, with the timeouts and retry policies of `ManagerSettings` like `AgentManager`.
+pub struct WasmPlugin {
+    manifest: PluginManifest,
+    tasks: Vec<WasmTask>,
+    // per agent, from its section of the agent settings
+    settings: Mutex<HashMap<String, ManagerSettings>>,
+    engine: Engine,
+    instance: Arc<Mutex<Option<WasmInstance>>>,
+    cancellation_flag: Arc<AtomicBool>,
+    schedule: Arc<Mutex<Schedule>>,
+    scheduler: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WasmPlugin {
+    pub fn new(library_path: &str) -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        // `shutdown` increments the epoch to interrupt a running task
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, library_path)?;
+
+        let mut linker: Linker<HostState> = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |state: &mut HostState| &mut state.wasi)?;
+        add_host_functions(&mut linker)?;
+
+        let wasi = WasiCtxBuilder::new().inherit_stdout().inherit_stderr().build_p1();
+        let mut store = Store::new(&engine, HostState { wasi, entry_store: None });
+        store.set_epoch_deadline(1);
+        let instance = linker.instantiate(&mut store, &module)?;
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            initialize.call(&mut store, ())?;
+        }
+
+        let abi_version = instance
+            .get_typed_func::<(), i32>(&mut store, "rust_bot_abi_version")
+            .map_err(|_| anyhow::anyhow!("missing `rust_bot_abi_version` export, not a rust-bot WASM plugin"))?
+            .call(&mut store, ())?;
+        if abi_version != WASM_ABI_VERSION {
+            return Err(anyhow::anyhow!(
+                "plugin WASM ABI version {} does not match host WASM ABI version {}",
+                abi_version,
+                WASM_ABI_VERSION
+            ));
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("missing `memory` export"))?;
+        let mut instance = WasmInstance { store, instance, memory };
+
+        let manifest_json = instance.call_for_buffer("rust_bot_manifest")?;
+        let mut manifest = PluginManifest::from_json(&String::from_utf8_lossy(&manifest_json))?;
+        // the host owns the store layout of WASM plugins
+        manifest.store_index_prefix = format!("{}{}", GLOBAL_PREFIX_TASK_STORE, REV_INDEX_PREFIX);
+
+        let tasks_json = instance.call_for_buffer("rust_bot_tasks")?;
+        let tasks: Vec<WasmTask> = serde_json::from_slice(&tasks_json)
+            .map_err(|err| anyhow::anyhow!("invalid `rust_bot_tasks`: {}", err))?;
+        if let Some(task) = tasks.iter().find(|task| !manifest.provides_agent(&task.agent)) {
+            return Err(anyhow::anyhow!("task {} belongs to unknown agent {}", task.task, task.agent));
+        }
+
+        Ok(WasmPlugin {
+            manifest,
+            tasks,
+            settings: Mutex::new(HashMap::new()),
+            engine,
+            instance: Arc::new(Mutex::new(Some(instance))),
+            cancellation_flag: Arc::new(AtomicBool::new(false)),
+            schedule: Arc::new(Mutex::new(Schedule::default())),
+            scheduler: Mutex::new(None),
+        })
+    }
+
+    fn is_running(&self) -> bool {
+        self.scheduler.lock().unwrap().is_some() && !self.cancellation_flag.load(Ordering::SeqCst)
+    }
+
+    // indices of the tasks of a running agent
+    fn agent_tasks(&self, agent: &str) -> anyhow::Result<Vec<usize>> {
+        if !self.manifest.provides_agent(agent) {
+            return Err(anyhow::anyhow!("unknown agent `{}`", agent));
+        }
+        if !self.is_running() {
+            return Err(anyhow::anyhow!("plugin {} is stopped", self.manifest.name));
+        }
+        Ok((0..self.tasks.len()).filter(|i| self.tasks[*i].agent == agent).collect())
+    }
+}
+
+#[async_trait]
+impl Plugin for WasmPlugin {
+    fn manifest(&self) -> &PluginManifest {
+        &self.manifest
+    }
+
+    fn init(&self, persistent_sled: &sled::Db, _temporary_sled: &sled::Db, agent_config: &str) -> anyhow::Result<()> {
+        let mut instance = self.instance.lock().unwrap();
+        let instance = instance
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("plugin was shut down"))?;
+        instance.store.data_mut().entry_store = Some(EntryStore::new(persistent_sled, GLOBAL_PREFIX_TASK_STORE));
+
+        // the guest gets the agents' own settings, the host keeps those of their managers
+        let mut agent_config: serde_json::Map<String, serde_json::Value> = serde_json::from_str(agent_config)?;
+        let mut settings = HashMap::new();
+        for agent in &self.manifest.agents {
+            if let Some(section) = agent_config.get_mut(&agent.name) {
+                let (agent_settings, manager_settings) = ManagerSettings::split(section)
+                    .map_err(|err| anyhow::anyhow!("invalid settings for {}: {}", agent.name, err))?;
+                *section = agent_settings;
+                settings.insert(agent.name.clone(), manager_settings);
+            }
+        }
+        instance.configure(&serde_json::Value::Object(agent_config).to_string())?;
+        *self.settings.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    fn start(&self) -> anyhow::Result<()> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        if scheduler.is_some() && !self.cancellation_flag.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // a stopped scheduler finishes its in-flight task first
+        if let Some(stopped) = scheduler.take() {
+            let _ = stopped.join();
+        }
+        self.cancellation_flag.store(false, Ordering::SeqCst);
+        let now = Instant::now();
+        *self.schedule.lock().unwrap() = Schedule {
+            next_run: (0..self.tasks.len()).map(|i| (i, now)).collect(),
+            ..Schedule::default()
+        };
+        let scheduled = ScheduledTasks {
+            tasks: self.tasks.clone(),
+            settings: self.settings.lock().unwrap().clone(),
+            engine: self.engine.clone(),
+        };
+        let instance = self.instance.clone();
+        let cancellation_flag = self.cancellation_flag.clone();
+        let schedule = self.schedule.clone();
+        let name = self.manifest.name.clone();
+        *scheduler = Some(std::thread::spawn(move || {
+            run_scheduler(&name, scheduled, instance, cancellation_flag, schedule)
+        }));
+        Ok(())
+    }
+
+    // no new task is started, the in-flight one keeps running
+    fn stop(&self) -> anyhow::Result<()> {
+        self.cancellation_flag.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // Interrupts the in-flight task once `timeout` passed.
+    fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        self.cancellation_flag.store(true, Ordering::SeqCst);
+        if let Some(scheduler) = self.scheduler.lock().unwrap().take() {
+            let deadline = Instant::now() + timeout;
+            while !scheduler.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            self.engine.increment_epoch();
+            scheduler
+                .join()
+                .map_err(|_| anyhow::anyhow!("WASM scheduler of {} panicked", self.manifest.name))?;
+        }
+        self.instance.lock().unwrap().take();
+        Ok(())
+    }
+
+    // The agent's tasks that are not running are due right away, including those that gave up.
+    fn start_agent(&self, agent: &str) -> anyhow::Result<()> {
+        let agent_tasks = self.agent_tasks(agent)?;
+        let mut schedule = self.schedule.lock().unwrap();
+        if !schedule.stopped_agents.remove(agent) {
+            return Err(anyhow::anyhow!("agent `{}` is already running", agent));
+        }
+        let now = Instant::now();
+        for i in agent_tasks {
+            let running = matches!(schedule.task_states.get(&i), Some(("pending", _)));
+            if !running {
+                schedule.next_run.insert(i, now);
+            }
+        }
+        Ok(())
+    }
+
+    // An in-flight task of the agent keeps running.
+    fn stop_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.agent_tasks(agent)?;
+        self.schedule.lock().unwrap().stopped_agents.insert(agent.to_string());
+        Ok(())
+    }
+
+    fn restart_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.stop_agent(agent)?;
+        self.start_agent(agent)
+    }
+
+    fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>> {
+        let settings = self.settings.lock().unwrap();
+        let schedule = self.schedule.lock().unwrap();
+        let now = unix_time();
+        let mut task_states: Vec<TaskStatus> = schedule
+            .task_states
+            .iter()
+            .map(|(i, (state, timestamp))| {
+                let agent = &self.tasks[*i].agent;
+                let hung_after_in_secs = settings
+                    .get(agent)
+                    .map(|settings| settings.hung_after_in_secs)
+                    .unwrap_or_else(|| ManagerSettings::default().hung_after_in_secs);
+                let state = match (*state, timestamp) {
+                    ("pending", Some(since)) if now - since > hung_after_in_secs => "hung",
+                    (state, _) => state,
+                };
+                TaskStatus {
+                    agent: agent.clone(),
+                    task: self.tasks[*i].task.clone(),
+                    state: state.to_string(),
+                    timestamp: *timestamp,
+                }
+            })
+            .collect();
+        task_states.sort_by(|a, b| (&a.agent, &a.task).cmp(&(&b.agent, &b.task)));
+        Ok(task_states)
+    }
+
+    fn run_task(&self, agent: &str, task: &str) -> anyhow::Result<()> {
+        let i = self
+            .agent_tasks(agent)?
+            .into_iter()
+            .find(|i| self.tasks[*i].task == task)
+            .ok_or_else(|| anyhow::anyhow!("unknown task `{}`", task))?;
+        let mut schedule = self.schedule.lock().unwrap();
+        if schedule.stopped_agents.contains(agent) {
+            return Err(anyhow::anyhow!("agent `{}` is not running", agent));
+        }
+        match schedule.next_run.get_mut(&i) {
+            Some(next_run) => {
+                *next_run = Instant::now();
+                Ok(())
+            }
+            None => Err(anyhow::anyhow!("task `{}` is already running", task)),
+        }
+    }
+}
+
+// What `run_scheduler` runs, with the settings of the agents' managers.
+struct ScheduledTasks {
+    tasks: Vec<WasmTask>,
+    settings: HashMap<String, ManagerSettings>,
+    // incremented to interrupt a task that runs longer than its timeout
+    engine: Engine,
+}
+
+impl ScheduledTasks {
+    fn settings_of(&self, task: &WasmTask) -> ManagerSettings {
+        self.settings.get(&task.agent).cloned().unwrap_or_default()
+    }
+}
+
+// Runs due tasks one after another until cancelled, tasks of stopped agents are skipped.
+// A resolved task runs again after its update interval. A failed or timed out one is retried
+// with the retry policy of its manager settings, `RetryPolicy::default()` if none is set.
+fn run_scheduler(
+    name: &str,
+    scheduled: ScheduledTasks,
+    instance: Arc<Mutex<Option<WasmInstance>>>,
+    cancellation_flag: Arc<AtomicBool>,
+    schedule: Arc<Mutex<Schedule>>,
+) {
+    let tasks = &scheduled.tasks;
+    info!("{}: tasks added: {:?}", name, tasks.iter().map(|task| &task.task).collect::<Vec<_>>());
+    let mut retries: HashMap<usize, RetryState> = HashMap::new();
+
+    while !cancellation_flag.load(Ordering::SeqCst) {
+        let due = {
+            let mut schedule = schedule.lock().unwrap();
+            let due = schedule
+                .next_run
+                .iter()
+                .filter(|(i, at)| **at <= Instant::now() && !schedule.stopped_agents.contains(&tasks[**i].agent))
+                .map(|(i, _)| *i)
+                .min();
+            if let Some(i) = due {
+                schedule.next_run.remove(&i);
+                schedule.task_states.insert(i, ("pending", Some(unix_time())));
+            }
+            due
+        };
+        let i = match due {
+            Some(i) => i,
+            None => {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let task = &tasks[i];
+        let settings = scheduled.settings_of(task);
+        let timed_out = AtomicBool::new(false);
+        let result = {
+            let mut instance = instance.lock().unwrap();
+            let instance = match instance.as_mut() {
+                Some(instance) => instance,
+                None => return,
+            };
+            let (done, finished) = mpsc::channel::<()>();
+            std::thread::scope(|scope| {
+                if let Some(timeout) = settings.task_timeout(&task.task) {
+                    let (timed_out, engine) = (&timed_out, &scheduled.engine);
+                    scope.spawn(move || {
+                        if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                            timed_out.store(true, Ordering::SeqCst);
+                            engine.increment_epoch();
+                        }
+                    });
+                }
+                let result = instance.run_task(task);
+                drop(done);
+                result
+            })
+        };
+        if cancellation_flag.load(Ordering::SeqCst) {
+            break;
+        }
+        let (state, next_run_in_secs) = match result {
+            Ok(PLUGIN_OK) => {
+                info!("task resolved: {}::{}", task.agent, task.task);
+                retries.remove(&i);
+                ("resolved", Some(task.update_interval_in_secs))
+            }
+            result => {
+                let state = if timed_out.load(Ordering::SeqCst) {
+                    error!("task timed out: {}::{}", task.agent, task.task);
+                    "timed_out"
+                } else {
+                    match result {
+                        Ok(status) => error!("task failed: {}::{} (status {})", task.agent, task.task, status),
+                        Err(err) => error!("task failed: {}::{}: {}", task.agent, task.task, err),
+                    }
+                    "failed"
+                };
+                let retry_policy = settings.retry_policy(&task.task).unwrap_or_default();
+                let retry = retries.entry(i).or_default();
+                match retry_policy.next_retry(retry) {
+                    Some(delay_in_secs) => (state, Some(delay_in_secs as u64)),
+                    None if retry_policy.give_up == GiveUp::NextRun => {
+                        error!("task gave up after {} attempts, waiting for its next run: {}::{}", retry.attempts, task.agent, task.task);
+                        retries.remove(&i);
+                        (state, Some(task.update_interval_in_secs))
+                    }
+                    None => {
+                        error!("task gave up after {} attempts: {}::{}", retry.attempts, task.agent, task.task);
+                        retries.remove(&i);
+                        (state, None)
+                    }
+                }
+            }
+        };
+        let mut schedule = schedule.lock().unwrap();
+        schedule.task_states.insert(i, (state, Some(unix_time())));
+        if let Some(next_run_in_secs) = next_run_in_secs {
+            schedule.next_run.insert(i, Instant::now() + Duration::from_secs(next_run_in_secs));
+        }
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    (((packed as u64) >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize)
+}
+
+fn read_guest(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<Vec<u8>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow::anyhow!("missing `memory` export"))?;
+    let mut buffer = vec![0u8; len as usize];
+    memory.read(&caller, ptr as usize, &mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_guest_str(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<String> {
+    Ok(String::from_utf8(read_guest(caller, ptr, len)?)?)
+}
+
+// copies `data` into a buffer allocated by the guest, returns it packed
+fn write_guest(caller: &mut Caller<'_, HostState>, data: &[u8]) -> anyhow::Result<i64> {
+    let alloc = caller
+        .get_export("rust_bot_alloc")
+        .and_then(|export| export.into_func())
+        .ok_or_else(|| anyhow::anyhow!("missing `rust_bot_alloc` export"))?
+        .typed::<i32, i32>(&caller)?;
+    let ptr = alloc.call(&mut *caller, data.len() as i32)?;
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow::anyhow!("missing `memory` export"))?;
+    memory.write(&mut *caller, ptr as usize, data)?;
+    Ok(((ptr as u32 as i64) << 32) | data.len() as i64)
+}
+
+fn entry_store<'a>(caller: &'a Caller<'_, HostState>) -> anyhow::Result<&'a EntryStore> {
+    caller
+        .data()
+        .entry_store
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("store accessed before init"))
+}
+
+fn add_host_functions(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
+    linker.func_wrap(
+        "rust_bot",
+        "log",
+        |mut caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let level = match level {
+                1 => Level::Error,
+                2 => Level::Warn,
+                3 => Level::Info,
+                4 => Level::Debug,
+                _ => Level::Trace,
+            };
+            let message = read_guest_str(&mut caller, ptr, len)?;
+            log!(target: "rust_bot::wasm::guest", level, "{}", message);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "rust_bot",
+        "store_get",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32, ok_only: i32| -> anyhow::Result<i64> {
+            let key = read_guest_str(&mut caller, ptr, len)?;
+            match entry_store(&caller)?.get(&key, ok_only != 0) {
+                Ok(Some(value)) => write_guest(&mut caller, &value),
+                Ok(None) => Ok(-1),
+                Err(err) => {
+                    error!("store_get {}: {}", key, err);
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rust_bot",
+        "store_insert",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32| -> anyhow::Result<i32> {
+            let key = read_guest_str(&mut caller, key_ptr, key_len)?;
+            let value = read_guest(&mut caller, value_ptr, value_len)?;
+            match entry_store(&caller)?.insert(&key, &value) {
+                Ok(()) => Ok(PLUGIN_OK),
+                Err(err) => {
+                    error!("store_insert {}: {}", key, err);
+                    Ok(PLUGIN_ERROR)
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rust_bot",
+        "store_remove_all",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i64> {
+            let key = read_guest_str(&mut caller, ptr, len)?;
+            match entry_store(&caller)?.remove_all(&key) {
+                Ok(removed) => Ok(removed as i64),
+                Err(err) => {
+                    error!("store_remove_all {}: {}", key, err);
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rust_bot",
+        "store_keys",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i64> {
+            let prefix = read_guest_str(&mut caller, ptr, len)?;
+            let keys = entry_store(&caller)?.keys(&prefix)?;
+            write_guest(&mut caller, serde_json::to_string(&keys)?.as_bytes())
+        },
+    )?;
+    Ok(())
+}
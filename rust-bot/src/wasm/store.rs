@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashSet};
+
+use log::trace;
+use rust_bot_common::store::{self, ENVELOPE_MAGIC};
+use sled::IVec;
+
+// Revisioned key value store with the layout of `rust_bot_common::store`, the same as `FallbackEntryStore` in rust-bot-plugin,
+// so native and WASM agents can read each other's entries.
+// Values are bincode encoded `Entry<T>`, the host only looks at the leading u32 (the `Result` variant, 0 is `Ok`).
+// Native agents store them in an envelope with the name and version of their schema, WASM guests get and write them without.
+pub struct EntryStore {
+    db: sled::Db,
+    global_prefix: String,
+}
+
+// Keys and revisions removed by `EntryStore::purge`, or that a dry run would remove.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Purged {
+    pub keys: u64,
+    pub revisions: u64,
+    // revisions no revision index points to, e.g. left by an import that failed
+    pub orphans: u64,
+}
+
+// An envelope holds ENVELOPE_MAGIC, the schema name (u64 length, UTF-8), its version (u32) and the `Entry<T>` (u64 length, bytes).
+
+impl EntryStore {
+    pub fn new(db: &sled::Db, global_prefix: &str) -> Self {
+        EntryStore {
+            db: db.clone(),
+            global_prefix: global_prefix.to_string(),
+        }
+    }
+
+    fn rev_index_key(&self, key: &str) -> String {
+        format!("{}{}", self.global_prefix, store::rev_index_key(key))
+    }
+
+    fn revision_key(&self, key: &str, index: u64) -> String {
+        format!("{}{}", self.global_prefix, store::revision_key(key, index))
+    }
+
+    fn current_revision(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        Self::revision_of(self.db.get(self.rev_index_key(key))?.as_deref())
+    }
+
+    fn revision_of(rev_index: Option<&[u8]>) -> anyhow::Result<Option<u64>> {
+        match rev_index {
+            Some(val) => Ok(Some(u64::from_be_bytes(val.try_into()?))),
+            None => Ok(None),
+        }
+    }
+
+    // Writes `batch` if the revision index of `key` is still `rev_index`, see `store::apply_if`.
+    fn apply_if(&self, key: &str, rev_index: Option<IVec>, batch: &[(Vec<u8>, Option<Vec<u8>>)]) -> anyhow::Result<bool> {
+        let expected = [(self.rev_index_key(key).into_bytes(), rev_index.map(|x| x.to_vec()))];
+        Ok(store::apply_if(&self.db, &expected, batch)?)
+    }
+
+    // the `Entry<T>` of a stored value
+    pub fn entry_of(value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if !value.starts_with(&ENVELOPE_MAGIC) {
+            return Ok(value.to_vec());
+        }
+        let (_, _, _, entry): ([u8; 4], String, u32, Vec<u8>) = bincode::deserialize(value)?;
+        Ok(entry)
+    }
+
+    pub fn is_ok_entry(value: &[u8]) -> bool {
+        Self::entry_of(value)
+            .map(|entry| entry.starts_with(&[0, 0, 0, 0]))
+            .unwrap_or(false)
+    }
+
+    // `Entry<T>` ends with its timestamp, an i64 (unix time in seconds), and so does its envelope
+    pub fn timestamp(entry: &[u8]) -> Option<i64> {
+        let bytes = entry.len().checked_sub(8).map(|start| &entry[start..])?;
+        Some(i64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    pub fn contains_key(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.current_revision(key)?.is_some())
+    }
+
+    // All stored revisions of `key`, oldest first.
+    pub fn revisions(&self, key: &str) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+        let mut revisions = Vec::new();
+        if let Some(index) = self.current_revision(key)? {
+            for i in (0..=index).rev() {
+                match self.db.get(self.revision_key(key, i))? {
+                    Some(val) => revisions.push((i, val.to_vec())),
+                    None => break,
+                }
+            }
+        }
+        revisions.reverse();
+        Ok(revisions)
+    }
+
+    // Writes the given revisions (key, index, entry) in one batch, the keys only show once `restore_rev_indexes` points to them.
+    pub fn restore(&self, revisions: &[(String, u64, Vec<u8>)]) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        for (key, index, entry) in revisions {
+            batch.insert(self.revision_key(key, *index).as_bytes(), entry.as_slice());
+        }
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    // Makes the given revision of each key the current one, in one batch.
+    pub fn restore_rev_indexes(&self, rev_indexes: &BTreeMap<String, u64>) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        for (key, index) in rev_indexes {
+            batch.insert(self.rev_index_key(key).as_bytes(), &index.to_be_bytes()[..]);
+        }
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    // latest revision, or the latest `Ok` revision if `ok_only` is set (`RetrievalMethod::GetOk`)
+    pub fn get(&self, key: &str, ok_only: bool) -> anyhow::Result<Option<Vec<u8>>> {
+        let index = match self.current_revision(key)? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        for i in (0..=index).rev() {
+            match self.db.get(self.revision_key(key, i))? {
+                Some(val) if !ok_only || Self::is_ok_entry(&val) => return Ok(Some(Self::entry_of(&val)?)),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    // Pushes a new revision and removes the revisions before the last `Ok` one.
+    // Called by WASM agents on other threads too, every writer gets a revision of its own.
+    pub fn insert(&self, key: &str, entry: &[u8]) -> anyhow::Result<()> {
+        let next_index = loop {
+            let rev_index = self.db.get(self.rev_index_key(key))?;
+            let next_index = match Self::revision_of(rev_index.as_deref())? {
+                Some(index) => index.checked_add(1).ok_or_else(|| anyhow::anyhow!("revision overflow for key {}", key))?,
+                None => 0,
+            };
+            let batch = [
+                (self.revision_key(key, next_index).into_bytes(), Some(entry.to_vec())),
+                (self.rev_index_key(key).into_bytes(), Some(next_index.to_be_bytes().to_vec())),
+            ];
+            if self.apply_if(key, rev_index, &batch)? {
+                break next_index;
+            }
+            trace!("push: key: {}, revision taken by another writer, retrying", key);
+        };
+        trace!("push: key: {}, rev: {}", key, next_index);
+
+        loop {
+            let rev_index = self.db.get(self.rev_index_key(key))?;
+            let mut smallest_required_index = next_index;
+            for i in (0..=next_index).rev() {
+                if let Some(val) = self.db.get(self.revision_key(key, i))? {
+                    if Self::is_ok_entry(&val) {
+                        smallest_required_index = i;
+                        break;
+                    }
+                }
+            }
+            let mut batch = Vec::new();
+            for i in (0..smallest_required_index).rev() {
+                let revision_key = self.revision_key(key, i);
+                if !self.db.contains_key(&revision_key)? {
+                    break;
+                }
+                batch.push((revision_key.into_bytes(), None));
+            }
+            // the revisions to remove were chosen as of `rev_index`
+            if batch.is_empty() || self.apply_if(key, rev_index, &batch)? {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn remove_all(&self, key: &str) -> anyhow::Result<u64> {
+        loop {
+            let rev_index = self.db.get(self.rev_index_key(key))?;
+            let max_index = match Self::revision_of(rev_index.as_deref())? {
+                Some(index) => index,
+                None => return Ok(0),
+            };
+            let mut batch = vec![(self.rev_index_key(key).into_bytes(), None)];
+            batch.extend((0..=max_index).map(|i| (self.revision_key(key, i).into_bytes(), None)));
+            if self.apply_if(key, rev_index, &batch)? {
+                return Ok(max_index + 1);
+            }
+        }
+    }
+
+    // Removes every key that starts with `key_prefix` with all its revisions in one batch, only counts them if `dry_run` is set.
+    // Revisions of these keys that no revision index points to are removed as well.
+    pub fn purge(&self, key_prefix: &str, dry_run: bool) -> anyhow::Result<Purged> {
+        let mut purged = Purged::default();
+        let mut batch = sled::Batch::default();
+        let mut revision_keys = HashSet::new();
+        for key in self.keys(key_prefix)? {
+            for (index, _) in self.revisions(&key)? {
+                let revision_key = self.revision_key(&key, index);
+                batch.remove(revision_key.as_bytes());
+                revision_keys.insert(revision_key);
+                purged.revisions += 1;
+            }
+            batch.remove(self.rev_index_key(&key).as_bytes());
+            purged.keys += 1;
+        }
+        for item in self.db.scan_prefix(format!("{}{}{}", self.global_prefix, store::KEY_PREFIX, key_prefix)) {
+            let (revision_key, _) = item?;
+            let revision_key = String::from_utf8_lossy(&revision_key).into_owned();
+            // `key_<prefix>` also holds the revisions of shorter keys, e.g. of `gov` for the prefix `gov_`
+            let orphan = revision_key
+                .strip_prefix(&self.global_prefix)
+                .and_then(store::key_of_revision)
+                .is_some_and(|key| key.starts_with(key_prefix));
+            if orphan && !revision_keys.contains(&revision_key) {
+                batch.remove(revision_key.as_bytes());
+                purged.orphans += 1;
+            }
+        }
+        if !dry_run {
+            self.db.apply_batch(batch)?;
+        }
+        Ok(purged)
+    }
+
+    pub fn keys(&self, key_prefix: &str) -> anyhow::Result<Vec<String>> {
+        let index_prefix = self.rev_index_key("");
+        self.db
+            .scan_prefix(self.rev_index_key(key_prefix))
+            .map(|item| {
+                let (key, _) = item?;
+                Ok(String::from_utf8_lossy(&key[index_prefix.len()..]).into_owned())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    const THREADS: u64 = 8;
+    const WRITES: u64 = 50;
+
+    fn in_memory_store() -> EntryStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        EntryStore::new(&db, "test_")
+    }
+
+    // a bincode encoded `Entry<u64>`, `Ok` or `Err`
+    fn entry(ok: bool, value: u64) -> Vec<u8> {
+        bincode::serialize(&(if ok { 0u32 } else { 1u32 }, value, 0i64)).unwrap()
+    }
+
+    // runs `write(thread, i)` WRITES times on every thread, all threads start at once
+    fn hammer<F>(store: &Arc<EntryStore>, write: F)
+    where
+        F: Fn(&EntryStore, u64, u64) + Send + Sync + 'static,
+    {
+        let write = Arc::new(write);
+        let barrier = Arc::new(Barrier::new(THREADS as usize));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let store = store.clone();
+                let write = write.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0..WRITES {
+                        write(&store, thread, i);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    fn revision_keys(store: &EntryStore, key: &str) -> Vec<String> {
+        let prefix = format!("{}{}{}_rev_", store.global_prefix, store::KEY_PREFIX, key);
+        store.db.scan_prefix(prefix).map(|item| String::from_utf8(item.unwrap().0.to_vec()).unwrap()).collect()
+    }
+
+    #[test]
+    fn concurrent_inserts_get_a_revision_each() {
+        let store = Arc::new(in_memory_store());
+        hammer(&store, |store, thread, i| {
+            store.insert("key", &entry(thread % 2 == 0, thread * WRITES + i)).unwrap();
+        });
+
+        // no two writers claimed the same revision
+        let index = store.current_revision("key").unwrap().unwrap();
+        assert_eq!(index, THREADS * WRITES - 1);
+        // the revisions from the last ok one up to the latest, without gaps, none was left behind
+        let revisions = store.revisions("key").unwrap();
+        assert_eq!(revisions.last().unwrap().0, index);
+        assert!(EntryStore::is_ok_entry(&revisions[0].1));
+        assert!(revisions.windows(2).all(|pair| pair[0].0 + 1 == pair[1].0));
+        assert_eq!(revision_keys(&store, "key").len(), revisions.len());
+    }
+
+    #[test]
+    fn concurrent_inserts_and_removals_leave_no_orphans() {
+        let store = Arc::new(in_memory_store());
+        hammer(&store, |store, thread, i| {
+            if thread % 2 == 0 {
+                store.remove_all("key").unwrap();
+            } else {
+                store.insert("key", &entry(true, i)).unwrap();
+            }
+        });
+
+        match store.current_revision("key").unwrap() {
+            // the latest revision is the one the index points to
+            Some(index) => assert_eq!(revision_keys(&store, "key"), vec![store.revision_key("key", index)]),
+            None => assert!(revision_keys(&store, "key").is_empty()),
+        }
+        store.remove_all("key").unwrap();
+        assert_eq!(store.current_revision("key").unwrap(), None);
+        assert!(revision_keys(&store, "key").is_empty());
+    }
+
+    #[test]
+    fn purge_removes_orphaned_revisions() {
+        let store = in_memory_store();
+        store.insert("gov_a", &entry(true, 1)).unwrap();
+        store.insert("gov_a", &entry(false, 2)).unwrap();
+        store.insert("gov", &entry(true, 3)).unwrap();
+        // a revision without an index and one above the index of `gov_a`
+        store.db.insert(store.revision_key("gov_b", 0), entry(true, 4)).unwrap();
+        store.db.insert(store.revision_key("gov_a", 5), entry(true, 5)).unwrap();
+
+        let expected = Purged { keys: 1, revisions: 2, orphans: 2 };
+        assert_eq!(store.purge("gov_", true).unwrap(), expected);
+        assert_eq!(revision_keys(&store, "gov_a").len(), 3);
+        assert_eq!(store.purge("gov_", false).unwrap(), expected);
+
+        assert!(revision_keys(&store, "gov_a").is_empty());
+        assert!(revision_keys(&store, "gov_b").is_empty());
+        assert_eq!(store.get("gov", false).unwrap(), Some(entry(true, 3)));
+        assert_eq!(store.purge("gov_", false).unwrap(), Purged::default());
+    }
+}
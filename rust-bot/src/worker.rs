@@ -0,0 +1,434 @@
+use std::ffi::CString;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use libloading::Library;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use rust_bot_common::abi::TaskStatus;
+use rust_bot_common::ipc::{read_frame, write_frame, Channel};
+use rust_bot_common::manifest::PluginManifest;
+
+use crate::ipc::serve_connection;
+use crate::{abi, Plugin};
+
+const SOCKET_DIR: &str = "./bin/tmp/ipc";
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+// how long a worker may take beyond the shutdown timeout before it is killed
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60 * 5);
+// a worker that ran this long without crashing resets the restart backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// numbers the worker sockets of this process, see `WorkerPlugin::load`
+static SOCKET_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Sent by the host on the control connection.
+// `Init` comes first and is answered with `Ready` or `Refused`, every other command with `Status`.
+#[derive(Serialize, Deserialize, Debug)]
+enum Control {
+    Init { agent_config: String },
+    Start,
+    Stop,
+    Shutdown { timeout_in_ms: u64 },
+    StartAgent { agent: String },
+    StopAgent { agent: String },
+    RestartAgent { agent: String },
+    // answered with `TaskStates`
+    TaskStates,
+    RunTask { agent: String, task: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum WorkerMessage {
+    // the plugin passed the ABI checks and was initialized, carries its manifest
+    Ready(PluginManifest),
+    Refused(String),
+    Status(Result<(), String>),
+    TaskStates(Result<Vec<TaskStatus>, String>),
+}
+
+struct WorkerState {
+    library_path: String,
+    agent_config: String,
+    socket_path: PathBuf,
+    control: Mutex<Option<UnixStream>>,
+    started: AtomicBool,
+    shutting_down: AtomicBool,
+    // the worker exited for good, its store connections are no longer accepted
+    closed: AtomicBool,
+    shutdown_timeout_in_ms: AtomicU64,
+}
+
+// A plugin running in its own `rust-bot worker` process.
+// A crash only takes down the worker, the supervisor restarts it with exponential backoff.
+// The worker never opens the sled databases, its store calls are served by the host (see ipc.rs).
+pub struct WorkerPlugin {
+    manifest: PluginManifest,
+    state: Arc<WorkerState>,
+    supervisor: Mutex<Option<JoinHandle<()>>>,
+    acceptor: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WorkerPlugin {
+    // Spawns the worker and waits until the plugin is initialized.
+    pub fn load(library_path: &str, agent_config: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(SOCKET_DIR)?;
+        let file_stem = Path::new(library_path)
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "plugin".to_string());
+        // unique per load, the accept thread of a reloaded plugin removes its socket once the old worker exited,
+        // after the new one is bound
+        let socket_path = Path::new(SOCKET_DIR).join(format!(
+            "{}-{}-{}.sock",
+            file_stem,
+            std::process::id(),
+            SOCKET_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let listener = UnixListener::bind(&socket_path)?;
+        listener.set_nonblocking(true)?;
+
+        let state = Arc::new(WorkerState {
+            library_path: library_path.to_string(),
+            agent_config: agent_config.to_string(),
+            socket_path,
+            control: Mutex::new(None),
+            started: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            shutdown_timeout_in_ms: AtomicU64::new(0),
+        });
+
+        let (control_sender, control_receiver) = channel();
+        let accept_state = state.clone();
+        // a worker that shuts down may still connect, e.g. to watch a prefix again
+        let acceptor = std::thread::spawn(move || {
+            while !accept_state.closed.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = stream.set_nonblocking(false);
+                        let control_sender = control_sender.clone();
+                        std::thread::spawn(move || serve_connection(stream, control_sender));
+                    }
+                    Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(err) => {
+                        error!("Failed to accept worker connection: {}", err);
+                        std::thread::sleep(Duration::from_millis(50));
+                    }
+                }
+            }
+            let _ = std::fs::remove_file(&accept_state.socket_path);
+        });
+
+        let (child, manifest) = match spawn_worker(&state, &control_receiver) {
+            Ok(spawned) => spawned,
+            Err(err) => {
+                state.shutting_down.store(true, Ordering::SeqCst);
+                state.closed.store(true, Ordering::SeqCst);
+                return Err(err);
+            }
+        };
+
+        let supervisor_state = state.clone();
+        let supervisor = std::thread::spawn(move || supervise(supervisor_state, child, control_receiver));
+
+        Ok(WorkerPlugin {
+            manifest,
+            state,
+            supervisor: Mutex::new(Some(supervisor)),
+            acceptor: Mutex::new(Some(acceptor)),
+        })
+    }
+
+    fn request(&self, control: Control) -> anyhow::Result<WorkerMessage> {
+        let mut guard = self.state.control.lock().unwrap();
+        let stream = guard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("worker for {} is not running", self.state.library_path))?;
+        write_frame(stream, &control)?;
+        Ok(read_frame::<WorkerMessage>(stream)?)
+    }
+
+    fn send(&self, control: Control) -> anyhow::Result<()> {
+        match self.request(control)? {
+            WorkerMessage::Status(result) => result.map_err(|err| anyhow::anyhow!(err)),
+            other => Err(anyhow::anyhow!("unexpected worker message: {:?}", other)),
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for WorkerPlugin {
+    fn manifest(&self) -> &PluginManifest {
+        &self.manifest
+    }
+
+    // The worker initializes the plugin itself, with the host as its store.
+    fn init(&self, _persistent_sled: &sled::Db, _temporary_sled: &sled::Db, _agent_config: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn start(&self) -> anyhow::Result<()> {
+        self.state.started.store(true, Ordering::SeqCst);
+        self.send(Control::Start)
+    }
+
+    fn stop(&self) -> anyhow::Result<()> {
+        self.state.started.store(false, Ordering::SeqCst);
+        self.send(Control::Stop)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        let timeout_in_ms = timeout.as_millis() as u64;
+        self.state.shutdown_timeout_in_ms.store(timeout_in_ms, Ordering::SeqCst);
+        self.state.shutting_down.store(true, Ordering::SeqCst);
+        let result = self.send(Control::Shutdown { timeout_in_ms });
+        if let Some(supervisor) = self.supervisor.lock().unwrap().take() {
+            let _ = supervisor.join();
+        }
+        self.state.closed.store(true, Ordering::SeqCst);
+        if let Some(acceptor) = self.acceptor.lock().unwrap().take() {
+            let _ = acceptor.join();
+        }
+        result
+    }
+
+    fn start_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.send(Control::StartAgent { agent: agent.to_string() })
+    }
+
+    fn stop_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.send(Control::StopAgent { agent: agent.to_string() })
+    }
+
+    fn restart_agent(&self, agent: &str) -> anyhow::Result<()> {
+        self.send(Control::RestartAgent { agent: agent.to_string() })
+    }
+
+    fn task_states(&self) -> anyhow::Result<Vec<TaskStatus>> {
+        match self.request(Control::TaskStates)? {
+            WorkerMessage::TaskStates(result) => result.map_err(|err| anyhow::anyhow!(err)),
+            other => Err(anyhow::anyhow!("unexpected worker message: {:?}", other)),
+        }
+    }
+
+    fn run_task(&self, agent: &str, task: &str) -> anyhow::Result<()> {
+        self.send(Control::RunTask {
+            agent: agent.to_string(),
+            task: task.to_string(),
+        })
+    }
+}
+
+fn spawn_worker(
+    state: &WorkerState,
+    control_receiver: &Receiver<UnixStream>,
+) -> anyhow::Result<(Child, PluginManifest)> {
+    // own process group, so SIGINT/SIGTERM for the host do not hit the worker before its plugin is shut down
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("worker")
+        .arg(&state.library_path)
+        .arg(&state.socket_path)
+        .process_group(0)
+        .spawn()?;
+
+    let deadline = Instant::now() + READY_TIMEOUT;
+    let mut stream = loop {
+        match control_receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(stream) => break stream,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(status) = child.try_wait()? {
+                    return Err(anyhow::anyhow!("worker exited before it was ready: {}", status));
+                }
+                if Instant::now() > deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(anyhow::anyhow!("worker did not connect within {:?}", READY_TIMEOUT));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow::anyhow!("worker socket closed"));
+            }
+        }
+    };
+
+    let init = Control::Init {
+        agent_config: state.agent_config.clone(),
+    };
+    match write_frame(&mut stream, &init).and_then(|_| read_frame::<WorkerMessage>(&mut stream)) {
+        Ok(WorkerMessage::Ready(manifest)) => {
+            *state.control.lock().unwrap() = Some(stream);
+            Ok((child, manifest))
+        }
+        Ok(WorkerMessage::Refused(reason)) => {
+            let _ = child.wait();
+            Err(anyhow::anyhow!(reason))
+        }
+        Ok(other) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(anyhow::anyhow!("unexpected worker message: {:?}", other))
+        }
+        Err(err) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(anyhow::anyhow!("worker did not report ready: {}", err))
+        }
+    }
+}
+
+// Waits for the worker to exit and restarts it unless the host is shutting it down.
+fn supervise(state: Arc<WorkerState>, child: Child, control_receiver: Receiver<UnixStream>) {
+    let mut child = Some(child);
+    let mut restart_delay = INITIAL_RESTART_DELAY;
+    let mut started_at = Instant::now();
+
+    loop {
+        if let Some(mut running) = child.take() {
+            let mut shutdown_seen: Option<Instant> = None;
+            let status = loop {
+                match running.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) => {}
+                    Err(err) => {
+                        error!("Failed to wait for worker of {}: {}", state.library_path, err);
+                        break None;
+                    }
+                }
+                if state.shutting_down.load(Ordering::SeqCst) {
+                    let seen = *shutdown_seen.get_or_insert_with(Instant::now);
+                    let timeout = Duration::from_millis(state.shutdown_timeout_in_ms.load(Ordering::SeqCst));
+                    if seen.elapsed() > timeout + SHUTDOWN_GRACE {
+                        warn!("Killing worker of {}, it did not shut down in time", state.library_path);
+                        let _ = running.kill();
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            };
+            state.control.lock().unwrap().take();
+
+            if state.shutting_down.load(Ordering::SeqCst) {
+                info!("Worker of {} exited", state.library_path);
+                break;
+            }
+            error!("Worker of {} exited unexpectedly: {:?}", state.library_path, status);
+            if started_at.elapsed() > STABLE_AFTER {
+                restart_delay = INITIAL_RESTART_DELAY;
+            }
+        }
+
+        info!("Restarting worker of {} in {:?}", state.library_path, restart_delay);
+        let wake_up = Instant::now() + restart_delay;
+        while Instant::now() < wake_up {
+            if state.shutting_down.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+
+        match spawn_worker(&state, &control_receiver) {
+            Ok((new_child, _)) => {
+                started_at = Instant::now();
+                child = Some(new_child);
+                if state.started.load(Ordering::SeqCst) {
+                    let mut guard = state.control.lock().unwrap();
+                    let restarted = guard.as_mut().map(|stream| {
+                        write_frame(stream, &Control::Start)
+                            .and_then(|_| read_frame::<WorkerMessage>(stream))
+                    });
+                    if let Some(Err(err)) = restarted {
+                        error!("Failed to start restarted worker of {}: {}", state.library_path, err);
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Failed to restart worker of {}: {}", state.library_path, err);
+            }
+        }
+    }
+}
+
+// Entry point of `rust-bot worker <library_path> <socket_path>`.
+// Loads the plugin in this process and executes the control commands sent by the host.
+pub fn run_worker(library_path: &str, socket_path: &str) -> anyhow::Result<()> {
+    let mut control = UnixStream::connect(socket_path)?;
+    write_frame(&mut control, &Channel::Control)?;
+    let agent_config = match read_frame::<Control>(&mut control)? {
+        Control::Init { agent_config } => CString::new(agent_config)?,
+        other => return Err(anyhow::anyhow!("expected Init, got {:?}", other)),
+    };
+
+    let loaded = unsafe {
+        Library::new(library_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|library| abi::load_declaration(&library).map(|loaded| (library, loaded)))
+    };
+    let (_library, (vtable, manifest)) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            write_frame(&mut control, &WorkerMessage::Refused(err.to_string()))?;
+            return Err(err);
+        }
+    };
+
+    let socket = CString::new(socket_path)?;
+    let initialized = vtable.check("init_remote", unsafe {
+        (vtable.init_remote)(socket.as_ptr(), agent_config.as_ptr())
+    });
+    if let Err(err) = initialized {
+        write_frame(&mut control, &WorkerMessage::Refused(err.to_string()))?;
+        return Err(err);
+    }
+    write_frame(&mut control, &WorkerMessage::Ready(manifest))?;
+
+    let status = |call: &str, status: i32| WorkerMessage::Status(vtable.check(call, status).map_err(|err| err.to_string()));
+    let result = |result: anyhow::Result<()>| WorkerMessage::Status(result.map_err(|err| err.to_string()));
+    loop {
+        match read_frame::<Control>(&mut control) {
+            Ok(Control::Start) => write_frame(&mut control, &status("start", (vtable.start)()))?,
+            Ok(Control::Stop) => write_frame(&mut control, &status("stop", (vtable.stop)()))?,
+            Ok(Control::StartAgent { agent }) => write_frame(&mut control, &result(vtable.start_agent(&agent)))?,
+            Ok(Control::StopAgent { agent }) => write_frame(&mut control, &result(vtable.stop_agent(&agent)))?,
+            Ok(Control::RestartAgent { agent }) => write_frame(&mut control, &result(vtable.restart_agent(&agent)))?,
+            Ok(Control::TaskStates) => {
+                let task_states = vtable.task_states().map_err(|err| err.to_string());
+                write_frame(&mut control, &WorkerMessage::TaskStates(task_states))?
+            }
+            Ok(Control::RunTask { agent, task }) => {
+                write_frame(&mut control, &result(vtable.run_task(&agent, &task)))?
+            }
+            Ok(Control::Shutdown { timeout_in_ms }) => {
+                write_frame(&mut control, &status("shutdown", (vtable.shutdown)(timeout_in_ms)))?;
+                break;
+            }
+            Ok(Control::Init { .. }) => {
+                write_frame(&mut control, &WorkerMessage::Status(Err("already initialized".to_string())))?;
+            }
+            Err(_) => {
+                // the host is gone
+                (vtable.stop)();
+                (vtable.shutdown)(0);
+                break;
+            }
+        }
+    }
+    Ok(())
+}